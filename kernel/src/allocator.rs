@@ -1,7 +1,7 @@
 //!
 //!
 //! We are allocating "From the back".
-//! 1. Find the last `FreeSegment` in memory that can hold the allocation.
+//! 1. Find a `FreeSegment` that can hold the allocation, which one depends on the `FitStrategy`.
 //! 2. Compute these three values, starting from the end of the free segment:
 //!   - Size of the padding: (end of free segment - size_of(UsedSegment)) % alignment
 //!   - Location of the new used segment header: end of free segment - (size_of(UsedSegment) + padding_size).
@@ -9,15 +9,30 @@
 //! 3. Write the `UsedSegment` at its location
 //! 4. Return a pointer to the location of the beginning of the newly allocated data.
 //!
+//! Bump region:
+//! Optionally (see `Allocator::new()`), a region is carved from the end of the heap at init.
+//! `alloc` first tries to just bump a pointer in it, and only falls back to the free list once it
+//! is exhausted. Freeing memory from the bump region only decrements a counter: nothing is reused
+//! until *every* allocation made from it has been freed, at which point the whole region resets.
+//! A single long-lived allocation in there thus leaks the rest of the region until it is freed.
+//!
+//! Heap growth:
+//! When no free segment can hold an allocation, `alloc` maps fresh frames at the end of the growth
+//! region (see `HEAP_GROWTH_START`), adds them to the free list as a new `FreeSegment`, and tries
+//! again. It only returns null once the frame allocator (or the growth region) is exhausted.
+//! NOTE: Mapped pages are never unmapped, even once everything in them was freed.
+//!
 //! TODO::
 //! - Explore how we could improve performances. Running through the list of free segments might take long.
 
 use core::{
     alloc::GlobalAlloc,
-    sync::atomic::{AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering},
 };
 
-use bootloader_api::info::MemoryRegionKind;
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+
+use crate::{error::KernelError, utils::fmt::ByteSize};
 
 /// This is the header stored memory in order to track a segment of unused memory.
 #[repr(C)]
@@ -38,6 +53,37 @@ impl FreeSegment {
     }
 }
 
+/// Walks the free list, yielding each `FreeSegment` in address order.
+///
+/// NOTE: The next segment is read before yielding the current one, so the current one may be
+/// modified (but not merged with the next one) while iterating.
+struct FreeSegmentIter {
+    cursor: *mut FreeSegment,
+}
+
+impl FreeSegmentIter {
+    /// Safety: `head` must be null or the first segment of a valid free list.
+    unsafe fn new(head: *mut FreeSegment) -> Self {
+        Self { cursor: head }
+    }
+}
+
+impl Iterator for FreeSegmentIter {
+    type Item = *mut FreeSegment;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cursor.is_null() {
+            return None;
+        }
+
+        let segment = self.cursor;
+        // Safety: See `new()`.
+        self.cursor = unsafe { (*segment).next_free };
+
+        Some(segment)
+    }
+}
+
 ///
 /// In memory, we will store this struct like so:
 /// | ... | data | UsedSegment | Padding | ... |
@@ -53,8 +99,14 @@ struct UsedSegment {
 
     /// Size of the padding used to respect alignment.
     align_padding: usize,
+
+    /// `USED_MAGIC` while allocated, cleared by `dealloc()`.
+    magic: usize,
 }
 
+/// Marks a valid `UsedSegment`, so that `dealloc()` can catch double and invalid frees.
+const USED_MAGIC: usize = 0xA110_CA7E_D5E6_0000;
+
 impl UsedSegment {
     /// Returns the size of the whole used segment as a number of bytes.
     ///
@@ -64,48 +116,307 @@ impl UsedSegment {
     }
 }
 
+/// Region in which allocating is just bumping `next`.
+struct BumpRegion {
+    start: AtomicUsize,
+    end: AtomicUsize,
+    next: AtomicUsize,
+    /// Number of allocations from this region that were not freed yet.
+    live: AtomicUsize,
+}
+
+impl BumpRegion {
+    const fn new() -> Self {
+        Self {
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+            next: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+        }
+    }
+
+    fn contains(&self, ptr: *mut u8) -> bool {
+        (self.start.load(Ordering::Relaxed)..self.end.load(Ordering::Relaxed))
+            .contains(&(ptr as usize))
+    }
+
+    /// Returns `None` if the region is exhausted (or was never set up).
+    fn alloc(&self, layout: core::alloc::Layout) -> Option<*mut u8> {
+        let data_start = self
+            .next
+            .load(Ordering::Relaxed)
+            .checked_next_multiple_of(layout.align())?;
+        let data_end = data_start.checked_add(layout.size())?;
+        if data_end > self.end.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        self.next.store(data_end, Ordering::Relaxed);
+        self.live.fetch_add(1, Ordering::Relaxed);
+
+        Some(data_start as *mut u8)
+    }
+
+    /// Nothing is actually freed until the last allocation is, then the whole region resets.
+    fn dealloc(&self) {
+        if self.live.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.next
+                .store(self.start.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Virtual region the heap grows into, pages are mapped in order from `start`.
+struct GrowthRegion {
+    /// First unmapped address, e.g. the end of what was mapped so far.
+    next: AtomicUsize,
+    /// 0 if the region was never set up, the heap then can't grow.
+    end: AtomicUsize,
+}
+
+impl GrowthRegion {
+    const fn new() -> Self {
+        Self {
+            next: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Which free segment `alloc` uses when several can hold an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+#[allow(clippy::enum_variant_names)]
+pub enum FitStrategy {
+    /// The first one in address order, the fastest to find.
+    FirstFit,
+    /// The smallest one, keeping the big segments for big allocations.
+    BestFit,
+    /// The last one in address order. Combined with allocating from the back of the segment, this
+    /// tends to fragment the heap.
+    LastFit,
+}
+
+impl FitStrategy {
+    /// The one after `self`, e.g. for the shell `fit` command to cycle through them.
+    pub fn next(self) -> Self {
+        match self {
+            Self::FirstFit => Self::BestFit,
+            Self::BestFit => Self::LastFit,
+            Self::LastFit => Self::FirstFit,
+        }
+    }
+
+    /// Inverse of `as u8`, e.g. for `Allocator::strategy`.
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::FirstFit,
+            1 => Self::BestFit,
+            _ => Self::LastFit,
+        }
+    }
+}
+
 /// NOTE: We might need to add a lock to this struct to make it thread-safe.
 pub struct Allocator {
     first_free: AtomicPtr<FreeSegment>,
+    /// A `FitStrategy`, so that it can be changed at runtime (see `set_fit_strategy()`).
+    strategy: AtomicU8,
+
+    /// Whether `alloc` first tries the bump region.
+    use_bump: bool,
+    bump: BumpRegion,
+
+    growth: GrowthRegion,
+    /// Maps a fresh frame at the given address, returns `false` if there is none left.
+    map_page: fn(u64) -> bool,
+}
+
+/// Size of the bump region carved from the heap, when enabled.
+const BUMP_REGION_SIZE: usize = 1024 * 1024;
+
+/// Size from which `alloc_zeroed` uses `utils::mem::fast_fill()`, a few cache lines: below that,
+/// the startup cost of `rep stosb` outweighs the gain.
+const FAST_FILL_THRESHOLD: usize = 256;
+
+/// When set, every `alloc` and `dealloc` is logged over serial, see `trace()`.
+///
+/// NOTE: Off by default, since it floods the output. Toggled by the shell `trace` command.
+pub static ALLOC_TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Logs an allocator event over serial, if `ALLOC_TRACE` is set.
+///
+/// NOTE: This must not allocate, or it would recurse. Formatting integers and pointers does not,
+/// and neither does the serial writer. It must also be called once the free list is consistent
+/// again (outside of `without_interrupts()`), so that nothing else runs in the middle of an update.
+fn trace(event: &str, layout: core::alloc::Layout, ptr: *mut u8) {
+    if !ALLOC_TRACE.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // Safety: Single threaded, and the serial writer is never borrowed across an allocation.
+    unsafe {
+        use core::fmt::Write;
+
+        if let Some(writer) = (*crate::io::serial::SERIAL_WRITER.0.get()).as_mut() {
+            // NOTE: Ignore errors, panicking in the allocator would be worse than a missing line.
+            let _ = writeln!(
+                writer,
+                "[alloc] {} size={} align={} ptr={:p}",
+                event,
+                layout.size(),
+                layout.align(),
+                ptr
+            );
+            writer.flush();
+        }
+    }
 }
 
 #[global_allocator]
-static ALLOC: Allocator = Allocator::new();
+static ALLOC: Allocator = Allocator::new(true);
 
 impl Allocator {
-    pub const fn new() -> Self {
+    /// `use_bump` enables the bump region fast path, see the module documentation.
+    pub const fn new(use_bump: bool) -> Self {
         Self {
             first_free: AtomicPtr::new(core::ptr::null_mut()),
+            strategy: AtomicU8::new(FitStrategy::BestFit as u8),
+            use_bump,
+            bump: BumpRegion::new(),
+            growth: GrowthRegion::new(),
+            map_page: map_heap_page,
         }
     }
+
+    /// Takes `size` bytes from the end of the first free segment for the bump region.
+    ///
+    /// Safety: The free list must have been initialized.
+    unsafe fn carve_bump_region(&self, size: usize) {
+        if !self.use_bump {
+            return;
+        }
+
+        let head = self.first_free.load(Ordering::Relaxed);
+        if head.is_null() || (*head).size < size {
+            println!("WARNING: Heap too small for the bump region, not using it.");
+            return;
+        }
+
+        (*head).size -= size;
+        let start = (*head).get_end() as usize;
+
+        self.bump.start.store(start, Ordering::Relaxed);
+        self.bump.end.store(start + size, Ordering::Relaxed);
+        self.bump.next.store(start, Ordering::Relaxed);
+        self.bump.live.store(0, Ordering::Relaxed);
+    }
+
+    /// Maps enough pages at the end of the growth region to hold `layout`, and adds them to the
+    /// free list.
+    ///
+    /// Returns `false` if not a single page could be mapped. Otherwise, whatever was mapped is
+    /// added, even if it's not enough for `layout`.
+    ///
+    /// Safety: The free list must have been initialized, and end below the growth region.
+    unsafe fn grow(&self, layout: core::alloc::Layout) -> bool {
+        // Worst case, the new segment is not merged with the previous one and needs full padding.
+        let needed = (core::mem::size_of::<FreeSegment>()
+            + core::mem::size_of::<UsedSegment>()
+            + layout.size()
+            + layout.align()
+            - 1)
+        .next_multiple_of(PAGE_SIZE as usize);
+
+        let start = self.growth.next.load(Ordering::Relaxed);
+        let limit = self.growth.end.load(Ordering::Relaxed);
+
+        let mut end = start;
+        while end - start < needed && end < limit {
+            if !(self.map_page)(end as u64) {
+                break;
+            }
+            end += PAGE_SIZE as usize;
+        }
+
+        if end == start {
+            return false;
+        }
+        self.growth.next.store(end, Ordering::Relaxed);
+
+        let segment = start as *mut FreeSegment;
+        segment.write(FreeSegment {
+            size: end - start - core::mem::size_of::<FreeSegment>(),
+            next_free: core::ptr::null_mut(),
+        });
+
+        insert_new_segment(self.first_free.load(Ordering::Relaxed), segment);
+        // Merges it with the previous growth, if it's still free.
+        clean_free_segment_list(self.first_free.load(Ordering::Relaxed));
+
+        true
+    }
 }
 
-/// This runs through the mapped memory regions in order to find the biggest one that we can use
-/// in our allocator.
-pub fn init(boot_info: &bootloader_api::BootInfo) {
-    assert_eq!(
-        core::mem::size_of::<FreeSegment>(),
-        core::mem::size_of::<UsedSegment>()
-    );
-    let mut head: *mut FreeSegment = core::ptr::null_mut();
-    let mut tail: *mut FreeSegment = core::ptr::null_mut();
+/// Virtual region the heap grows into once its initial region is full.
+///
+/// NOTE: This is the first address of the 301st PML4 entry, right after the kernel stack's one
+/// (`memory::stack::KERNEL_STACK_TOP`), which the bootloader does not use either.
+const HEAP_GROWTH_START: u64 = 0xFFFF_9680_0000_0000;
+const HEAP_GROWTH_SIZE: u64 = 1024 * 1024 * 1024;
 
-    let Some(physical_memory_offset) = crate::PHYS_MEM_OFFSET.0.get() else {
-        panic!("Physical memory offset should have been set by now.")
+/// Maps a frame from the frame allocator at `virt`, for `Allocator::grow()`.
+///
+/// NOTE: Fails until `frame_alloc::init()` was called.
+fn map_heap_page(virt: u64) -> bool {
+    let Some(frame) = crate::memory::frame_alloc::next_frame() else {
+        return false;
     };
 
+    crate::memory::paging::map_page(virt, frame, crate::memory::paging::PageFlags::WRITABLE).is_ok()
+}
+
+/// The heap never takes more than this, the rest of its region is left to the frame allocator.
+const MAX_HEAP_SIZE: u64 = 64 * 1024 * 1024;
+
+const PAGE_SIZE: u64 = crate::memory::paging::PAGE_SIZE;
+
+/// This runs through the mapped memory regions in order to find the largest one that we can use
+/// in our allocator.
+///
+/// The heap is page aligned (so that page aligned allocations can be satisfied) and is capped to
+/// `MAX_HEAP_SIZE`.
+///
+/// Returns the physical memory range used by the heap, `AllocFailed` if no region fits.
+pub fn init(boot_info: &bootloader_api::BootInfo) -> Result<core::ops::Range<u64>, KernelError> {
+    // A freed block is turned into a `FreeSegment` in place.
+    assert!(core::mem::size_of::<FreeSegment>() <= core::mem::size_of::<UsedSegment>());
+    let mut heap = 0..0;
+
     println!("\n----- Allocator Initialization -----");
 
     // Get the kernel section because we can't use memory that overlaps with it.
-    let kernel_start = boot_info.kernel_addr;
-    let kernel_len = boot_info.kernel_len;
+    let kernel = crate::memory::kernel_range(boot_info);
     println!(
-        "[{} -> {} ({} Mb)] Kernel",
-        kernel_start,
-        kernel_start + kernel_len,
-        kernel_len / 1024 / 1024
+        "[{} -> {} ({})] Kernel",
+        kernel.start,
+        kernel.end,
+        ByteSize(kernel.end - kernel.start)
     );
 
+    // Print everything we won't touch, so that the memory map is complete in the logs.
+    for region in boot_info.memory_regions.iter() {
+        if region.kind != MemoryRegionKind::Usable {
+            println!(
+                "[{} -> {} ({})] kind: {} - Not usable",
+                region.start,
+                region.end,
+                ByteSize(region.end - region.start),
+                region_kind_name(region.kind)
+            );
+        }
+    }
+
     for region in boot_info.memory_regions.iter() {
         // Only consider usable memory regions
         if region.kind != MemoryRegionKind::Usable {
@@ -113,83 +424,173 @@ pub fn init(boot_info: &bootloader_api::BootInfo) {
         }
 
         // Skip the region if it collides with the region used by the kernel.
-        if region.end <= (kernel_start + kernel_len) {
+        if region.end <= kernel.end {
             println!(
-                "[{} -> {} ({} Mb)] kind: {:?} - Collides with kernel, skipping...",
+                "[{} -> {} ({})] kind: {:?} - Collides with kernel, skipping...",
                 region.start,
                 region.end,
-                (region.end - region.start) / 1024 / 1024,
+                ByteSize(region.end - region.start),
                 region.kind
             );
             continue;
         }
 
+        // Only use whole pages, and not more than `MAX_HEAP_SIZE`.
+        let start = region.start.next_multiple_of(PAGE_SIZE);
+        let end = (region.end & !(PAGE_SIZE - 1)).min(start + MAX_HEAP_SIZE);
+        if end <= start {
+            continue;
+        }
+
         println!(
-            "[{} -> {} ({} Mb)] kind: {:?}",
+            "[{} -> {} ({})] kind: {:?}, could use [{} -> {} ({})]",
             region.start,
             region.end,
-            (region.end - region.start) / 1024 / 1024,
-            region.kind
+            ByteSize(region.end - region.start),
+            region.kind,
+            start,
+            end,
+            ByteSize(end - start)
         );
 
-        // Write a `FreeSegment` to the region we found.
-        let segment: *mut FreeSegment = (region.start + physical_memory_offset) as *mut FreeSegment;
-        unsafe {
-            segment.write(FreeSegment {
-                size: (region.end - region.start) as usize - core::mem::size_of::<FreeSegment>(),
-                next_free: core::ptr::null_mut(),
-            });
+        if end - start > heap.end - heap.start {
+            heap = start..end;
         }
+    }
 
-        // Insert at the end of the linked list.
-        if head.is_null() {
-            head = segment;
-            tail = segment;
-        } else {
-            unsafe {
-                assert!(
-                    segment > (*tail).next_free,
-                    "Wtf, memory regions are not ordered"
-                );
-                (*tail).next_free = segment;
-            }
-            tail = segment;
-        }
+    if heap.is_empty() {
+        return Err(KernelError::AllocFailed);
     }
+    println!(
+        "Using [{} -> {} ({})]",
+        heap.start,
+        heap.end,
+        ByteSize(heap.end - heap.start)
+    );
 
-    // FIXME: Here, we enforce that we found a single memory region that we can use.
+    // Write a `FreeSegment` to the region we found.
+    // Safety: Nothing uses that memory yet, and the frame allocator skips the heap.
+    let memory =
+        unsafe { crate::memory::region_as_slice(heap.start, (heap.end - heap.start) as usize) };
+    let head = memory.as_mut_ptr() as *mut FreeSegment;
     unsafe {
-        assert!((*head).next_free.is_null());
+        head.write(FreeSegment {
+            size: memory.len() - core::mem::size_of::<FreeSegment>(),
+            next_free: core::ptr::null_mut(),
+        });
+    }
+
+    // NOTE: The bootloader should never give overlapping regions, but writing our free list over
+    // e.g. an MMIO hole would fail in much more confusing ways.
+    if let Some(region) = non_usable_overlap(&boot_info.memory_regions, &heap) {
+        panic!(
+            "The heap [{} -> {}] overlaps a non-usable region: {:?}",
+            heap.start, heap.end, region
+        );
     }
 
+    // NOTE: `insert_new_segment()` can't insert before the head.
+    assert!(crate::memory::phys_to_virt(heap.end) as u64 <= HEAP_GROWTH_START);
+    assert!(
+        crate::memory::paging::translate(HEAP_GROWTH_START).is_none(),
+        "The heap growth region is mapped"
+    );
+
     println!("Allocator Initialization done. HEAD = {:?}\n", head);
 
     ALLOC.first_free.store(head, Ordering::Relaxed);
+    unsafe { ALLOC.carve_bump_region(BUMP_REGION_SIZE) };
+    ALLOC
+        .growth
+        .next
+        .store(HEAP_GROWTH_START as usize, Ordering::Relaxed);
+    ALLOC.growth.end.store(
+        (HEAP_GROWTH_START + HEAP_GROWTH_SIZE) as usize,
+        Ordering::Relaxed,
+    );
+
+    Ok(heap)
+}
+
+/// Name of `kind` for the logs.
+fn region_kind_name(kind: MemoryRegionKind) -> &'static str {
+    match kind {
+        MemoryRegionKind::Usable => "Usable",
+        MemoryRegionKind::Bootloader => "Bootloader",
+        MemoryRegionKind::UnknownUefi(_) => "Reserved (UEFI)",
+        MemoryRegionKind::UnknownBios(_) => "Reserved (BIOS)",
+        // `MemoryRegionKind` is non exhaustive.
+        _ => "Unknown",
+    }
+}
+
+/// Returns the first non-usable region of `regions` overlapping `range`, if any.
+fn non_usable_overlap<'a>(
+    regions: &'a [MemoryRegion],
+    range: &core::ops::Range<u64>,
+) -> Option<&'a MemoryRegion> {
+    regions.iter().find(|region| {
+        region.kind != MemoryRegionKind::Usable
+            && region.start < range.end
+            && range.start < region.end
+    })
+}
+
+/// Strategy the global allocator uses to pick a free segment.
+pub fn fit_strategy() -> FitStrategy {
+    FitStrategy::from_u8(ALLOC.strategy.load(Ordering::Relaxed))
+}
+
+/// Changes the strategy of the global allocator, e.g. to compare how they fragment the heap.
+pub fn set_fit_strategy(strategy: FitStrategy) {
+    ALLOC.strategy.store(strategy as u8, Ordering::Relaxed);
 }
 
 pub fn print_free_segments() {
     let mut count = 0;
-    let mut cursor: *mut FreeSegment = ALLOC.first_free.load(Ordering::Relaxed);
 
     println!("----- List of Mapped FreeSegment -----");
-    while !cursor.is_null() {
+    for segment in unsafe { FreeSegmentIter::new(ALLOC.first_free.load(Ordering::Relaxed)) } {
         count += 1;
+        let segment = unsafe { &*segment };
         println!(
-            "Region #{}: [{:?} -> {:?} ({} Mb)] Mapped & free",
+            "Region #{}: [{:?} -> {:?} ({})] Mapped & free",
             count,
-            cursor,
-            (*cursor).get_end(),
-            (*cursor).size / 1024 / 1024
+            segment as *const FreeSegment,
+            segment.get_end(),
+            ByteSize(segment.size as u64)
         );
-
-        unsafe {
-            cursor = (*cursor).next_free;
-        }
     }
 
     println!("Total number of mapped regions: {}\n", count);
 }
 
+/// Summary of the free list.
+#[derive(Debug, Default)]
+pub struct HeapStats {
+    pub free_segments: usize,
+    /// Total free bytes, headers excluded.
+    pub free_bytes: usize,
+    pub largest_free: usize,
+}
+
+pub fn stats() -> HeapStats {
+    let mut stats = HeapStats::default();
+
+    for segment in unsafe { FreeSegmentIter::new(ALLOC.first_free.load(Ordering::Relaxed)) } {
+        let size = unsafe { (*segment).size };
+        stats.free_segments += 1;
+        stats.free_bytes += size;
+        stats.largest_free = stats.largest_free.max(size);
+    }
+
+    stats
+}
+
+/// Merges the adjacent free segments.
+///
+/// NOTE: This does not use `FreeSegmentIter`, since a segment must be checked again after being
+/// merged with the next one.
 unsafe fn clean_free_segment_list(head: *mut FreeSegment) {
     let mut cursor = head;
 
@@ -211,9 +612,7 @@ unsafe fn clean_free_segment_list(head: *mut FreeSegment) {
 }
 
 unsafe fn insert_new_segment(head: *mut FreeSegment, new_segment: *mut FreeSegment) {
-    let mut cursor = head;
-
-    while !cursor.is_null() {
+    for cursor in FreeSegmentIter::new(head) {
         assert!(cursor < new_segment);
 
         if (*cursor).next_free.is_null() || new_segment < (*cursor).next_free {
@@ -221,8 +620,6 @@ unsafe fn insert_new_segment(head: *mut FreeSegment, new_segment: *mut FreeSegme
             (*cursor).next_free = new_segment;
             return;
         }
-
-        cursor = (*cursor).next_free;
     }
 
     // We didn't insert before so we must have a new head.
@@ -230,32 +627,36 @@ unsafe fn insert_new_segment(head: *mut FreeSegment, new_segment: *mut FreeSegme
     ALLOC.first_free.store(new_segment, Ordering::Relaxed);
 }
 
-unsafe fn find_last_big_enough(
-    head: *mut FreeSegment,
-    layout: core::alloc::Layout,
-) -> Option<*mut FreeSegment> {
-    let mut cursor = head;
-    let mut last = core::ptr::null_mut();
+/// Whether a free segment of `segment_size` bytes ending at `segment_end` can hold `layout`.
+///
+/// NOTE: This only uses integers: computing pointers out of a segment that's too small is UB.
+fn fits(segment_size: usize, segment_end: usize, layout: core::alloc::Layout) -> bool {
+    let header_and_data = layout.size() + core::mem::size_of::<UsedSegment>();
+    if header_and_data > segment_size {
+        return false;
+    }
 
-    while !cursor.is_null() {
-        let segment_end = (*cursor).get_end();
-        let data_start = segment_end.sub(layout.size());
-        let padding_size = (data_start as usize) % layout.align();
-        let segment_start = data_start.sub(padding_size + core::mem::size_of::<UsedSegment>());
+    // Same padding as in `write_used_segment()`.
+    let padding_size = (segment_end - header_and_data) % layout.align();
 
-        // We found a big enough segment
-        if segment_start < (*cursor).get_end() {
-            last = cursor;
-        }
+    header_and_data + padding_size <= segment_size
+}
 
-        cursor = (*cursor).next_free;
-    }
+/// Returns the free segment `strategy` picks to hold `layout`, if any can.
+unsafe fn find_fit(
+    head: *mut FreeSegment,
+    layout: core::alloc::Layout,
+    strategy: FitStrategy,
+) -> Option<*mut FreeSegment> {
+    let mut big_enough = FreeSegmentIter::new(head)
+        .filter(|&segment| fits((*segment).size, (*segment).get_end() as usize, layout));
 
-    if !last.is_null() {
-        return Some(last);
+    match strategy {
+        FitStrategy::FirstFit => big_enough.next(),
+        // NOTE: On a tie, the first one is kept.
+        FitStrategy::BestFit => big_enough.min_by_key(|&segment| (*segment).size),
+        FitStrategy::LastFit => big_enough.last(),
     }
-
-    None
 }
 
 /// Returns the start of the newly allocated memory.
@@ -279,6 +680,7 @@ unsafe fn write_used_segment(
     (*used) = UsedSegment {
         size: layout.size(),
         align_padding: padding_size,
+        magic: USED_MAGIC,
     };
 
     (*free_segment).size -= (*used).whole_size();
@@ -286,28 +688,525 @@ unsafe fn write_used_segment(
     data_start as *mut u8
 }
 
+/// Called when an allocation fails (e.g. `alloc` returned null).
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    println!(
+        "\nOUT OF MEMORY!!! Failed to allocate {} bytes (align = {})",
+        layout.size(),
+        layout.align()
+    );
+
+    let stats = stats();
+    println!(
+        "Heap: {} bytes free in {} segments (largest: {} bytes)",
+        stats.free_bytes, stats.free_segments, stats.largest_free
+    );
+
+    #[cfg(test)]
+    crate::io::exit(1);
+
+    loop {
+        unsafe { core::arch::asm!("hlt", options(nomem, nostack)) };
+    }
+}
+
+// NOTE: The free list is shared state, so we don't want an interrupt handler to allocate while
+// we're in the middle of updating it.
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let Some(last_big) = find_last_big_enough(self.first_free.load(Ordering::Relaxed), layout)
-        else {
-            panic!("No free memory found.")
-        };
+        // A zero-sized allocation must not use any memory, but still be non-null and aligned.
+        // Otherwise, its data would start right at its `UsedSegment` header.
+        if layout.size() == 0 {
+            let ptr = layout.align() as *mut u8;
+            trace("alloc", layout, ptr);
+            return ptr;
+        }
 
-        write_used_segment(last_big, layout)
+        let ptr = crate::interrupts::without_interrupts(|| {
+            if self.use_bump {
+                if let Some(ptr) = self.bump.alloc(layout) {
+                    return ptr;
+                }
+            }
+
+            loop {
+                if let Some(segment) = find_fit(
+                    self.first_free.load(Ordering::Relaxed),
+                    layout,
+                    FitStrategy::from_u8(self.strategy.load(Ordering::Relaxed)),
+                ) {
+                    return write_used_segment(segment, layout);
+                }
+
+                // NOTE: Returning null makes the caller go through `alloc_error()`.
+                if !self.grow(layout) {
+                    return core::ptr::null_mut();
+                }
+            }
+        });
+        trace("alloc", layout, ptr);
+
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+        // NOTE: Zero-sized allocations are dangling, there is nothing to write.
+        if !ptr.is_null() && layout.size() != 0 {
+            if layout.size() >= FAST_FILL_THRESHOLD {
+                crate::utils::mem::fast_fill(ptr, 0, layout.size());
+            } else {
+                ptr.write_bytes(0, layout.size());
+            }
+        }
+
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        let used = (ptr.add(layout.size())) as *mut UsedSegment;
+        trace("dealloc", layout, ptr);
+
+        // Dangling pointer from a zero-sized `alloc`.
+        if layout.size() == 0 {
+            return;
+        }
+
+        crate::interrupts::without_interrupts(|| {
+            if self.use_bump && self.bump.contains(ptr) {
+                self.bump.dealloc();
+                return;
+            }
+
+            let used = (ptr.add(layout.size())) as *mut UsedSegment;
+            // NOTE: This only catches the common cases: a bogus `ptr` can still fault here, or
+            // happen to point right before a valid header.
+            if (*used).magic != USED_MAGIC || (*used).size != layout.size() {
+                panic!("invalid or double free at {:p}", ptr);
+            }
+            // So that freeing `ptr` again is caught, until the memory is reused.
+            (*used).magic = 0;
+
+            let new_free = FreeSegment {
+                size: (*used).whole_size() - core::mem::size_of::<FreeSegment>(),
+                next_free: core::ptr::null_mut(),
+            };
+            let ptr = ptr as *mut FreeSegment;
+            ptr.write(new_free);
+
+            insert_new_segment(self.first_free.load(Ordering::Relaxed), ptr);
+
+            clean_free_segment_list(self.first_free.load(Ordering::Relaxed));
+        })
+    }
+}
 
-        let new_free = FreeSegment {
-            size: (*used).size + (*used).align_padding,
+/// Makes a few small allocations from a fresh allocator with its own heap, with or without the
+/// bump region, so that `bench::BENCHES` can compare both.
+#[cfg(feature = "bench")]
+pub fn bench_alloc_many(use_bump: bool) {
+    const BENCH_HEAP_SIZE: usize = 4 * PAGE_SIZE as usize;
+
+    #[repr(C, align(4096))]
+    struct BenchHeap(core::cell::UnsafeCell<[u8; BENCH_HEAP_SIZE]>);
+    // Safety: Only used by a single benchmark at a time.
+    unsafe impl Sync for BenchHeap {}
+
+    static BENCH_HEAP: BenchHeap = BenchHeap(core::cell::UnsafeCell::new([0; BENCH_HEAP_SIZE]));
+
+    let head = BENCH_HEAP.0.get() as *mut FreeSegment;
+    unsafe {
+        head.write(FreeSegment {
+            size: BENCH_HEAP_SIZE - core::mem::size_of::<FreeSegment>(),
             next_free: core::ptr::null_mut(),
-        };
-        let ptr = ptr as *mut FreeSegment;
-        ptr.write(new_free);
+        });
+    }
+    let allocator = Allocator::new(use_bump);
+    allocator.first_free.store(head, Ordering::Relaxed);
+    unsafe { allocator.carve_bump_region(PAGE_SIZE as usize) };
 
-        insert_new_segment(self.first_free.load(Ordering::Relaxed), ptr);
+    let layout = core::alloc::Layout::from_size_align(32, 8).unwrap();
+    for _ in 0..32 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+    }
+}
 
-        clean_free_segment_list(self.first_free.load(Ordering::Relaxed));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+    use alloc::vec::Vec;
+    use core::{alloc::Layout, cell::UnsafeCell};
+
+    const TEST_HEAP_SIZE: usize = 4 * PAGE_SIZE as usize;
+
+    #[repr(C, align(4096))]
+    struct TestHeap(UnsafeCell<[u8; TEST_HEAP_SIZE]>);
+    // Safety: Only used by a single test at a time.
+    unsafe impl Sync for TestHeap {}
+
+    static TEST_HEAP: TestHeap = TestHeap(UnsafeCell::new([0; TEST_HEAP_SIZE]));
+
+    /// Writes a single free segment of `size` bytes at the start of `TEST_HEAP`.
+    fn test_segment(size: usize) -> *mut FreeSegment {
+        let head = TEST_HEAP.0.get() as *mut FreeSegment;
+        unsafe {
+            head.write(FreeSegment {
+                size,
+                next_free: core::ptr::null_mut(),
+            });
+        }
+
+        head
+    }
+
+    /// Returns an allocator using the whole `TEST_HEAP`.
+    ///
+    /// NOTE: The global allocator is not initialized when running tests.
+    fn test_allocator(use_bump: bool) -> Allocator {
+        let head = test_segment(TEST_HEAP_SIZE - core::mem::size_of::<FreeSegment>());
+
+        let allocator = Allocator::new(use_bump);
+        allocator.first_free.store(head, Ordering::Relaxed);
+        unsafe { allocator.carve_bump_region(PAGE_SIZE as usize) };
+
+        allocator
+    }
+
+    /// `ALLOC.first_free` saved by `use_test_heap()`.
+    static SAVED_FIRST_FREE: AtomicPtr<FreeSegment> = AtomicPtr::new(core::ptr::null_mut());
+
+    /// Makes the global allocator use `TEST_HEAP`, see `restore_heap()`.
+    fn use_test_heap() {
+        let head = test_segment(TEST_HEAP_SIZE - core::mem::size_of::<FreeSegment>());
+        SAVED_FIRST_FREE.store(
+            ALLOC.first_free.swap(head, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn restore_heap() {
+        ALLOC
+            .first_free
+            .store(SAVED_FIRST_FREE.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    #[test_case]
+    fn test_page_aligned_alloc() -> TestCase {
+        TestCase {
+            name: "Test page aligned allocation",
+            test: || {
+                let allocator = test_allocator(false);
+                let layout = Layout::from_size_align(100, 4096).unwrap();
+
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!((ptr as usize).is_multiple_of(4096));
+
+                let heap = TEST_HEAP.0.get() as usize;
+                assert!(heap < ptr as usize && (ptr as usize) + 100 <= heap + TEST_HEAP_SIZE);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_segment_too_small() -> TestCase {
+        TestCase {
+            name: "Test that a segment one byte too small is not used",
+            test: || {
+                let layout = Layout::from_size_align(64, 1).unwrap();
+                let needed = layout.size() + core::mem::size_of::<UsedSegment>();
+
+                let head = test_segment(needed - 1);
+                assert!(unsafe { find_fit(head, layout, FitStrategy::BestFit) }.is_none());
+
+                let head = test_segment(needed);
+                assert_eq!(
+                    unsafe { find_fit(head, layout, FitStrategy::BestFit) },
+                    Some(head)
+                );
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_fit_strategies() -> TestCase {
+        TestCase {
+            name: "Test each fit strategy picks its own free segment",
+            test: || {
+                // A big, a snug and a medium segment, for 64 bytes (88 with the header).
+                let base = TEST_HEAP.0.get() as *mut u8;
+                let sizes = [512, 96, 256];
+                let segments =
+                    [0, 1024, 2048].map(|offset| unsafe { base.add(offset) } as *mut FreeSegment);
+                for (i, &segment) in segments.iter().enumerate() {
+                    unsafe {
+                        segment.write(FreeSegment {
+                            size: sizes[i],
+                            next_free: segments
+                                .get(i + 1)
+                                .copied()
+                                .unwrap_or(core::ptr::null_mut()),
+                        });
+                    }
+                }
+                let layout = Layout::from_size_align(64, 8).unwrap();
+
+                for (strategy, expected) in [
+                    (FitStrategy::FirstFit, 0),
+                    (FitStrategy::BestFit, 1),
+                    (FitStrategy::LastFit, 2),
+                ] {
+                    assert_eq!(
+                        unsafe { find_fit(segments[0], layout, strategy) },
+                        Some(segments[expected]),
+                        "{:?}",
+                        strategy
+                    );
+                }
+
+                // `alloc` follows the allocator's strategy.
+                let allocator = Allocator::new(false);
+                allocator
+                    .strategy
+                    .store(FitStrategy::LastFit as u8, Ordering::Relaxed);
+                allocator.first_free.store(segments[0], Ordering::Relaxed);
+                let ptr = unsafe { allocator.alloc(layout) } as usize;
+                let end = unsafe { (*segments[2]).get_end() } as usize;
+                assert!((segments[2] as usize..end).contains(&ptr));
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_bump_alloc() -> TestCase {
+        TestCase {
+            name: "Test allocating from the bump region",
+            test: || {
+                let allocator = test_allocator(true);
+                let layout = Layout::from_size_align(100, 16).unwrap();
+
+                let first = unsafe { allocator.alloc(layout) };
+                let second = unsafe { allocator.alloc(layout) };
+                assert!(allocator.bump.contains(first) && allocator.bump.contains(second));
+                assert_eq!(second as usize, (first as usize + 100).next_multiple_of(16));
+
+                // The region only resets once everything was freed.
+                unsafe { allocator.dealloc(first, layout) };
+                assert_eq!(
+                    allocator.bump.next.load(Ordering::Relaxed),
+                    second as usize + 100
+                );
+                unsafe { allocator.dealloc(second, layout) };
+                assert_eq!(allocator.bump.next.load(Ordering::Relaxed), first as usize);
+
+                // Falls back to the free list once exhausted.
+                let big = Layout::from_size_align(2 * PAGE_SIZE as usize, 8).unwrap();
+                let ptr = unsafe { allocator.alloc(big) };
+                assert!(!ptr.is_null() && !allocator.bump.contains(ptr));
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_zero_sized_alloc() -> TestCase {
+        TestCase {
+            name: "Test zero-sized allocations don't touch the heap",
+            test: || {
+                for use_bump in [false, true] {
+                    let allocator = test_allocator(use_bump);
+                    let head = allocator.first_free.load(Ordering::Relaxed);
+                    let free_size = unsafe { (*head).size };
+                    let bump_next = allocator.bump.next.load(Ordering::Relaxed);
+
+                    let layout = Layout::from_size_align(0, 64).unwrap();
+                    let ptr = unsafe { allocator.alloc(layout) };
+                    assert!(!ptr.is_null() && (ptr as usize).is_multiple_of(64));
+                    unsafe { allocator.dealloc(ptr, layout) };
+
+                    assert_eq!(allocator.first_free.load(Ordering::Relaxed), head);
+                    assert_eq!(unsafe { (*head).size }, free_size);
+                    assert_eq!(allocator.bump.next.load(Ordering::Relaxed), bump_next);
+                }
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_global_alloc() -> TestCase {
+        TestCase {
+            name: "Test allocating through the global allocator",
+            setup: use_test_heap,
+            test: || {
+                let v: Vec<u64> = (0..64).collect();
+                assert_eq!(v.iter().sum::<u64>(), 63 * 64 / 2);
+
+                let heap = TEST_HEAP.0.get() as usize;
+                assert!((heap..heap + TEST_HEAP_SIZE).contains(&(v.as_ptr() as usize)));
+            },
+            teardown: restore_heap,
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_dealloc_magic() -> TestCase {
+        TestCase {
+            name: "Test the UsedSegment magic is set and cleared",
+            test: || {
+                let allocator = test_allocator(false);
+                let head = allocator.first_free.load(Ordering::Relaxed);
+                let free_size = unsafe { (*head).size };
+                let layout = Layout::from_size_align(40, 8).unwrap();
+
+                let ptr = unsafe { allocator.alloc(layout) };
+                let used = unsafe { ptr.add(layout.size()) } as *mut UsedSegment;
+                assert_eq!(unsafe { (*used).magic }, USED_MAGIC);
+
+                unsafe { allocator.dealloc(ptr, layout) };
+                assert_eq!(unsafe { (*used).magic }, 0);
+                assert_eq!(unsafe { (*head).size }, free_size);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_invalid_free() -> TestCase {
+        TestCase {
+            name: "Test freeing a corrupted header panics",
+            test: || {
+                let allocator = test_allocator(false);
+                let layout = Layout::from_size_align(40, 8).unwrap();
+
+                let ptr = unsafe { allocator.alloc(layout) };
+                unsafe { (*(ptr.add(layout.size()) as *mut UsedSegment)).magic ^= 1 };
+
+                unsafe { allocator.dealloc(ptr, layout) };
+            },
+            should_panic: true,
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_non_usable_overlap() -> TestCase {
+        TestCase {
+            name: "Test detecting a heap overlapping a non-usable region",
+            test: || {
+                let region = |start, end, kind| MemoryRegion { start, end, kind };
+                let regions = [
+                    region(0x0, 0x1000, MemoryRegionKind::Bootloader),
+                    region(0x1000, 0x9000, MemoryRegionKind::Usable),
+                    region(0x9000, 0xA000, MemoryRegionKind::UnknownBios(2)),
+                ];
+
+                assert_eq!(non_usable_overlap(&regions, &(0x1000..0x9000)), None);
+                assert_eq!(
+                    non_usable_overlap(&regions, &(0x8000..0x9001)),
+                    Some(&regions[2])
+                );
+                assert_eq!(
+                    non_usable_overlap(&regions, &(0x0FFF..0x2000)),
+                    Some(&regions[0])
+                );
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Number of frames `fake_map_page()` can still hand out.
+    static FAKE_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Pretends to map a page, `TEST_HEAP` being already mapped.
+    fn fake_map_page(_virt: u64) -> bool {
+        FAKE_FRAMES
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    #[test_case]
+    fn test_heap_growth() -> TestCase {
+        TestCase {
+            name: "Test the heap grows while frames are left",
+            test: || {
+                // The first page is the initial heap, the rest is the growth region.
+                let heap = TEST_HEAP.0.get() as usize;
+                let page = PAGE_SIZE as usize;
+                let head = test_segment(page - core::mem::size_of::<FreeSegment>());
+
+                let mut allocator = Allocator::new(false);
+                allocator.map_page = fake_map_page;
+                allocator.first_free.store(head, Ordering::Relaxed);
+                allocator.growth.next.store(heap + page, Ordering::Relaxed);
+                allocator
+                    .growth
+                    .end
+                    .store(heap + TEST_HEAP_SIZE, Ordering::Relaxed);
+                FAKE_FRAMES.store(2, Ordering::Relaxed);
+
+                let layout = Layout::from_size_align(5000, 8).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+                assert!(heap + page <= ptr as usize && ptr as usize + 5000 <= heap + 3 * page);
+                assert_eq!(
+                    allocator.growth.next.load(Ordering::Relaxed),
+                    heap + 3 * page
+                );
+                // Adjacent to the initial heap, so merged with it.
+                assert_eq!(unsafe { (*head).next_free }, core::ptr::null_mut());
+
+                // No frame left.
+                let layout = Layout::from_size_align(2 * page, 8).unwrap();
+                assert!(unsafe { allocator.alloc(layout) }.is_null());
+                assert_eq!(
+                    allocator.growth.next.load(Ordering::Relaxed),
+                    heap + 3 * page
+                );
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_free_segment_iter() -> TestCase {
+        TestCase {
+            name: "Test iterating over the free list",
+            test: || {
+                // Three 64 bytes segments, with a 64 bytes gap after each.
+                let base = TEST_HEAP.0.get() as *mut u8;
+                let segments =
+                    [0, 128, 256].map(|offset| unsafe { base.add(offset) } as *mut FreeSegment);
+                for (i, &segment) in segments.iter().enumerate() {
+                    unsafe {
+                        segment.write(FreeSegment {
+                            size: 64 - core::mem::size_of::<FreeSegment>(),
+                            next_free: segments
+                                .get(i + 1)
+                                .copied()
+                                .unwrap_or(core::ptr::null_mut()),
+                        });
+                    }
+                }
+
+                let mut iter = unsafe { FreeSegmentIter::new(segments[0]) };
+                for &segment in &segments {
+                    assert_eq!(iter.next(), Some(segment));
+                }
+                assert_eq!(iter.next(), None);
+                assert_eq!(unsafe { FreeSegmentIter::new(segments[0]) }.count(), 3);
+
+                assert_eq!(
+                    unsafe { FreeSegmentIter::new(core::ptr::null_mut()) }.count(),
+                    0
+                );
+            },
+            ..Default::default()
+        }
     }
 }
@@ -0,0 +1,139 @@
+//! Benchmarks, run instead of the kernel when built with the `bench` feature
+//! (e.g. `cargo run --features bench`).
+//!
+//! Unlike tests, they run once everything (e.g. the heap) is initialized.
+//!
+//! NOTE: Cycles come from `rdtsc`, they are only meaningful compared to each other on the same
+//! machine.
+
+use alloc::boxed::Box;
+use core::hint::black_box;
+
+use crate::utils::{
+    bits::{GetBit, SetBit},
+    tsc::rdtsc,
+};
+
+pub struct BenchCase {
+    pub name: &'static str,
+
+    /// Number of times `body` is run.
+    pub iters: u64,
+
+    pub body: fn(),
+}
+
+/// Every benchmark, add new ones here.
+pub const BENCHES: &[BenchCase] = &[
+    BenchCase {
+        name: "Allocate and free a small box",
+        iters: 10_000,
+        body: || drop(black_box(Box::new(black_box(42u64)))),
+    },
+    BenchCase {
+        // NOTE: Compare with the next one, which goes through the bump region first.
+        name: "Allocate 32 small blocks from the free list",
+        iters: 1_000,
+        body: || crate::allocator::bench_alloc_many(false),
+    },
+    BenchCase {
+        name: "Allocate 32 small blocks from the bump region",
+        iters: 1_000,
+        body: || crate::allocator::bench_alloc_many(true),
+    },
+    BenchCase {
+        name: "Get and set bits of a u64",
+        iters: 100_000,
+        body: || {
+            let mut value = black_box(0x0123456789ABCDEFu64);
+            value.set_bits(31, 16, black_box(0xBEEF));
+            black_box(value.get_bits(39, 24));
+        },
+    },
+    BenchCase {
+        name: "Write 1 KiB to serial",
+        iters: 10,
+        body: || unsafe {
+            use core::fmt::Write;
+
+            if let Some(writer) = (*crate::io::serial::SERIAL_WRITER.0.get()).as_mut() {
+                for _ in 0..16 {
+                    writer.write_str(BENCH_LINE).unwrap();
+                }
+                writer.flush();
+            }
+        },
+    },
+    BenchCase {
+        // NOTE: Compare with and without the `hardened` feature.
+        name: "Fill the screen pixel by pixel",
+        iters: 10,
+        body: || {
+            crate::io::vga::with_screen(|writer| {
+                let (width, height) = writer.dimensions();
+                for y in 0..height {
+                    for x in 0..width {
+                        writer.write_pixel(
+                            x,
+                            y,
+                            black_box(crate::io::vga::Color::gray((x ^ y) as u8)),
+                        );
+                    }
+                }
+            });
+        },
+    },
+    BenchCase {
+        // NOTE: `clear()` goes through `utils::mem::fast_fill()`, compare with the plain slice
+        // fill below.
+        name: "Clear the screen",
+        iters: 10,
+        body: || {
+            crate::io::vga::with_screen(|writer| writer.clear());
+        },
+    },
+    BenchCase {
+        name: "Clear the screen with a slice fill",
+        iters: 10,
+        body: || {
+            crate::io::vga::with_screen(|writer| {
+                writer.with_pixels(|buffer, _| black_box(buffer).fill(0));
+            });
+        },
+    },
+    BenchCase {
+        name: "Dump the GDT and IDT",
+        iters: 1,
+        body: || {
+            crate::interrupts::Gdtr::print(true);
+            crate::interrupts::Idtr::print(true);
+        },
+    },
+];
+
+/// 64 bytes, written over serial.
+const BENCH_LINE: &str = "The quick brown fox jumps over the lazy dog, again and again...\n";
+
+pub fn bench_runner(benches: &[BenchCase]) {
+    println!("Running {} benchmarks", benches.len());
+
+    for bench in benches {
+        crate::watchdog::pet();
+
+        // Warm up.
+        (bench.body)();
+
+        let start = rdtsc();
+        for _ in 0..bench.iters {
+            (bench.body)();
+        }
+        let cycles = rdtsc() - start;
+
+        println!(
+            "{}: {} iterations, {} cycles/op",
+            bench.name,
+            bench.iters,
+            cycles / bench.iters.max(1)
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! CPU state helpers.
+
+use core::{
+    arch::{asm, naked_asm},
+    fmt,
+};
+
+use crate::{
+    io::{inb, outb},
+    utils::{
+        bits::{GetBit, SetBit},
+        cpuid,
+    },
+};
+
+/// Snapshot of the general-purpose registers.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+}
+
+impl Registers {
+    /// Captures the registers as they are when calling this.
+    ///
+    /// NOTE: `rdi` holds the pointer to the snapshot, and `rip`/`rsp` are the ones of the caller
+    /// right after the call.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let mut regs = Registers::default();
+        unsafe { capture_registers(&mut regs) };
+
+        regs
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = [
+            [("RAX", self.rax), ("RBX", self.rbx), ("RCX", self.rcx)],
+            [("RDX", self.rdx), ("RSI", self.rsi), ("RDI", self.rdi)],
+            [("RBP", self.rbp), ("RSP", self.rsp), ("R8 ", self.r8)],
+            [("R9 ", self.r9), ("R10", self.r10), ("R11", self.r11)],
+            [("R12", self.r12), ("R13", self.r13), ("R14", self.r14)],
+            [("R15", self.r15), ("RIP", self.rip), ("FLG", self.rflags)],
+        ];
+
+        for row in rows {
+            for (name, value) in row {
+                write!(f, "{} = {:#018x}  ", name, value)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stores every register in `regs`.
+///
+/// NOTE: This is naked so that nothing touches the registers before we save them.
+#[unsafe(naked)]
+unsafe extern "C" fn capture_registers(regs: *mut Registers) {
+    naked_asm!(
+        "mov [rdi + 0x00], rax",
+        "mov [rdi + 0x08], rbx",
+        "mov [rdi + 0x10], rcx",
+        "mov [rdi + 0x18], rdx",
+        "mov [rdi + 0x20], rsi",
+        "mov [rdi + 0x28], rdi",
+        "mov [rdi + 0x30], rbp",
+        "mov [rdi + 0x40], r8",
+        "mov [rdi + 0x48], r9",
+        "mov [rdi + 0x50], r10",
+        "mov [rdi + 0x58], r11",
+        "mov [rdi + 0x60], r12",
+        "mov [rdi + 0x68], r13",
+        "mov [rdi + 0x70], r14",
+        "mov [rdi + 0x78], r15",
+        // The caller's stack pointer, before pushing the return address.
+        "lea rax, [rsp + 8]",
+        "mov [rdi + 0x38], rax",
+        // The return address, e.g. where we were called from.
+        "mov rax, [rsp]",
+        "mov [rdi + 0x80], rax",
+        "pushfq",
+        "pop rax",
+        "mov [rdi + 0x88], rax",
+        // Restore `rax`, the caller might expect it to be untouched.
+        "mov rax, [rdi + 0x00]",
+        "ret",
+    )
+}
+
+/// Prints `count` 8-bytes words from the stack, starting at `rsp`.
+///
+/// Safety: `rsp` must point to `count` readable words.
+pub unsafe fn print_stack(rsp: u64, count: usize) {
+    let words = rsp as *const u64;
+
+    for i in 0..count {
+        let word = words.add(i);
+        println!("{:p}: {:#018x}", word, *word);
+    }
+}
+
+/// Enables SSE (and AVX if supported) so that SSE instructions don't fault.
+///
+/// NOTE: The `x86_64-unknown-none` target is soft-float, the compiler never emits SSE
+/// instructions by itself. This is for inline assembly and code built with SSE enabled.
+///
+/// NOTE: SSE registers are not saved on interrupts, so interrupt handlers must not use them.
+///
+/// Returns whether AVX was enabled too.
+pub fn enable_sse() -> bool {
+    assert!(cpuid::has_sse(), "SSE is not supported by the CPU.");
+
+    unsafe {
+        let mut cr0: u64;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        // Clear EM (x87 emulation) and set MP (monitor co-processor).
+        cr0.set_bit(2, false);
+        cr0.set_bit(1, true);
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+
+        let mut cr4: u64;
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        // OSFXSR (`fxsave`/`fxrstor` and SSE instructions) and OSXMMEXCPT (SSE exceptions).
+        cr4.set_bit(9, true);
+        cr4.set_bit(10, true);
+        asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+    }
+
+    if !cpuid::has_xsave() || !cpuid::has_avx() {
+        return false;
+    }
+
+    unsafe {
+        let mut cr4: u64;
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        // OSXSAVE, needed to access XCR0.
+        cr4.set_bit(18, true);
+        asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+
+        let (low, high): (u32, u32);
+        asm!("xgetbv", in("ecx") 0, out("eax") low, out("edx") high, options(nomem, nostack));
+        let mut xcr0 = ((high as u64) << 32) | low as u64;
+        // x87, SSE and AVX states.
+        xcr0.set_bits(2, 3, 0b111);
+        asm!(
+            "xsetbv",
+            in("ecx") 0,
+            in("eax") xcr0 as u32,
+            in("edx") (xcr0 >> 32) as u32,
+            options(nostack)
+        );
+
+        // Read it back, rather than trusting the value we wrote.
+        let (low, _high): (u32, u32);
+        asm!("xgetbv", in("ecx") 0, out("eax") low, out("edx") _high, options(nomem, nostack));
+        cpuid::has_avx() && low.get_bit(2)
+    }
+}
+
+/// Sleeps until the next interrupt, enabling interrupts so that one can come.
+///
+/// NOTE: `sti` only takes effect after the next instruction, so an interrupt can't sneak in right
+/// before the `hlt` and leave us sleeping until the one after.
+pub fn wait_for_interrupt() {
+    unsafe { asm!("sti", "hlt", options(nomem, nostack)) };
+}
+
+/// Idles forever, instead of spinning at 100% of a (host) CPU: each interrupt wakes us up, gives
+/// the scheduler's tasks a turn, and we go back to sleep.
+///
+/// NOTE: The IDT must be set up (see `interrupts::init()`), since this enables interrupts.
+pub fn idle() -> ! {
+    loop {
+        idle_once();
+    }
+}
+
+/// A single round of `idle()`, e.g. for a loop waiting on something only an interrupt brings.
+pub fn idle_once() {
+    crate::sched::yield_now();
+    // Sleeping is not being stuck, the timer wakes us up regularly.
+    crate::watchdog::pet();
+    wait_for_interrupt();
+}
+
+/// `IA32_EFER` MSR, and its No-Execute Enable bit.
+const IA32_EFER: u32 = 0xC000_0080;
+const EFER_NXE: u32 = 11;
+
+/// Write Protect bit of CR0.
+const CR0_WP: u32 = 16;
+
+/// Safety: `msr` must exist on this CPU, otherwise this faults.
+unsafe fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+
+    ((high as u64) << 32) | low as u64
+}
+
+/// Safety: See `rdmsr()`, and `value` must not break anything relying on that MSR.
+unsafe fn wrmsr(msr: u32, value: u64) {
+    asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Makes the page protections apply: CR0.WP so that the kernel can't write read-only pages
+/// either, and EFER.NXE (if the CPU supports NX) so that `PageFlags::NO_EXECUTE` is honored.
+///
+/// NOTE: Without NX, the `NO_EXECUTE` bit is reserved and mapping a page with it faults.
+///
+/// Returns whether NX was enabled.
+pub fn enable_memory_protection() -> bool {
+    let nx = cpuid::has_nx();
+
+    unsafe {
+        if nx {
+            let mut efer = rdmsr(IA32_EFER);
+            efer.set_bit(EFER_NXE, true);
+            wrmsr(IA32_EFER, efer);
+        }
+
+        let mut cr0: u64;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        cr0.set_bit(CR0_WP, true);
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+    }
+
+    nx
+}
+
+/// Maximum number of frames printed by `print_backtrace()`.
+const MAX_FRAMES: usize = 32;
+
+/// Prints the return addresses found by walking the frame pointers chain from `rbp`, so that
+/// they can be resolved offline, e.g. with `addr2line -e <kernel> <addr>`.
+///
+/// NOTE: This needs the kernel to be compiled with `-C force-frame-pointers=yes` (see
+/// `.cargo/config.toml`), otherwise `rbp` is just another register and we stop at the first
+/// frame that looks wrong.
+pub fn print_backtrace(mut rbp: u64) {
+    // We can only check that frames are mapped once paging can translate addresses.
+    if crate::memory::try_phys_offset().is_none() {
+        println!("No backtrace: physical memory offset is not set.");
+        return;
+    }
+
+    let is_mapped = |addr: u64| crate::memory::paging::translate(addr).is_some();
+
+    println!("Backtrace:");
+    for i in 0..MAX_FRAMES {
+        // Each frame holds the caller's `rbp`, followed by the return address.
+        if rbp == 0 || !rbp.is_multiple_of(8) || !is_mapped(rbp) || !is_mapped(rbp + 8) {
+            break;
+        }
+
+        let frame = rbp as *const u64;
+        let (caller_rbp, return_addr) = unsafe { (*frame, *frame.add(1)) };
+        if return_addr == 0 {
+            break;
+        }
+        println!("#{}: {:#x}", i, return_addr);
+
+        // The stack grows down, so callers' frames must be higher up.
+        if caller_rbp <= rbp {
+            break;
+        }
+        rbp = caller_rbp;
+    }
+}
+
+/// Command port of the 8042 (PS/2) controller, and its command pulsing the CPU reset line.
+const PS2_COMMAND_PORT: u16 = 0x64;
+const PS2_RESET: u8 = 0xFE;
+
+/// Resets the machine, through the 8042 controller, or with a triple fault if that did not work.
+///
+/// NOTE: What happens then is up to the firmware (and QEMU exits instead with `-no-reboot`). Some
+/// machines have no 8042 at all, and whether RAM is cleared varies too.
+pub fn reboot() -> ! {
+    unsafe {
+        // Bit 1: the controller's input buffer is full, it would miss our command.
+        for _ in 0..100_000 {
+            if !inb(PS2_COMMAND_PORT).get_bit(1) {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        outb(PS2_COMMAND_PORT, PS2_RESET);
+    }
+    crate::time::delay_us(10_000);
+
+    // With an empty IDT, the breakpoint can't be delivered, nor the resulting #GP and double
+    // fault: the CPU gives up and resets.
+    let empty_idt = [0u16; 5];
+    unsafe {
+        asm!(
+            "lidt [{}]",
+            "int3",
+            in(reg) &empty_idt,
+            options(noreturn)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_capture_registers() -> TestCase {
+        TestCase {
+            name: "Test capturing the registers",
+            test: || {
+                let local = 0u64;
+                let regs = Registers::capture();
+
+                // Our local variable lives just above the stack pointer.
+                let local = &local as *const u64 as u64;
+                assert!(regs.rsp <= local && local - regs.rsp < 4096);
+                // Bit 1 of RFLAGS is reserved and always set.
+                assert!(regs.rflags & 0b10 != 0);
+                assert_ne!(regs.rip, 0);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_sse() -> TestCase {
+        TestCase {
+            name: "Test running SSE instructions",
+            test: || {
+                // `addsd` raises #UD if SSE was not enabled.
+                // NOTE: We can't declare `xmm` clobbers since the target has SSE disabled, but
+                // the compiler doesn't use these registers either.
+                let (a, b) = (1.5f64, 2.25f64);
+                let sum: u64;
+                unsafe {
+                    asm!(
+                        "movq xmm0, {a}",
+                        "movq xmm1, {b}",
+                        "addsd xmm0, xmm1",
+                        "movq {sum}, xmm0",
+                        a = in(reg) a.to_bits(),
+                        b = in(reg) b.to_bits(),
+                        sum = lateout(reg) sum,
+                        options(nomem, nostack, preserves_flags)
+                    );
+                }
+                assert_eq!(f64::from_bits(sum), a + b);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_memory_protection() -> TestCase {
+        TestCase {
+            name: "Test enabling write protection and NX",
+            test: || {
+                let nx = enable_memory_protection();
+                assert_eq!(nx, cpuid::has_nx());
+
+                let cr0: u64;
+                unsafe { asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack)) };
+                assert!(cr0.get_bit(CR0_WP));
+                assert_eq!(unsafe { rdmsr(IA32_EFER) }.get_bit(EFER_NXE), nx);
+            },
+            ..Default::default()
+        }
+    }
+}
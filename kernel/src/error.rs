@@ -0,0 +1,46 @@
+//! Errors returned by the initialization of the kernel's subsystems.
+//!
+//! `kernel_main` decides what to do with them, e.g. continue without a screen, or stop the boot
+//! with a clear message.
+//!
+//! NOTE: Broken invariants (e.g. a corrupted boot info, a double free) still `panic!`, this is
+//! only for what can legitimately go wrong on some machines.
+
+use core::fmt;
+
+use crate::memory::paging::MapError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KernelError {
+    /// The serial chip failed its loopback test.
+    SerialInit,
+    /// The bootloader did not give us a framebuffer.
+    NoFramebuffer,
+    /// The bootloader did not map the physical memory.
+    NoPhysMem,
+    /// There is no memory left for what we were setting up.
+    AllocFailed,
+    /// Paging could not map a page we needed.
+    Map(MapError),
+    /// The PS/2 mouse did not answer as expected.
+    Mouse(&'static str),
+}
+
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernelError::SerialInit => f.write_str("The serial port failed its self test"),
+            KernelError::NoFramebuffer => f.write_str("No framebuffer in boot info"),
+            KernelError::NoPhysMem => f.write_str("Physical memory is not mapped"),
+            KernelError::AllocFailed => f.write_str("Out of memory"),
+            KernelError::Map(err) => write!(f, "Failed to map a page: {:?}", err),
+            KernelError::Mouse(err) => write!(f, "Mouse: {}", err),
+        }
+    }
+}
+
+impl From<MapError> for KernelError {
+    fn from(err: MapError) -> Self {
+        KernelError::Map(err)
+    }
+}
@@ -1,17 +1,133 @@
 #![allow(clippy::fn_to_numeric_cast)]
 
-use crate::utils::bits::{GetBit, SetBit};
-use core::{arch::asm, cell::OnceCell, fmt};
+use crate::io::pic;
+use crate::utils::bits::{bitfield, GetBit, SetBit};
+use core::{
+    arch::asm,
+    cell::{OnceCell, UnsafeCell},
+    fmt,
+};
 
-#[allow(unused)]
 #[repr(align(16))]
-struct AlignedGDT([SegmentDescriptor; 3]);
+struct AlignedGDT([SegmentDescriptor; 5]);
+
+/// Set once in `init()`, since the TSS descriptor (the last two entries) needs the TSS address.
+///
+/// NOTE: Being a `static`, its address stays valid for as long as the kernel runs, which `lgdt`
+/// requires.
+static GLOBAL_DESCRIPTOR_TABLE: Gdt = Gdt {
+    table: OnceCell::new(),
+};
+
+struct Gdt {
+    table: OnceCell<AlignedGDT>,
+}
+// Safety: We're in a single-threaded environment for now.
+unsafe impl Sync for Gdt {}
+
+/// Selector of the TSS descriptor in the GDT.
+const TSS_SELECTOR: u16 = 0x18;
+/// Selector of the kernel code segment, which every handler runs in.
+const KERNEL_CODE_SELECTOR: u16 = 0x08;
+/// Selector of the kernel data segment, loaded in every data segment register.
+const KERNEL_DATA_SELECTOR: u16 = 0x10;
+
+/// Segment selector, as found in segment registers and gates.
+///
+/// |15   3|2 |1  0|
+/// |Index |TI|RPL |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Selector(u16);
+
+impl Selector {
+    /// Index of the descriptor in its table.
+    fn index(&self) -> u16 {
+        self.0.get_bits(15, 13)
+    }
+
+    /// Table Indicator: whether the descriptor is in the LDT rather than the GDT.
+    fn ti(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    /// Requested Privilege Level.
+    fn rpl(&self) -> u8 {
+        self.0.get_bits(1, 2) as u8
+    }
+
+    /// Name of the GDT segment this selects, if it's one of ours.
+    fn name(&self) -> Option<&'static str> {
+        if self.ti() {
+            return None;
+        }
+
+        // The RPL does not change which segment is selected.
+        match self.0 & !0b11 {
+            0 => Some("null"),
+            KERNEL_CODE_SELECTOR => Some("kernel code"),
+            KERNEL_DATA_SELECTOR => Some("kernel data"),
+            TSS_SELECTOR => Some("TSS"),
+            _ => None,
+        }
+    }
+}
 
-static mut GLOBAL_DESCRIPTOR_TABLE: AlignedGDT = AlignedGDT([
-    SegmentDescriptor(0),
-    SegmentDescriptor::kernel_mode_code_segment(),
-    SegmentDescriptor::kernel_mode_data_segment(),
-]);
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name)?,
+            None => write!(f, "{:#X}", self.0)?,
+        }
+
+        write!(
+            f,
+            " (index {}, {}, RPL {})",
+            self.index(),
+            if self.ti() { "LDT" } else { "GDT" },
+            self.rpl()
+        )
+    }
+}
+
+/// IST (Interrupt Stack Table) index used by the double fault and page fault handlers.
+///
+/// NOTE: IST indices start at 1, 0 meaning "don't switch stacks".
+const FAULT_IST_INDEX: u8 = 1;
+const FAULT_STACK_SIZE: usize = 5 * 4096;
+
+#[repr(C, align(16))]
+struct FaultStack(UnsafeCell<[u8; FAULT_STACK_SIZE]>);
+// Safety: Only ever used by the CPU as a stack.
+unsafe impl Sync for FaultStack {}
+
+/// Stack the CPU switches to on a double fault or page fault, so that we can still report a
+/// kernel stack overflow.
+static FAULT_STACK: FaultStack = FaultStack(UnsafeCell::new([0; FAULT_STACK_SIZE]));
+
+/// Task State Segment. In 64-bit mode, it only holds stack pointers.
+#[derive(Default)]
+#[repr(C, packed(4))]
+struct TaskStateSegment {
+    reserved_1: u32,
+    /// Stacks used when switching to a more privileged ring.
+    privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    /// Stacks that gates with a non-zero IST switch to.
+    interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    iomap_base: u16,
+}
+
+struct Tss {
+    tss: OnceCell<TaskStateSegment>,
+}
+// Safety: We're in a single-threaded environment for now.
+unsafe impl Sync for Tss {}
+
+static TASK_STATE_SEGMENT: Tss = Tss {
+    tss: OnceCell::new(),
+};
 
 /// Segment Descriptor (64bits)
 ///
@@ -75,9 +191,16 @@ impl SegmentDescriptor {
         ((upper << 24) | rest) as u32
     }
 
-    /// In 64-bit mode, base is ignored.
-    fn _set_base(&mut self) {
-        unimplemented!()
+    /// In 64-bit mode, base is ignored for code and data segments but it is still used by system
+    /// segments (e.g. the TSS).
+    ///
+    /// NOTE: System descriptors are 16 bytes long, the upper 32 bits of their base are stored in
+    /// the next entry and are not handled here.
+    fn set_base(&mut self, base: u32) {
+        let base = base as u64;
+
+        self.0.set_bits(63, 8, base.get_bits(31, 8));
+        self.0.set_bits(39, 24, base.get_bits(23, 24));
     }
 
     fn limit(&self) -> u32 {
@@ -87,9 +210,40 @@ impl SegmentDescriptor {
         ((upper << 16) | rest) as u32
     }
 
-    /// In 64-bit mode, limit is ignored.
-    fn _set_limit(&mut self) {
-        unimplemented!()
+    /// In 64-bit mode, limit is ignored for code and data segments but it is still used by system
+    /// segments (e.g. the TSS).
+    ///
+    /// NOTE: The limit is only 20 bits long.
+    fn set_limit(&mut self, limit: u32) {
+        assert!(limit < (1 << 20), "Segment limit must fit in 20 bits.");
+        let limit = limit as u64;
+
+        self.0.set_bits(51, 4, limit.get_bits(19, 4));
+        self.0.set_bits(15, 16, limit.get_bits(15, 16));
+    }
+
+    /// Whether this is the first half of a 16 bytes system descriptor: present, with the S bit
+    /// clear and a type valid in 64-bit mode (LDT, available or busy TSS).
+    fn is_system(&self) -> bool {
+        let access_byte = self.access_byte();
+
+        access_byte.p()
+            && !access_byte.s()
+            && matches!(access_byte.0.get_bits(3, 4), 0x2 | 0x9 | 0xB)
+    }
+
+    /// A TSS descriptor is a 16 bytes system descriptor, e.g. it takes two GDT entries. The second
+    /// one only holds the upper 32 bits of the base.
+    fn task_state_segment(tss: &TaskStateSegment) -> [Self; 2] {
+        let base = tss as *const TaskStateSegment as u64;
+
+        let mut low = SegmentDescriptor(0);
+        low.set_base(base as u32);
+        low.set_limit((size_of::<TaskStateSegment>() - 1) as u32);
+        // Present, DPL 0, system segment of type 0x9 (available 64-bit TSS).
+        low.0.set_bits(47, 8, 0x89);
+
+        [low, SegmentDescriptor(base >> 32)]
     }
 
     // Pre-calculated and taken from the wiki.
@@ -115,6 +269,12 @@ enum Dpl {
     Ring3,
 }
 
+impl From<Dpl> for u8 {
+    fn from(value: Dpl) -> Self {
+        value as u8
+    }
+}
+
 impl TryFrom<u8> for Dpl {
     type Error = &'static str;
 
@@ -150,61 +310,15 @@ impl fmt::Display for AccessByte {
     }
 }
 
-impl AccessByte {
-    fn p(&self) -> bool {
-        self.0.get_bit(7)
-    }
-
-    #[cfg(test)]
-    fn set_p(&mut self, value: bool) {
-        self.0.set_bit(7, value);
-    }
-
-    fn dpl(&self) -> Dpl {
-        self.0
-            .get_bits(6, 2)
-            .try_into()
-            .expect("Unreachable expect.")
-    }
-
-    #[cfg(test)]
-    fn set_dpl(&mut self, value: Dpl) {
-        self.0.set_bits(6, 2, value as u8)
-    }
-
-    fn s(&self) -> bool {
-        self.0.get_bit(4)
-    }
-
-    #[cfg(test)]
-    fn set_s(&mut self, value: bool) {
-        self.0.set_bit(4, value);
-    }
-
-    fn e(&self) -> bool {
-        self.0.get_bit(3)
-    }
-
-    #[cfg(test)]
-    fn set_e(&mut self, value: bool) {
-        self.0.set_bit(3, value);
-    }
-
-    fn dc(&self) -> bool {
-        self.0.get_bit(2)
-    }
-
-    fn rw(&self) -> bool {
-        self.0.get_bit(1)
-    }
-
-    #[cfg(test)]
-    fn set_rw(&mut self, value: bool) {
-        self.0.set_bit(1, value);
-    }
-
-    fn a(&self) -> bool {
-        self.0.get_bit(0)
+bitfield! {
+    impl AccessByte(u8) {
+        p, set_p: bool @ 7;
+        dpl, set_dpl: Dpl @ 6, 2;
+        s, set_s: bool @ 4;
+        e, set_e: bool @ 3;
+        dc, set_dc: bool @ 2;
+        rw, set_rw: bool @ 1;
+        a, set_a: bool @ 0;
     }
 }
 
@@ -262,15 +376,15 @@ impl Flags {
 
 /// GDT descriptor.
 #[repr(C, packed)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Gdtr {
     limit: u16,
     base: u64,
 }
 
 impl Gdtr {
-    /// Prints the GDT
-    pub fn print(print_entries: bool) {
+    /// Reads the currently loaded GDT descriptor.
+    pub fn current() -> Self {
         let mut gdtr = Gdtr { limit: 0, base: 0 };
         unsafe {
             asm!(
@@ -280,83 +394,350 @@ impl Gdtr {
             );
         }
 
-        let limit = gdtr.limit;
-        let base = gdtr.base;
+        gdtr
+    }
 
-        println!("GDT: limit = {} + 1 bytes, base = {:#x}", limit, base);
+    /// Loads this GDT descriptor, then reads it back to check that the CPU took it.
+    ///
+    /// Safety: `base` must point to a valid GDT which outlives its use by the CPU, and whose
+    /// entries match the currently loaded segment selectors.
+    unsafe fn load(&self) {
+        asm!("lgdt [{}]", in(reg) self, options(nostack, preserves_flags));
 
-        let mut gdt = base as *mut u64;
+        let loaded = Gdtr::current();
+        assert_eq!(loaded, *self, "GDT was not loaded correctly");
+    }
 
-        // We're in 64-bit, so I'm hardcoding this 8.
-        let nb_entries = (gdtr.limit + 1) / 8;
-        println!("Number of entries in the GDT: {}", nb_entries);
+    /// Prints the current GDT, see `Display`.
+    pub fn print(print_entries: bool) {
+        // NOTE: This is many lines, which an interrupt handler's prints could end up in between.
+        let _log = crate::io::log_block();
+        let gdtr = Gdtr::current();
 
         if print_entries {
-            for i in 0..nb_entries {
-                println!("Entry #{}: {:p} = {:#016X}", i, gdt, *gdt);
-                println!("{}", SegmentDescriptor(*gdt));
+            print!("{:#}", gdtr);
+        } else {
+            print!("{}", gdtr);
+        }
+    }
+}
 
-                // TODO: The last one must be the TSS?
+/// Prints the limit and number of entries. With `{:#}`, the entries are printed too.
+///
+/// NOTE: This reads the GDT at `base`, which is only valid for the GDT descriptors we load or read
+/// with `current()`.
+impl fmt::Display for Gdtr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = self.limit;
+        let base = self.base;
+
+        writeln!(f, "GDT: limit = {} + 1 bytes, base = {:#x}", limit, base)?;
+
+        let mut gdt = base as *mut u64;
 
-                // Go to the next entry
-                gdt = unsafe { gdt.add(1) };
+        // We're in 64-bit, so I'm hardcoding this 8.
+        assert!(
+            (limit as usize + 1).is_multiple_of(8),
+            "GDT size is not a multiple of 8 bytes."
+        );
+        let nb_entries = (limit + 1) / 8;
+        writeln!(f, "Number of entries in the GDT: {}", nb_entries)?;
+
+        if !f.alternate() {
+            return Ok(());
+        }
+
+        let mut i = 0;
+        while i < nb_entries {
+            let descriptor = SegmentDescriptor(unsafe { *gdt });
+            writeln!(f, "Entry #{}: {:p} = {:#016X}", i, gdt, descriptor.0)?;
+
+            // System descriptors (e.g. the TSS) take two entries.
+            if descriptor.is_system() && i + 1 < nb_entries {
+                let upper = unsafe { *gdt.add(1) };
+                writeln!(
+                    f,
+                    "Entry #{}: {:p} = {:#016X}",
+                    i + 1,
+                    gdt.wrapping_add(1),
+                    upper
+                )?;
+                writeln!(
+                    f,
+                    "System segment (type = {:#X}): base = {:#x}, limit = {}\n",
+                    descriptor.access_byte().0.get_bits(3, 4),
+                    ((upper & 0xFFFF_FFFF) << 32) | descriptor.base() as u64,
+                    descriptor.limit()
+                )?;
+
+                i += 2;
+                gdt = unsafe { gdt.add(2) };
+                continue;
             }
+
+            writeln!(f, "{}", descriptor)?;
+
+            // Go to the next entry
+            i += 1;
+            gdt = unsafe { gdt.add(1) };
         }
+
+        Ok(())
     }
 }
 
-extern "x86-interrupt" fn interrupt_handler() {
+/// What the CPU pushes on the stack before calling an interrupt handler.
+#[derive(Debug)]
+#[repr(C)]
+pub struct InterruptStackFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Called by the trampolines installed for every vector without a handler of its own.
+fn unhandled(vector: u8, frame: &InterruptStackFrame, error_code: Option<u64>) -> ! {
+    match error_code {
+        Some(error_code) => panic!(
+            "Unhandled interrupt {} (error code = {:#x}) at rip = {:#x}\n{:#x?}",
+            vector, error_code, frame.rip, frame
+        ),
+        None => panic!(
+            "Unhandled interrupt {} at rip = {:#x}\n{:#x?}",
+            vector, frame.rip, frame
+        ),
+    }
+}
+
+/// A handler can't know which vector it was called for, so each vector gets its own instance.
+extern "x86-interrupt" fn unhandled_trampoline<const VECTOR: u8>(frame: InterruptStackFrame) {
+    unhandled(VECTOR, &frame, None);
+}
+
+/// Same as `unhandled_trampoline()`, for the exceptions pushing an error code.
+extern "x86-interrupt" fn unhandled_error_trampoline<const VECTOR: u8>(
+    frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    unhandled(VECTOR, &frame, Some(error_code));
+}
+
+pub type Trampoline = extern "x86-interrupt" fn(InterruptStackFrame);
+
+/// Expands to the 16 trampolines of vectors `16 * $hi` to `16 * $hi + 15`.
+macro_rules! trampoline_row {
+    ($hi:literal; $($lo:literal)+) => {
+        [$(unhandled_trampoline::<{ 16 * $hi + $lo }> as Trampoline),+]
+    };
+}
+
+/// `unhandled_trampoline()` of each vector, indexed by `[vector / 16][vector % 16]`.
+static TRAMPOLINES: [[Trampoline; 16]; 16] = {
+    macro_rules! rows {
+        ($($hi:literal)+) => {
+            [$(trampoline_row!($hi; 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)),+]
+        };
+    }
+
+    rows!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)
+};
+
+/// Address of the trampoline reporting `vector` to `unhandled()`.
+fn trampoline(vector: u8) -> u64 {
+    // NOTE: These exceptions push an error code, which would otherwise be taken for `rip`.
+    macro_rules! with_error_code {
+        ($($v:literal),+) => {
+            match vector {
+                $($v => unhandled_error_trampoline::<$v> as u64,)+
+                vector => TRAMPOLINES[vector as usize / 16][vector as usize % 16] as u64,
+            }
+        };
+    }
+
+    with_error_code!(10, 11, 12, 13, 17, 21, 29, 30)
+}
+
+/// Logs the breakpoint and carries on, e.g. `int3` can be used to check interrupts work.
+extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
+    println!("BREAKPOINT at rip = {:#x}", frame.rip);
+}
+
+extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, _error_code: u64) -> ! {
+    panic!("DOUBLE FAULT at rip = {:#x}\n{:#x?}", frame.rip, frame);
+}
+
+extern "x86-interrupt" fn page_fault_handler(frame: InterruptStackFrame, error_code: u64) {
+    // CR2 holds the address that caused the fault.
+    let addr: u64;
     unsafe {
-        asm!("nop");
+        asm!("mov {}, cr2", out(reg) addr, options(nomem, nostack, preserves_flags));
     }
-    println!("INTERRRRUPPPPTTT");
-    panic!("INTERRRRUPPPPTTT");
+
+    if crate::memory::stack::is_guard_page(addr) {
+        panic!(
+            "STACK OVERFLOW: accessed guard page at {:#x}, rip = {:#x}",
+            addr, frame.rip
+        );
+    }
+
+    panic!(
+        "PAGE FAULT at {:#x} (error code = {:#x}), rip = {:#x}\n{:#x?}",
+        addr, error_code, frame.rip, frame
+    );
 }
 
-// FIXME: Set at compile time, is it correct ?
+extern "x86-interrupt" fn timer_handler(_frame: InterruptStackFrame) {
+    crate::time::handle_irq();
+}
+
+extern "x86-interrupt" fn keyboard_handler(_frame: InterruptStackFrame) {
+    crate::io::keyboard::handle_irq();
+}
+
+extern "x86-interrupt" fn irq7_handler(_frame: InterruptStackFrame) {
+    pic::handle_irq7();
+}
+
+extern "x86-interrupt" fn mouse_handler(_frame: InterruptStackFrame) {
+    crate::io::mouse::handle_irq();
+}
+
+extern "x86-interrupt" fn irq15_handler(_frame: InterruptStackFrame) {
+    pic::handle_irq15();
+}
+
+/// Filled by `init()`, entries can then be replaced with `set_handler()`.
+///
+/// NOTE: Being a `static`, its address stays valid for as long as the kernel runs, which `lidt`
+/// requires.
 static INTERRUPT_DESCRIPTOR_TABLE: Idt = Idt {
-    handlers: OnceCell::new(),
+    handlers: UnsafeCell::new([const { GateDescriptor(0, 0) }; 256]),
 };
 
 struct Idt {
-    handlers: OnceCell<[GateDescriptor; 256]>,
+    handlers: UnsafeCell<[GateDescriptor; 256]>,
 }
-// Safety: We're in a single-threaded environment for now.
+// Safety: We're in a single-threaded environment for now, and entries are only written with
+// interrupts disabled.
 unsafe impl Sync for Idt {}
 
+impl Idt {
+    /// Descriptor to load this IDT with.
+    fn idtr(&self) -> Idtr {
+        Idtr {
+            limit: (size_of::<[GateDescriptor; 256]>() - 1) as u16,
+            base: self.handlers.get() as u64,
+        }
+    }
+}
+
+/// Installs `handler` for `vector` in our IDT.
+///
+/// The IDT is read by the CPU on each interrupt, so once `init()` loaded it, this takes effect
+/// right away.
+#[allow(unused)]
+pub fn set_handler(vector: u8, handler: Trampoline, gtype: GateType) {
+    let gate = match gtype {
+        GateType::Interrupt => GateDescriptor::interrupt(handler as u64),
+        GateType::Trap => GateDescriptor::trap(handler as u64),
+    };
+
+    // NOTE: An interrupt for `vector` coming in while the entry is half written would jump to a
+    // bogus address.
+    without_interrupts(|| unsafe {
+        (*INTERRUPT_DESCRIPTOR_TABLE.handlers.get())[vector as usize] = gate;
+    });
+}
+
 // Interrupt Table Descriptor
 #[repr(C, packed)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Idtr {
     limit: u16,
     base: u64,
 }
 
 impl Idtr {
-    pub fn print() {
+    /// Reads the currently loaded IDT descriptor.
+    pub fn current() -> Self {
         let mut idtr = Idtr { limit: 0, base: 0 };
         unsafe {
             asm!(
-                    "sidt [{idtr}]",
-                    idtr = in(reg) &mut idtr,
+                "sidt [{idtr}]",
+                idtr = in(reg) &mut idtr,
                 options(nostack, preserves_flags)
             );
         }
 
-        // let ptr = &idtr as *const IDTR as *const u8;
-        // let limit = unsafe { *(ptr as *const u16) };
-        let limit = idtr.limit;
-        let base = idtr.base;
-        // let base = unsafe { *(ptr.add(2) as *const u64) };
+        idtr
+    }
+
+    /// Loads this IDT descriptor, then reads it back to check that the CPU took it.
+    ///
+    /// Safety: `base` must point to a valid IDT which outlives its use by the CPU.
+    unsafe fn load(&self) {
+        asm!("lidt [{}]", in(reg) self, options(nostack, preserves_flags));
 
-        println!("IDT: limit = {} + 1 bytes, base = {:#X}", limit, base);
-        let nb_entries = (idtr.limit + 1) / 16;
-        println!("Number of entries in the IDT: {}", nb_entries);
+        let loaded = Idtr::current();
+        assert_eq!(loaded, *self, "IDT was not loaded correctly");
+    }
+
+    /// Prints the current IDT, see `Display`.
+    pub fn print(print_entries: bool) {
+        // NOTE: See `Gdtr::print()`.
+        let _log = crate::io::log_block();
+        let idtr = Idtr::current();
+
+        if print_entries {
+            print!("{:#}", idtr);
+        } else {
+            print!("{}", idtr);
+        }
+    }
+}
+
+/// Prints the limit and number of entries. With `{:#}`, the present entries are printed too
+/// (otherwise we'd get ~250 empty gates).
+///
+/// NOTE: Same as `Gdtr`, this reads the IDT at `base`.
+impl fmt::Display for Idtr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = self.limit;
+        let base = self.base;
+
+        writeln!(f, "IDT: limit = {} + 1 bytes, base = {:#X}", limit, base)?;
+        let nb_entries = (limit + 1) / 16;
+        writeln!(f, "Number of entries in the IDT: {}", nb_entries)?;
+
+        if !f.alternate() {
+            return Ok(());
+        }
+
+        let mut idt = base as *const u64;
+        for i in 0..nb_entries {
+            // Each gate is 16 bytes, e.g. two `u64`s.
+            let descriptor = unsafe { GateDescriptor(*idt, *idt.add(1)) };
+
+            if descriptor.p() {
+                writeln!(f, "Entry #{}: {:p}", i, idt)?;
+                writeln!(f, "{}", descriptor)?;
+            }
+
+            // Go to the next entry
+            idt = unsafe { idt.add(2) };
+        }
+
+        Ok(())
     }
 }
 
 /// These are 2 kinds of interrupts.
+///
+/// NOTE: Interrupt gates disable interrupts while their handler runs, trap gates don't.
 #[derive(Debug, PartialEq, Eq)]
-enum GateType {
+pub enum GateType {
     Interrupt,
     Trap,
 }
@@ -373,15 +754,22 @@ impl TryFrom<u8> for GateType {
     }
 }
 
-impl From<GateType> for u8 {
-    fn from(value: GateType) -> Self {
-        match value {
+impl GateType {
+    /// Value of the "Gate Type" field of a `GateDescriptor`.
+    const fn bits(&self) -> u8 {
+        match self {
             GateType::Interrupt => 0x0E,
             GateType::Trap => 0x0F,
         }
     }
 }
 
+impl From<GateType> for u8 {
+    fn from(value: GateType) -> Self {
+        value.bits()
+    }
+}
+
 /// A entry in the IDTR that gives points to the function to run on interrupt.
 ///
 /// First u64:
@@ -393,7 +781,7 @@ impl From<GateType> for u8 {
 /// |Offset (16 of 64bits)|P |DPL (2bits)|0 |Gate Type (4bits)|Reserved (5bits)|IST (3bits)|
 /// |31                     16|15                  0|
 /// |Segment Selector (16bits)|Offset (16 of 64bits)|
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 struct GateDescriptor(u64, u64);
 
 impl fmt::Display for GateDescriptor {
@@ -404,23 +792,67 @@ impl fmt::Display for GateDescriptor {
         writeln!(f, "DPL: {:?}", self.dpl())?;
         writeln!(f, "Gate Type: {:?}", self.gate_type())?;
         writeln!(f, "IST: {:#X}", self.ist())?;
-        writeln!(f, "Segment Selector: {:#X}", self.selector())?;
+        writeln!(f, "Segment Selector: {}", Selector(self.selector()))?;
 
         Ok(())
     }
 }
 
+// A descriptor built at compile time has the fields at the right place, and the tests check that
+// the setters agree (see `test_gate_descriptor_const`).
+const _: () = {
+    let gd = GateDescriptor::new(0x0123_4567_89AB_CDEF, 0x08, Dpl::Ring3, GateType::Trap);
+    assert!(gd.0 == 0x89AB_EF00_0008_CDEF && gd.1 == 0x0123_4567);
+};
+
 impl GateDescriptor {
-    fn new(fn_ptr: u64, selector: u16, dpl: Dpl, gtype: GateType) -> Self {
-        let mut descriptor = GateDescriptor(0, 0);
+    /// NOTE: This is a `const fn`, but the IDT is still filled at runtime: casting a function
+    /// pointer to an integer (e.g. `handler as u64`) can't be done at compile time.
+    const fn new(fn_ptr: u64, selector: u16, dpl: Dpl, gtype: GateType) -> Self {
+        // NOTE: `GetBit`/`SetBit` are not const, so this builds the bits itself. They must match
+        // what the setters below write.
+        let low = (fn_ptr & 0xFFFF)
+            | (selector as u64) << 16
+            | (gtype.bits() as u64) << 40
+            | (dpl as u64) << 45
+            | 1 << 47
+            | (fn_ptr >> 16 & 0xFFFF) << 48;
+
+        GateDescriptor(low, fn_ptr >> 32)
+    }
+
+    /// Interrupt gate to `handler`, in the kernel code segment and only reachable from Ring0.
+    ///
+    /// NOTE: Interrupts are disabled while `handler` runs, unlike with `trap()`.
+    const fn interrupt(handler: u64) -> Self {
+        Self::new(
+            handler,
+            KERNEL_CODE_SELECTOR,
+            Dpl::Ring0,
+            GateType::Interrupt,
+        )
+    }
+
+    /// Trap gate to `handler`, in the kernel code segment and only reachable from Ring0.
+    const fn trap(handler: u64) -> Self {
+        Self::new(handler, KERNEL_CODE_SELECTOR, Dpl::Ring0, GateType::Trap)
+    }
 
-        descriptor.set_offset(fn_ptr);
-        descriptor.set_selector(selector);
-        descriptor.set_dpl(dpl);
-        descriptor.set_gate_type(gtype);
-        descriptor.set_p(true);
+    /// Switches to the stack of the IST entry `index` (1 to 7) when entering the handler.
+    const fn with_ist(mut self, index: u8) -> Self {
+        assert!(
+            index >= 1 && index <= 7,
+            "Invalid IST index, must be 1 to 7"
+        );
+        self.0 = self.0 & !(0b111 << 32) | (index as u64) << 32;
+        self
+    }
 
-        descriptor
+    /// Lets code running in `dpl` (or a more privileged ring) trigger the gate with `int`.
+    #[allow(unused)]
+    const fn with_dpl(mut self, dpl: Dpl) -> Self {
+        self.0 = self.0 & !(0b11 << 45) | (dpl as u64) << 45;
+        self
     }
 
     fn offset(&self) -> u64 {
@@ -431,174 +863,271 @@ impl GateDescriptor {
         (upper << 32) | (lower_first << 16) | lower_rest
     }
 
-    fn set_offset(&mut self, offset: u64) {
-        self.1.set_bits(31, 32, offset >> 32);
-        self.0.set_bits(63, 16, offset.get_bits(31, 16));
-        self.0.set_bits(15, 16, offset.get_bits(15, 16));
-    }
-
     fn p(&self) -> bool {
         self.0.get_bit(47)
     }
 
-    fn set_p(&mut self, value: bool) {
-        self.0.set_bit(47, value);
-    }
-
     fn dpl(&self) -> Dpl {
         (self.0.get_bits(46, 2) as u8)
             .try_into()
             .expect("Invalid DPL found in GateDescriptor.")
     }
 
-    fn set_dpl(&mut self, dpl: Dpl) {
-        self.0.set_bits(46, 2, dpl as u64);
-    }
-
     fn gate_type(&self) -> GateType {
         GateType::try_from(self.0.get_bits(43, 4) as u8)
             .expect("Invalid GateType found in GateDescriptor.")
     }
 
-    fn set_gate_type(&mut self, gtype: GateType) {
-        self.0.set_bits(43, 4, u8::from(gtype) as u64);
-    }
-
     /// Offset to the IST (Interrupt Stack Table) stored in the TSS (Task State Segment). If set
     /// to 0, means the IST is not used.
     fn ist(&self) -> u8 {
         self.0.get_bits(34, 3) as u8
     }
 
-    #[cfg(test)]
-    fn set_ist(&mut self, value: u8) {
-        self.0.set_bits(34, 3, value as u64);
-    }
-
     fn selector(&self) -> u16 {
         self.0.get_bits(31, 16) as u16
     }
+}
+
+/// Field by field updates, e.g. to patch a descriptor already in the IDT.
+///
+/// NOTE: The constructors are `const fn`s, so they don't use these.
+#[allow(unused)]
+impl GateDescriptor {
+    fn set_offset(&mut self, offset: u64) {
+        self.1.set_bits(31, 32, offset >> 32);
+        self.0.set_bits(63, 16, offset.get_bits(31, 16));
+        self.0.set_bits(15, 16, offset.get_bits(15, 16));
+    }
+
+    fn set_p(&mut self, value: bool) {
+        self.0.set_bit(47, value);
+    }
+
+    fn set_dpl(&mut self, dpl: Dpl) {
+        self.0.set_bits(46, 2, dpl as u64);
+    }
+
+    fn set_gate_type(&mut self, gtype: GateType) {
+        self.0.set_bits(43, 4, u8::from(gtype) as u64);
+    }
+
+    fn set_ist(&mut self, value: u8) {
+        // NOTE: `set_bits` keeps only the low 3 bits, a bigger index would pick another stack.
+        debug_assert!(value < 8, "IST index out of range: {}", value);
+        self.0.set_bits(34, 3, value as u64);
+    }
 
     fn set_selector(&mut self, selector: u16) {
         self.0.set_bits(31, 16, selector as u64);
     }
 }
 
-pub fn init() {
-    // 1. Disable interrupts
+/// Expands to a `match` running `int <vector>` for each of the listed vectors, since the vector
+/// is an immediate of the instruction.
+macro_rules! raise_match {
+    ($vector:expr; $($v:literal),+) => {
+        match $vector {
+            $($v => unsafe { asm!(concat!("int ", stringify!($v))) },)+
+            vector => panic!("Can't raise interrupt {:#x}, see `interrupts::raise()`", vector),
+        }
+    };
+}
+
+/// Triggers interrupt `vector` with `int`, e.g. to test its handler.
+///
+/// NOTE: Only the breakpoint (3) and the software vectors `0x80` to `0x87` are supported, add
+/// more to the list if needed. Any other vector panics.
+pub fn raise(vector: u8) {
+    raise_match!(vector; 3, 0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87)
+}
+
+/// Enables interrupts.
+///
+/// NOTE: The IDT must be loaded by then, see `init()`.
+pub fn enable() {
+    unsafe {
+        asm!("sti", options(nostack, preserves_flags));
+    }
+}
+
+/// Disables interrupts.
+pub fn disable() {
     unsafe {
         asm!("cli", options(nostack, preserves_flags));
     }
+}
 
-    // Check that interrupts were correctly disabled
+/// Reads the IF (Interrupt enable Flag) bit from RFLAGS.
+pub fn are_enabled() -> bool {
+    let rflags: u64;
     unsafe {
-        let mut cpu_flags: i32;
         asm!(
             "pushf",
-            "pop {cpu_flags:r}",
-            "push {cpu_flags:r}",
-            "popf",
-            cpu_flags = out(reg) cpu_flags
+            "pop {rflags}",
+            rflags = out(reg) rflags,
+            options(nomem, preserves_flags)
         );
+    }
 
-        assert_eq!(
-            (cpu_flags >> 9) & 1,
-            0,
-            "Disabling interrupts did not work."
-        );
+    rflags.get_bit(9)
+}
+
+/// Disables interrupts for as long as it lives.
+///
+/// On `Drop`, interrupts are only re-enabled if they were enabled when the guard was created, so
+/// nesting guards (or creating one before `init()`) never turns interrupts on by mistake.
+pub struct InterruptGuard {
+    were_enabled: bool,
+}
+
+impl InterruptGuard {
+    pub fn new() -> Self {
+        let were_enabled = are_enabled();
+        disable();
+
+        Self { were_enabled }
     }
+}
 
-    // 2. Tell the CPU where the Global Descriptor Table (GDT) is
-    let gdtr = Gdtr {
-        limit: (3 * 8 - 1) as u16,
-        base: &raw const GLOBAL_DESCRIPTOR_TABLE as *const _ as u64,
-    };
-    unsafe {
-        asm!(
-            "lgdt [{}]",
-            in(reg) &gdtr, options(nostack, preserves_flags)
-        );
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if self.were_enabled {
+            enable();
+        }
     }
+}
+
+/// Runs `f` with interrupts disabled, then restores the previous interrupt state.
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = InterruptGuard::new();
+
+    f()
+}
 
-    // Read it to check that it worked.
+pub fn init() {
+    // 1. Disable interrupts
+    disable();
+    assert!(!are_enabled(), "Disabling interrupts did not work.");
+
+    // 2. Set up the TSS so that faults can switch to a known good stack, and add it to the GDT.
+    let tss = TASK_STATE_SEGMENT.tss.get_or_init(|| {
+        let mut tss = TaskStateSegment::default();
+        let stack_top = FAULT_STACK.0.get() as u64 + FAULT_STACK_SIZE as u64;
+        tss.interrupt_stack_table[FAULT_IST_INDEX as usize - 1] = stack_top;
+
+        tss
+    });
+    let gdt = GLOBAL_DESCRIPTOR_TABLE.table.get_or_init(|| {
+        let [tss_low, tss_high] = SegmentDescriptor::task_state_segment(tss);
+
+        AlignedGDT([
+            SegmentDescriptor(0),
+            SegmentDescriptor::kernel_mode_code_segment(),
+            SegmentDescriptor::kernel_mode_data_segment(),
+            tss_low,
+            tss_high,
+        ])
+    });
+
+    // Tell the CPU where the Global Descriptor Table (GDT) is
+    let gdtr = Gdtr {
+        // NOTE: Not `size_of::<AlignedGDT>()`, which includes the alignment padding.
+        limit: (size_of_val(&gdt.0) - 1) as u16,
+        base: gdt.0.as_ptr() as u64,
+    };
+    // Safety: The GDT is a `static`, and its code/data selectors are reloaded right after.
+    unsafe { gdtr.load() };
     Gdtr::print(false);
 
     // 3. Reload segment registers
     unsafe {
         asm!(
             // Reload the CS (Code Segment) register:
-            // 0x08 is the selector for the code segment
-            "push 0x08",
+            "push {code}",
             "lea rax, [rip + 2f]",
             "push rax",
             "retfq",
             // Reload the other segments:
             "2:",
-            // 0x10 is the selector for the data segment
-            "mov ax, 0x10",
+            "mov ax, {data}",
             "mov ds, ax",
             "mov es, ax",
             "mov fs, ax",
             "mov gs, ax",
             "mov ss, ax",
+            code = const KERNEL_CODE_SELECTOR,
+            data = const KERNEL_DATA_SELECTOR,
+            out("rax") _,
             options(nostack, preserves_flags)
         );
     };
 
-    // 4. Disable PICs handling interrupts (because they were set in BIOS and conflict)
+    // Check that the CPU took them.
+    let (cs, ss): (u16, u16);
     unsafe {
-        crate::io::outb(0x21, 0xFF); // PIC1 mask all
-        crate::io::outb(0xA1, 0xFF); // PIC2 mask all
+        asm!(
+            "mov {0:x}, cs",
+            "mov {1:x}, ss",
+            out(reg) cs,
+            out(reg) ss,
+            options(nomem, nostack, preserves_flags)
+        );
     }
+    assert!(
+        cs == KERNEL_CODE_SELECTOR && ss == KERNEL_DATA_SELECTOR,
+        "Segments were not reloaded: CS = {}, SS = {}",
+        Selector(cs),
+        Selector(ss)
+    );
+    println!("CS = {}, SS = {}", Selector(cs), Selector(ss));
+
+    // Load the TSS
+    unsafe {
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
+    }
+
+    // 4. Remap the PICs (their BIOS vectors conflict with exceptions) and mask all their IRQs
+    pic::init();
 
     // 5. Initialize the starting Interrupt Descriptor Table (IDT)
-    let _ = INTERRUPT_DESCRIPTOR_TABLE
-        .handlers
-        .set(core::array::from_fn(|i| {
-            if i == 2 {
-                GateDescriptor::new(
-                    interrupt_handler as u64,
-                    0x08,
-                    Dpl::Ring0,
-                    GateType::Interrupt,
-                )
-            } else if i == 3 {
-                GateDescriptor::new(interrupt_handler as u64, 0x08, Dpl::Ring0, GateType::Trap)
-            } else {
-                GateDescriptor::default()
-            }
-        }));
+    // Safety: Interrupts are disabled, and the IDT is not loaded yet.
+    let handlers = unsafe { &mut *INTERRUPT_DESCRIPTOR_TABLE.handlers.get() };
+    *handlers = core::array::from_fn(|i| match i {
+        3 => GateDescriptor::trap(breakpoint_handler as u64),
+        8 => GateDescriptor::interrupt(double_fault_handler as u64).with_ist(FAULT_IST_INDEX),
+        14 => GateDescriptor::interrupt(page_fault_handler as u64).with_ist(FAULT_IST_INDEX),
+        i if i == pic::PIC1_OFFSET as usize => GateDescriptor::interrupt(timer_handler as u64),
+        i if i == (pic::PIC1_OFFSET + 1) as usize => {
+            GateDescriptor::interrupt(keyboard_handler as u64)
+        }
+        i if i == (pic::PIC1_OFFSET + 7) as usize => GateDescriptor::interrupt(irq7_handler as u64),
+        i if i == (pic::PIC2_OFFSET + 4) as usize => {
+            GateDescriptor::interrupt(mouse_handler as u64)
+        }
+        i if i == (pic::PIC2_OFFSET + 7) as usize => {
+            GateDescriptor::interrupt(irq15_handler as u64)
+        }
+        i => GateDescriptor::interrupt(trampoline(i as u8)),
+    });
 
     // 7. Tell the CPU where the Interrupt Descriptor Table (IDT) is
-    let handlers = INTERRUPT_DESCRIPTOR_TABLE
-        .handlers
-        .get()
-        .expect("INTERRUPT_DESCRIPTOR_TABLE should have been set by now");
-    let idtr = Idtr {
-        limit: (handlers.len() * 16 - 1) as u16,
-        base: handlers.as_ptr() as *const u64 as u64,
-    };
-    unsafe {
-        asm!(
-            "lidt [{idt_ptr}]",
-            idt_ptr = in(reg) &idtr,
-            options(nostack, preserves_flags)
-        );
-    }
-
-    // Print it to check that it worked
-    Idtr::print();
+    // Safety: The IDT is a `static`.
+    unsafe { INTERRUPT_DESCRIPTOR_TABLE.idtr().load() };
+    Idtr::print(true);
 
     // 7. Re-enable interrupts
-    unsafe {
-        asm!("sti", options(nostack, preserves_flags));
-    }
+    enable();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::testing::TestCase;
+    use core::sync::atomic::{AtomicUsize, Ordering};
 
     #[test_case]
     fn test_set_flags() -> TestCase {
@@ -613,6 +1142,7 @@ mod tests {
                 f.set_l(true);
                 assert_eq!(f.0, 0xE0);
             },
+            ..Default::default()
         }
     }
 
@@ -628,12 +1158,25 @@ mod tests {
                 assert_eq!(ab.0, 0xE0);
                 ab.set_s(true);
                 assert_eq!(ab.0, 0xF0);
+                ab.set_dpl(Dpl::Ring0);
+                assert_eq!(ab.0, 0x90);
+
+                // Same as `kernel_mode_code_segment()`.
+                let ab = AccessByte(0x9A);
+                assert!(ab.p());
+                assert_eq!(ab.dpl(), Dpl::Ring0);
+                assert!(ab.s());
+                assert!(ab.e());
+                assert!(!ab.dc());
+                assert!(ab.rw());
+                assert!(!ab.a());
             },
+            ..Default::default()
         }
     }
 
     #[test_case]
-    fn test_set_access_byte() -> TestCase {
+    fn test_segment_descriptor_fields() -> TestCase {
         TestCase {
             name: "Test getting Flags and AccessByte from SegmentDescriptor",
             test: || {
@@ -649,6 +1192,7 @@ mod tests {
                 let abmut = sd.access_byte_mut();
                 assert_eq!(abmut.0, 0xFF);
             },
+            ..Default::default()
         }
     }
 
@@ -666,6 +1210,155 @@ mod tests {
                     0x00C0920000000000
                 );
             },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_system_segment_descriptor() -> TestCase {
+        TestCase {
+            name: "Test detecting the TSS as a 16 bytes system descriptor",
+            test: || {
+                let tss = TaskStateSegment::default();
+                let [low, high] = SegmentDescriptor::task_state_segment(&tss);
+                assert!(low.is_system());
+
+                let base = &tss as *const TaskStateSegment as u64;
+                assert_eq!((high.0 << 32) | low.base() as u64, base);
+                assert_eq!(low.limit() as usize, size_of::<TaskStateSegment>() - 1);
+
+                assert!(!SegmentDescriptor(0).is_system());
+                assert!(!SegmentDescriptor::kernel_mode_code_segment().is_system());
+                assert!(!SegmentDescriptor::kernel_mode_data_segment().is_system());
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_segment_descriptor_base_limit() -> TestCase {
+        TestCase {
+            name: "Test SegmentDescriptor by setting/getting base and limit",
+            test: || {
+                let mut sd = SegmentDescriptor(0);
+                sd.set_base(0x12345678);
+                assert_eq!(sd.base(), 0x12345678);
+                assert_eq!(sd.0, 0x1200003456780000);
+
+                sd.set_limit(0xABCDE);
+                assert_eq!(sd.limit(), 0xABCDE);
+                assert_eq!(sd.base(), 0x12345678);
+                assert_eq!(sd.0, 0x120A00345678BCDE);
+
+                // Setting the base and limit must not touch the flags nor the access byte.
+                let mut sd = SegmentDescriptor::kernel_mode_code_segment();
+                sd.set_base(0xFFFFFFFF);
+                sd.set_limit(0xFFFFF);
+                assert_eq!(sd.base(), 0xFFFFFFFF);
+                assert_eq!(sd.limit(), 0xFFFFF);
+                assert_eq!(sd.flags().0, 0x0A);
+                assert_eq!(sd.access_byte().0, 0x9A);
+
+                sd.set_base(0);
+                sd.set_limit(0);
+                assert_eq!(sd.0, SegmentDescriptor::kernel_mode_code_segment().0);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_segment_descriptor_limit_too_big() -> TestCase {
+        TestCase {
+            name: "Test SegmentDescriptor rejects limits over 20 bits",
+            test: || SegmentDescriptor(0).set_limit(1 << 20),
+            should_panic: true,
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_enable_disable() -> TestCase {
+        TestCase {
+            name: "Test are_enabled reflects enable/disable",
+            test: || {
+                let before = are_enabled();
+
+                disable();
+                assert!(!are_enabled());
+                enable();
+                assert!(are_enabled());
+                disable();
+                assert!(!are_enabled());
+
+                if before {
+                    enable();
+                }
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_without_interrupts() -> TestCase {
+        TestCase {
+            name: "Test without_interrupts restores the previous interrupt state",
+            test: || {
+                let before = are_enabled();
+
+                let ret = without_interrupts(|| {
+                    assert!(!are_enabled());
+
+                    // Nested guards must not re-enable interrupts on drop.
+                    without_interrupts(|| assert!(!are_enabled()));
+                    assert!(!are_enabled());
+
+                    42
+                });
+
+                assert_eq!(ret, 42);
+                assert_eq!(are_enabled(), before);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_reload_gdt() -> TestCase {
+        TestCase {
+            name: "Test loading the GDT reads back the same descriptor",
+            test: || {
+                let gdtr = Gdtr::current();
+                assert!((gdtr.limit as usize + 1).is_multiple_of(8));
+
+                unsafe { gdtr.load() };
+                assert_eq!(Gdtr::current(), gdtr);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_load_idt() -> TestCase {
+        TestCase {
+            name: "Test loading an IDT reads back the same descriptor",
+            test: || {
+                static TEST_IDT: [GateDescriptor; 32] = [const { GateDescriptor(0, 0) }; 32];
+
+                let previous = Idtr::current();
+                let idtr = Idtr {
+                    limit: (size_of_val(&TEST_IDT) - 1) as u16,
+                    base: TEST_IDT.as_ptr() as u64,
+                };
+
+                // No interrupt must come in while our empty IDT is loaded.
+                without_interrupts(|| unsafe {
+                    idtr.load();
+                    previous.load();
+                });
+                assert_eq!(Idtr::current(), previous);
+            },
+            ..Default::default()
         }
     }
 
@@ -702,6 +1395,219 @@ mod tests {
                 gd.set_selector(0xFFFF);
                 assert_eq!(gd.selector(), 0xFFFF);
             },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_gate_descriptor_builders() -> TestCase {
+        TestCase {
+            name: "Test GateDescriptor interrupt/trap defaults",
+            test: || {
+                let gd = GateDescriptor::interrupt(0x0123456789ABCDEF);
+                assert_eq!(gd.offset(), 0x0123456789ABCDEF);
+                assert_eq!(gd.selector(), KERNEL_CODE_SELECTOR);
+                assert_eq!(gd.dpl(), Dpl::Ring0);
+                assert_eq!(gd.gate_type(), GateType::Interrupt);
+                assert_eq!(gd.ist(), 0);
+                assert!(gd.p());
+
+                let gd = GateDescriptor::trap(0x1000)
+                    .with_ist(2)
+                    .with_dpl(Dpl::Ring3);
+                assert_eq!(gd.offset(), 0x1000);
+                assert_eq!(gd.selector(), KERNEL_CODE_SELECTOR);
+                assert_eq!(gd.dpl(), Dpl::Ring3);
+                assert_eq!(gd.gate_type(), GateType::Trap);
+                assert_eq!(gd.ist(), 2);
+                assert!(gd.p());
+
+                // Same as the raw constructor.
+                let raw = GateDescriptor::new(0x1000, 0x08, Dpl::Ring0, GateType::Trap);
+                let gd = GateDescriptor::trap(0x1000);
+                assert_eq!((gd.0, gd.1), (raw.0, raw.1));
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_gate_descriptor_const() -> TestCase {
+        TestCase {
+            name: "Test const-built GateDescriptors match the setters",
+            test: || {
+                const GD: GateDescriptor = GateDescriptor::new(
+                    0xFFFF_8000_DEAD_BEEF,
+                    KERNEL_CODE_SELECTOR,
+                    Dpl::Ring3,
+                    GateType::Trap,
+                )
+                .with_ist(5);
+
+                let mut gd = GateDescriptor(0, 0);
+                gd.set_offset(0xFFFF_8000_DEAD_BEEF);
+                gd.set_selector(KERNEL_CODE_SELECTOR);
+                gd.set_dpl(Dpl::Ring3);
+                gd.set_gate_type(GateType::Trap);
+                gd.set_p(true);
+                gd.set_ist(5);
+                assert_eq!(GD, gd);
+
+                // Overwriting the IST and DPL does not touch the other fields.
+                let gd = GD.with_ist(1).with_dpl(Dpl::Ring0);
+                assert_eq!(gd.ist(), 1);
+                assert_eq!(gd.dpl(), Dpl::Ring0);
+                assert_eq!(gd.offset(), 0xFFFF_8000_DEAD_BEEF);
+                assert_eq!(gd.gate_type(), GateType::Trap);
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Checks that what is written is the expected string, since we can't allocate a `String`.
+    struct Expect(&'static str);
+
+    impl fmt::Write for Expect {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 = self.0.strip_prefix(s).ok_or(fmt::Error)?;
+
+            Ok(())
+        }
+    }
+
+    #[test_case]
+    fn test_descriptor_tables_display() -> TestCase {
+        TestCase {
+            name: "Test formatting the GDT and IDT descriptors",
+            test: || {
+                use core::fmt::Write;
+
+                // NOTE: Without `{:#}`, the tables themselves are not read.
+                let gdtr = Gdtr {
+                    limit: 23,
+                    base: 0x1000,
+                };
+                let mut expect = Expect(
+                    "GDT: limit = 23 + 1 bytes, base = 0x1000\nNumber of entries in the GDT: 3\n",
+                );
+                write!(expect, "{}", gdtr).unwrap();
+                assert_eq!(expect.0, "");
+
+                let idtr = Idtr {
+                    limit: 4095,
+                    base: 0x2000,
+                };
+                let mut expect = Expect(
+                    "IDT: limit = 4095 + 1 bytes, base = 0x2000\nNumber of entries in the IDT: 256\n",
+                );
+                write!(expect, "{}", idtr).unwrap();
+                assert_eq!(expect.0, "");
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_selector_decode() -> TestCase {
+        TestCase {
+            name: "Test decoding segment selectors",
+            test: || {
+                let code = Selector(0x08);
+                assert_eq!((code.index(), code.ti(), code.rpl()), (1, false, 0));
+                assert_eq!(code.name(), Some("kernel code"));
+
+                let data = Selector(0x10);
+                assert_eq!((data.index(), data.ti(), data.rpl()), (2, false, 0));
+                assert_eq!(data.name(), Some("kernel data"));
+
+                // Same segment, requested from ring 3.
+                let user = Selector(0x13);
+                assert_eq!((user.index(), user.ti(), user.rpl()), (2, false, 3));
+                assert_eq!(user.name(), Some("kernel data"));
+
+                // Nothing of ours is in the LDT.
+                let ldt = Selector(0x0C);
+                assert_eq!((ldt.index(), ldt.ti(), ldt.rpl()), (1, true, 0));
+                assert_eq!(ldt.name(), None);
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Number of times `test_handler()` ran.
+    static HANDLER_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    extern "x86-interrupt" fn test_handler(_frame: InterruptStackFrame) {
+        HANDLER_CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test_case]
+    fn test_set_handler() -> TestCase {
+        TestCase {
+            name: "Test installing a handler and triggering it with int",
+            test: || {
+                const VECTOR: u8 = 0x80;
+                let entry = unsafe {
+                    &raw mut (*INTERRUPT_DESCRIPTOR_TABLE.handlers.get())[VECTOR as usize]
+                };
+                let saved = unsafe { *entry };
+
+                set_handler(VECTOR, test_handler, GateType::Trap);
+                let gate = unsafe { *entry };
+                assert_eq!(gate.offset(), test_handler as Trampoline as u64);
+                assert_eq!(gate.selector(), KERNEL_CODE_SELECTOR);
+                assert_eq!(gate.gate_type(), GateType::Trap);
+
+                // NOTE: `interrupts::init()` already loaded our IDT before the tests.
+                raise(VECTOR);
+                raise(VECTOR);
+                assert_eq!(HANDLER_CALLS.load(Ordering::Relaxed), 2);
+
+                unsafe { *entry = saved };
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_unhandled_trampolines() -> TestCase {
+        TestCase {
+            name: "Test each vector gets its own trampoline",
+            test: || {
+                for vector in 0..=255u8 {
+                    let addr = trampoline(vector);
+                    assert_ne!(addr, 0);
+                    if vector > 0 {
+                        assert_ne!(addr, trampoline(vector - 1), "vector {}", vector);
+                    }
+                }
+
+                assert_eq!(trampoline(0x42), unhandled_trampoline::<0x42> as u64);
+                assert_eq!(trampoline(13), unhandled_error_trampoline::<13> as u64);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_raise_unsupported() -> TestCase {
+        TestCase {
+            name: "Test raising an unsupported vector panics",
+            test: || raise(0x42),
+            should_panic: true,
+            ..Default::default()
+        }
+    }
+
+    // NOTE: The check is a `debug_assert!`.
+    #[cfg(debug_assertions)]
+    #[test_case]
+    fn test_gate_descriptor_ist_out_of_range() -> TestCase {
+        TestCase {
+            name: "Test setting an IST index out of range panics",
+            test: || GateDescriptor(0, 0).set_ist(8),
+            should_panic: true,
+            ..Default::default()
         }
     }
 }
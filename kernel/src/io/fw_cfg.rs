@@ -0,0 +1,93 @@
+//! QEMU's Firmware Configuration (fw_cfg) device.
+//!
+//! This lets the host pass data to the kernel at runtime, e.g. with:
+//! `-fw_cfg name=opt/rust-os/test-filter,string=GateDescriptor`
+//!
+//! NOTE: We only use the legacy I/O port interface (no DMA).
+
+use super::{inb, outw};
+
+/// Port used to select the item to read.
+const SELECTOR_PORT: u16 = 0x510;
+/// Port used to read the selected item, one byte at a time.
+const DATA_PORT: u16 = 0x511;
+
+const SIGNATURE_KEY: u16 = 0x0000;
+const FILE_DIR_KEY: u16 = 0x0019;
+
+/// Length of the name field of a file directory entry.
+const FILE_NAME_LEN: usize = 56;
+
+/// Selects an item and resets the read offset to 0.
+fn select(key: u16) {
+    unsafe { outw(SELECTOR_PORT, key) }
+}
+
+/// Reads bytes from the currently selected item.
+fn read(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        *b = unsafe { inb(DATA_PORT) };
+    }
+}
+
+/// NOTE: Everything in fw_cfg is big endian.
+fn read_u32() -> u32 {
+    let mut buf = [0; 4];
+    read(&mut buf);
+
+    u32::from_be_bytes(buf)
+}
+
+fn read_u16() -> u16 {
+    let mut buf = [0; 2];
+    read(&mut buf);
+
+    u16::from_be_bytes(buf)
+}
+
+/// Checks that the fw_cfg device is present.
+pub fn is_present() -> bool {
+    let mut signature = [0; 4];
+    select(SIGNATURE_KEY);
+    read(&mut signature);
+
+    &signature == b"QEMU"
+}
+
+/// Reads the file `name` into `buf`.
+///
+/// Returns the number of bytes read, or `None` if there is no such file. If `buf` is too small,
+/// the file is truncated.
+pub fn read_file(name: &str, buf: &mut [u8]) -> Option<usize> {
+    if !is_present() {
+        return None;
+    }
+
+    select(FILE_DIR_KEY);
+    let count = read_u32();
+
+    for _ in 0..count {
+        let size = read_u32() as usize;
+        let key = read_u16();
+        let _reserved = read_u16();
+        let mut file_name = [0; FILE_NAME_LEN];
+        read(&mut file_name);
+
+        // The name is NUL-terminated.
+        let len = file_name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(FILE_NAME_LEN);
+        if &file_name[..len] != name.as_bytes() {
+            continue;
+        }
+
+        let len = size.min(buf.len());
+        select(key);
+        read(&mut buf[..len]);
+
+        return Some(len);
+    }
+
+    None
+}
@@ -0,0 +1,402 @@
+//! PS/2 keyboard, with a US layout.
+//!
+//! The IRQ1 handler only queues the raw scancodes (set 1, which the controller translates to by
+//! default), they are decoded by `read_char()` on the main thread.
+//!
+//! NOTE: Only the keys producing characters, the modifiers and the locks are handled. Extended
+//! keys (arrows, home, end...) are ignored for now.
+
+use core::{
+    cell::UnsafeCell,
+    ops::BitOr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{inb, outb, pic};
+use crate::utils::{bits::GetBit, ringbuf::RingBuffer};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+
+const IRQ: u8 = 1;
+
+/// Sent by the keyboard before the scancode of an extended key.
+const EXTENDED_PREFIX: u8 = 0xE0;
+/// Set in the scancode of a released key.
+const RELEASED: u8 = 0x80;
+/// Replies of the keyboard to our commands.
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+/// Followed by the new state of the LEDs.
+const SET_LEDS: u8 = 0xED;
+
+const LEFT_SHIFT: u8 = 0x2A;
+const RIGHT_SHIFT: u8 = 0x36;
+/// Also right ctrl and right alt, with the extended prefix.
+const CTRL: u8 = 0x1D;
+const ALT: u8 = 0x38;
+const CAPS_LOCK: u8 = 0x3A;
+const NUM_LOCK: u8 = 0x45;
+const SCROLL_LOCK: u8 = 0x46;
+
+/// Characters of the scancodes below `0x3A`, `0` when there is none.
+const UNSHIFTED: &[u8; 0x3A] =
+    b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+const SHIFTED: &[u8; 0x3A] =
+    b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+/// Characters of the keypad scancodes (`0x47` to `0x53`), when Num Lock is on.
+const KEYPAD: &[u8; 13] = b"789-456+1230.";
+
+/// Scancodes queued by the IRQ handler.
+static SCANCODES: RingBuffer<u8, 64> = RingBuffer::new();
+/// Scancodes dropped because `SCANCODES` was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// State of the modifier keys and of the lock toggles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const SHIFT: Self = Self(1 << 0);
+    pub const CTRL: Self = Self(1 << 1);
+    pub const ALT: Self = Self(1 << 2);
+    pub const CAPS_LOCK: Self = Self(1 << 3);
+    pub const NUM_LOCK: Self = Self(1 << 4);
+    pub const SCROLL_LOCK: Self = Self(1 << 5);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Whether all the modifiers of `other` are set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+
+    fn toggle(&mut self, other: Self) {
+        self.0 ^= other.0;
+    }
+
+    /// The byte sent after `SET_LEDS`.
+    fn leds(self) -> u8 {
+        let mut leds = 0;
+        if self.contains(Self::SCROLL_LOCK) {
+            leds |= 1 << 0;
+        }
+        if self.contains(Self::NUM_LOCK) {
+            leds |= 1 << 1;
+        }
+        if self.contains(Self::CAPS_LOCK) {
+            leds |= 1 << 2;
+        }
+
+        leds
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// What a scancode did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+    None,
+    Char(char),
+    /// A lock was toggled, the LEDs need to be updated.
+    LocksChanged,
+}
+
+/// Scancode decoder.
+struct Keyboard {
+    modifiers: Modifiers,
+    /// The previous scancode was `EXTENDED_PREFIX`.
+    extended: bool,
+}
+
+impl Keyboard {
+    const fn new() -> Self {
+        Self {
+            modifiers: Modifiers::empty(),
+            extended: false,
+        }
+    }
+
+    fn process(&mut self, scancode: u8) -> Event {
+        if scancode == EXTENDED_PREFIX {
+            self.extended = true;
+            return Event::None;
+        }
+        let extended = core::mem::take(&mut self.extended);
+
+        let pressed = !scancode.get_bit(7);
+        let code = scancode & !RELEASED;
+
+        match code {
+            LEFT_SHIFT | RIGHT_SHIFT if !extended => self.modifiers.set(Modifiers::SHIFT, pressed),
+            CTRL => self.modifiers.set(Modifiers::CTRL, pressed),
+            ALT => self.modifiers.set(Modifiers::ALT, pressed),
+            _ if !pressed || extended => {}
+            CAPS_LOCK => {
+                self.modifiers.toggle(Modifiers::CAPS_LOCK);
+                return Event::LocksChanged;
+            }
+            NUM_LOCK => {
+                self.modifiers.toggle(Modifiers::NUM_LOCK);
+                return Event::LocksChanged;
+            }
+            SCROLL_LOCK => {
+                self.modifiers.toggle(Modifiers::SCROLL_LOCK);
+                return Event::LocksChanged;
+            }
+            0x47..=0x53 if self.modifiers.contains(Modifiers::NUM_LOCK) => {
+                return Event::Char(KEYPAD[(code - 0x47) as usize] as char);
+            }
+            _ => return self.translate(code).map_or(Event::None, Event::Char),
+        }
+
+        Event::None
+    }
+
+    /// Character produced by pressing the (non-extended) key `code`, given the modifiers.
+    fn translate(&self, code: u8) -> Option<char> {
+        let c = *UNSHIFTED.get(code as usize)?;
+        if c == 0 {
+            return None;
+        }
+
+        let shift = self.modifiers.contains(Modifiers::SHIFT);
+        if c.is_ascii_lowercase() {
+            if self.modifiers.contains(Modifiers::CTRL) {
+                // Ctrl+A is 0x01, Ctrl+B is 0x02...
+                return Some((c - b'a' + 1) as char);
+            }
+
+            // Caps Lock only affects letters, and Shift reverts it.
+            let upper = shift != self.modifiers.contains(Modifiers::CAPS_LOCK);
+            return Some(if upper { c.to_ascii_uppercase() } else { c } as char);
+        }
+
+        Some(if shift { SHIFTED[code as usize] } else { c } as char)
+    }
+}
+
+struct KeyboardHolder(UnsafeCell<Keyboard>);
+// Safety: Only used from the main thread, the IRQ handler only touches `SCANCODES`.
+unsafe impl Sync for KeyboardHolder {}
+
+static KEYBOARD: KeyboardHolder = KeyboardHolder(UnsafeCell::new(Keyboard::new()));
+
+/// Unmasks the keyboard IRQ.
+///
+/// NOTE: The IDT must have a handler calling `handle_irq()` by then.
+pub fn init() {
+    set_leds(Modifiers::empty());
+    pic::unmask(IRQ);
+}
+
+/// Called by the IRQ1 handler.
+///
+/// Reads every byte the controller has buffered, not just one, so that none is lost when they
+/// come in faster than we handle the IRQs.
+///
+/// NOTE: When the queue is full, the newest scancodes are dropped: only `read_char()` may pop
+/// from it (see `RingBuffer`), and keeping the oldest keeps the ones typed first in order.
+pub fn handle_irq() {
+    unsafe {
+        // Bit 0: the output buffer is full. Bit 5: the byte comes from the mouse, leave it to
+        // its own IRQ.
+        while inb(STATUS_PORT).get_bit(0) && !inb(STATUS_PORT).get_bit(5) {
+            let scancode = inb(DATA_PORT);
+            if !SCANCODES.push(scancode) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pic::end_of_interrupt(IRQ);
+}
+
+/// Number of scancodes dropped so far, because they were not read fast enough.
+#[allow(unused)]
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Returns the next character typed, if any.
+///
+/// Control characters are returned for Ctrl+letter, e.g. `'\x03'` for Ctrl+C.
+pub fn read_char() -> Option<char> {
+    // Safety: See `KeyboardHolder`.
+    let keyboard = unsafe { &mut *KEYBOARD.0.get() };
+
+    while let Some(scancode) = SCANCODES.pop() {
+        if matches!(scancode, ACK | RESEND) {
+            continue;
+        }
+
+        match keyboard.process(scancode) {
+            Event::None => {}
+            Event::Char(c) => return Some(c),
+            Event::LocksChanged => set_leds(keyboard.modifiers),
+        }
+    }
+
+    None
+}
+
+/// Current state of the modifiers, as of the last `read_char()`.
+#[allow(unused)]
+pub fn modifiers() -> Modifiers {
+    unsafe { (*KEYBOARD.0.get()).modifiers }
+}
+
+/// Sends a byte to the keyboard, once the controller is ready for it.
+fn send(byte: u8) {
+    unsafe {
+        while inb(STATUS_PORT).get_bit(1) {
+            core::hint::spin_loop();
+        }
+
+        outb(DATA_PORT, byte);
+    }
+}
+
+fn set_leds(modifiers: Modifiers) {
+    // NOTE: The `ACK`s sent back are skipped by `read_char()`.
+    send(SET_LEDS);
+    send(modifiers.leds());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    /// Feeds `scancodes` to `keyboard` and writes the characters produced to `out`.
+    fn type_scancodes(keyboard: &mut Keyboard, scancodes: &[u8], out: &mut [u8]) -> usize {
+        let mut len = 0;
+        for &scancode in scancodes {
+            if let Event::Char(c) = keyboard.process(scancode) {
+                out[len] = c as u8;
+                len += 1;
+            }
+        }
+
+        len
+    }
+
+    #[test_case]
+    fn test_keyboard_modifiers() -> TestCase {
+        TestCase {
+            name: "Test keyboard Shift, Caps Lock and Ctrl handling",
+            test: || {
+                let mut keyboard = Keyboard::new();
+                let mut out = [0; 16];
+
+                // a, Shift+a, Shift+1, release Shift, 1.
+                let len = type_scancodes(
+                    &mut keyboard,
+                    &[0x1E, 0x9E, 0x2A, 0x1E, 0x02, 0xAA, 0x02],
+                    &mut out,
+                );
+                assert_eq!(&out[..len], b"aA!1");
+
+                // Caps Lock only affects letters, and Shift reverts it.
+                assert_eq!(keyboard.process(CAPS_LOCK), Event::LocksChanged);
+                assert!(keyboard.modifiers.contains(Modifiers::CAPS_LOCK));
+                assert_eq!(keyboard.modifiers.leds(), 1 << 2);
+                let len = type_scancodes(
+                    &mut keyboard,
+                    &[0x1E, 0x02, 0x36, 0x1E, 0x02, 0xB6],
+                    &mut out,
+                );
+                assert_eq!(&out[..len], b"A1a!");
+                keyboard.process(CAPS_LOCK);
+                keyboard.process(CAPS_LOCK | RELEASED);
+                assert!(!keyboard.modifiers.contains(Modifiers::CAPS_LOCK));
+
+                // Ctrl+C, with the right ctrl (extended).
+                let len = type_scancodes(
+                    &mut keyboard,
+                    &[
+                        EXTENDED_PREFIX,
+                        CTRL,
+                        0x2E,
+                        EXTENDED_PREFIX,
+                        CTRL | RELEASED,
+                        0x2E,
+                    ],
+                    &mut out,
+                );
+                assert_eq!(&out[..len], b"\x03c");
+                assert_eq!(keyboard.modifiers, Modifiers::empty());
+
+                // The keypad only types with Num Lock.
+                assert_eq!(keyboard.process(0x47), Event::None);
+                keyboard.process(NUM_LOCK);
+                assert_eq!(keyboard.process(0x47), Event::Char('7'));
+                assert_eq!(keyboard.modifiers, Modifiers::NUM_LOCK);
+                assert!(!keyboard
+                    .modifiers
+                    .contains(Modifiers::NUM_LOCK | Modifiers::SCROLL_LOCK));
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_keyboard_stress() -> TestCase {
+        TestCase {
+            name: "Test decoding many queued scancodes",
+            test: || {
+                // q to p, the first row of letters.
+                const KEYS: &[u8] = b"qwertyuiop";
+
+                let queue: RingBuffer<u8, 64> = RingBuffer::new();
+                let mut keyboard = Keyboard::new();
+                let mut typed = 0;
+
+                let drain = |keyboard: &mut Keyboard, typed: &mut usize| {
+                    while let Some(scancode) = queue.pop() {
+                        if let Event::Char(c) = keyboard.process(scancode) {
+                            assert_eq!(c as u8, KEYS[*typed % KEYS.len()]);
+                            *typed += 1;
+                        }
+                    }
+                };
+
+                // Fill the queue with presses and releases before draining it, like when IRQs
+                // come in faster than `read_char()` runs.
+                for i in 0..1000 {
+                    let code = 0x10 + (i % KEYS.len()) as u8;
+                    let bytes = [code, code | RELEASED];
+                    if queue.len() + bytes.len() > queue.capacity() {
+                        drain(&mut keyboard, &mut typed);
+                    }
+
+                    for byte in bytes {
+                        assert!(queue.push(byte));
+                    }
+                }
+                drain(&mut keyboard, &mut typed);
+
+                assert_eq!(typed, 1000);
+                assert_eq!(keyboard.modifiers, Modifiers::empty());
+            },
+            ..Default::default()
+        }
+    }
+}
@@ -1,9 +1,84 @@
 use core::arch::asm;
 
+use crate::{interrupts::InterruptGuard, utils::sync::MutexGuard};
+
+// NOTE: The macros are defined before the submodules, so that those can use them too.
+/// Writes to the serial port only, skipped if it is not initialized.
+#[allow(unused_macros)]
+macro_rules! sprint {
+    ($($arg:tt)*) => {
+        unsafe {
+            use core::fmt::Write as FmtWrite;
+
+            if let Some(writer) = (*$crate::io::serial::SERIAL_WRITER.0.get()).as_mut() {
+                write!(writer, $($arg)*).expect("Failed to write in serial.");
+                writer.flush();
+            }
+        }
+    }
+}
+
+#[allow(unused_macros)]
+macro_rules! sprintln {
+    ($($arg:tt)*) => {
+        sprint!($($arg)*);
+        sprint!("\n");
+    }
+}
+
+/// Writes to the screen only, skipped if there is none (e.g. before `VGAWriter::init`).
+#[allow(unused_macros)]
+macro_rules! vprint {
+    ($($arg:tt)*) => {
+        unsafe {
+            use core::fmt::Write as FmtWrite;
+
+            // NOTE: Not `lock()`: if we panic while printing, the panic handler must still be able
+            // to print. Holding the lock only keeps interrupt handlers off the screen meanwhile.
+            let _screen = $crate::io::vga::SCREEN_LOCK.try_lock();
+            if let Some(writer) = (*$crate::io::vga::SCREEN_WRITER.0.get()).as_mut() {
+                write!(writer, $($arg)*).expect("Failed to write to VGA.");
+            }
+        }
+    }
+}
+
+#[allow(unused_macros)]
+macro_rules! vprintln {
+    ($($arg:tt)*) => {
+        vprint!($($arg)*);
+        vprint!("\n");
+    }
+}
+
+/// Writes to every initialized writer, the other ones are silently skipped (e.g. before
+/// `VGAWriter::init`).
+macro_rules! print {
+    ($($arg:tt)*) => {{
+        sprint!($($arg)*);
+        vprint!($($arg)*);
+    }}
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => {
+        print!($($arg)*);
+        print!("\n");
+    }
+}
+
+// NOTE: Only used to get the test filter for now.
+#[cfg(test)]
+pub mod fw_cfg;
+pub mod keyboard;
+pub mod mouse;
+pub mod pic;
+pub mod pit;
+pub mod rtc;
 pub mod serial;
 pub mod vga;
 
-unsafe fn inb(port: u16) -> u8 {
+pub unsafe fn inb(port: u16) -> u8 {
     let mut ret;
 
     asm!(
@@ -27,6 +102,45 @@ pub unsafe fn outb(port: u16, value: u8) {
     );
 }
 
+#[allow(unused)]
+pub unsafe fn outw(port: u16, value: u16) {
+    asm!(
+        r#"
+        out %ax, %dx
+        "#,
+        in("dx") port,
+        in("ax") value,
+        options(att_syntax)
+    );
+}
+
+/// Keeps the prints made while it lives in one piece, e.g. a multi-line dump is not interleaved
+/// with the output of an interrupt handler. See `log_block()`.
+pub struct LogBlock {
+    // NOTE: Fields are dropped in order: the lock is released before interrupts come back.
+    _screen: MutexGuard<'static, ()>,
+    _interrupts: InterruptGuard,
+}
+
+/// Disables interrupts and holds `vga::SCREEN_LOCK` until the returned guard is dropped, so that
+/// the prints in between come out atomically, unlike separate `println!`s.
+///
+/// NOTE: Keep the block short: meanwhile ticks are delayed, and keys or mouse packets may be lost
+/// once the controllers' buffers are full. The prints themselves still work (they don't wait for
+/// the lock), but `vga::with_screen()` spins on it forever.
+pub fn log_block() -> LogBlock {
+    let interrupts = InterruptGuard::new();
+
+    LogBlock {
+        _screen: vga::SCREEN_LOCK.lock(),
+        _interrupts: interrupts,
+    }
+}
+
+/// Exits QEMU with `code`, through its `isa-debug-exit` device. Returns if there is none.
+///
+/// NOTE: Only the tests and benchmarks exit, the kernel itself runs forever.
+#[allow(unused)]
 pub fn exit(code: u8) {
     serial::wait_until_done();
 
@@ -37,34 +151,29 @@ pub fn exit(code: u8) {
     }
 }
 
-macro_rules! print {
-    ($($arg:tt)*) => {
-        unsafe {
-            use core::fmt::Write as FmtWrite;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
 
-            let mut writer = match (*$crate::io::serial::SERIAL_WRITER.0.get()).as_mut()
+    #[test_case]
+    fn test_log_block() -> TestCase {
+        TestCase {
+            name: "Test log_block() holds the screen with interrupts disabled",
+            test: || {
                 {
-                Some(w) => w,
-                None => {
-                    panic!("Attempted to use SerialWriter before calling init.")
-                }
-            };
+                    let _log = log_block();
+                    assert!(!crate::interrupts::are_enabled());
+                    assert!(vga::SCREEN_LOCK.try_lock().is_none());
 
-            write!(&mut (writer), $($arg)*).expect("Failed to write in serial.");
-            let writer = match (*$crate::io::vga::SCREEN_WRITER.0.get()).as_mut() {
-                Some(w) => w,
-                None => {
-                    panic!("Attempted to use ScreenWriter before calling init.")
+                    // Printing must still work while the lock is held.
+                    println!("log_block: first line");
+                    println!("log_block: second line");
                 }
-            };
-            write!(&mut *(writer), $($arg)*).expect("Failed to write to VGA.");
-        }
-    }
-}
 
-macro_rules! println {
-    ($($arg:tt)*) => {
-        print!($($arg)*);
-        print!("\n");
+                assert!(vga::SCREEN_LOCK.try_lock().is_some());
+            },
+            ..Default::default()
+        }
     }
 }
@@ -0,0 +1,285 @@
+//! PS/2 mouse, on the auxiliary port of the keyboard controller.
+//!
+//! The IRQ12 handler assembles the 3 bytes movement packets and queues them, they are read with
+//! `poll()` on the main thread.
+//!
+//! NOTE: Only the standard 3 bytes packets are supported, e.g. no scroll wheel.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{inb, outb, pic};
+use crate::{
+    error::KernelError,
+    utils::{
+        bits::{GetBit, SetBit},
+        ringbuf::RingBuffer,
+    },
+};
+
+const DATA_PORT: u16 = 0x60;
+/// Status when read, command when written.
+const STATUS_PORT: u16 = 0x64;
+
+const IRQ: u8 = 12;
+
+/// Controller commands.
+const ENABLE_AUX: u8 = 0xA8;
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+/// The next byte written to `DATA_PORT` goes to the mouse.
+const WRITE_AUX: u8 = 0xD4;
+
+/// Mouse commands, and its reply.
+const SET_DEFAULTS: u8 = 0xF6;
+const ENABLE_REPORTING: u8 = 0xF4;
+const ACK: u8 = 0xFA;
+
+/// Number of status polls before giving up on the controller, see `wait()`.
+const TIMEOUT: usize = 100_000;
+
+/// Bits of the first byte of a packet.
+const LEFT_BUTTON: u32 = 0;
+const RIGHT_BUTTON: u32 = 1;
+const MIDDLE_BUTTON: u32 = 2;
+/// Always set, so that we can resynchronize on the first byte of a packet.
+const ALWAYS_ONE: u32 = 3;
+const X_SIGN: u32 = 4;
+const Y_SIGN: u32 = 5;
+const X_OVERFLOW: u32 = 6;
+const Y_OVERFLOW: u32 = 7;
+
+/// Packets queued by the IRQ handler.
+static PACKETS: RingBuffer<MouseState, 32> = RingBuffer::new();
+/// Packets dropped because `PACKETS` was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Movement since the previous packet, and the buttons held.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseState {
+    pub dx: i16,
+    /// NOTE: Positive is up, unlike screen coordinates.
+    pub dy: i16,
+
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// Assembles packets from the bytes sent by the mouse.
+struct PacketDecoder {
+    bytes: [u8; 3],
+    len: usize,
+}
+
+impl PacketDecoder {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; 3],
+            len: 0,
+        }
+    }
+
+    /// Returns the decoded packet once `byte` completes one.
+    fn push(&mut self, byte: u8) -> Option<MouseState> {
+        // We're out of sync (e.g. a byte was lost), skip bytes until one looks like a first one.
+        if self.len == 0 && !byte.get_bit(ALWAYS_ONE) {
+            return None;
+        }
+
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.bytes.len() {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, dx, dy] = self.bytes;
+        // The deltas are 9 bits two's complement, the sign being in `flags`. They are garbage on
+        // overflow, so they're dropped.
+        let delta = |value: u8, sign: u32, overflow: u32| match flags.get_bit(overflow) {
+            true => 0,
+            false if flags.get_bit(sign) => value as i16 - 0x100,
+            false => value as i16,
+        };
+
+        Some(MouseState {
+            dx: delta(dx, X_SIGN, X_OVERFLOW),
+            dy: delta(dy, Y_SIGN, Y_OVERFLOW),
+            left: flags.get_bit(LEFT_BUTTON),
+            right: flags.get_bit(RIGHT_BUTTON),
+            middle: flags.get_bit(MIDDLE_BUTTON),
+        })
+    }
+}
+
+struct DecoderHolder(UnsafeCell<PacketDecoder>);
+// Safety: Only used by the IRQ handler, which can't be interrupted by itself.
+unsafe impl Sync for DecoderHolder {}
+
+static DECODER: DecoderHolder = DecoderHolder(UnsafeCell::new(PacketDecoder::new()));
+
+/// Spins until the controller's status bit `bit` is `set`. Returns `false` on timeout.
+fn wait(bit: u32, set: bool) -> bool {
+    for _ in 0..TIMEOUT {
+        if unsafe { inb(STATUS_PORT) }.get_bit(bit) == set {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+
+    false
+}
+
+/// Sends a command to the controller, with its data byte if any.
+fn command(cmd: u8, data: Option<u8>) -> Result<(), &'static str> {
+    // Bit 1: the input buffer is full.
+    if !wait(1, false) {
+        return Err("Controller timeout");
+    }
+    unsafe { outb(STATUS_PORT, cmd) };
+
+    if let Some(data) = data {
+        if !wait(1, false) {
+            return Err("Controller timeout");
+        }
+        unsafe { outb(DATA_PORT, data) };
+    }
+
+    Ok(())
+}
+
+/// Reads the next byte from the controller.
+fn read() -> Result<u8, &'static str> {
+    // Bit 0: the output buffer is full.
+    if !wait(0, true) {
+        return Err("Controller timeout");
+    }
+
+    Ok(unsafe { inb(DATA_PORT) })
+}
+
+/// Sends a command to the mouse, and waits for it to acknowledge it.
+fn send(byte: u8) -> Result<(), &'static str> {
+    command(WRITE_AUX, Some(byte))?;
+
+    match read()? {
+        ACK => Ok(()),
+        _ => Err("The mouse did not acknowledge"),
+    }
+}
+
+/// Enables the mouse and its IRQ.
+///
+/// NOTE: The IDT must have a handler calling `handle_irq()` by then.
+pub fn init() -> Result<(), KernelError> {
+    // NOTE: The keyboard IRQ would otherwise steal the replies we wait for.
+    crate::interrupts::without_interrupts(|| {
+        command(ENABLE_AUX, None)?;
+
+        // Bit 1: IRQ12 enabled. Bit 5: mouse clock disabled.
+        command(READ_CONFIG, None)?;
+        let mut config = read()?;
+        config.set_bit(1, true);
+        config.set_bit(5, false);
+        command(WRITE_CONFIG, Some(config))?;
+
+        send(SET_DEFAULTS)?;
+        send(ENABLE_REPORTING)
+    })
+    .map_err(KernelError::Mouse)?;
+
+    pic::unmask(IRQ);
+
+    Ok(())
+}
+
+/// Called by the IRQ12 handler.
+pub fn handle_irq() {
+    // Safety: See `DecoderHolder`.
+    let decoder = unsafe { &mut *DECODER.0.get() };
+
+    unsafe {
+        // Bit 0: the output buffer is full. Bit 5: the byte comes from the mouse.
+        while inb(STATUS_PORT).get_bit(0) && inb(STATUS_PORT).get_bit(5) {
+            let Some(state) = decoder.push(inb(DATA_PORT)) else {
+                continue;
+            };
+
+            if !PACKETS.push(state) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pic::end_of_interrupt(IRQ);
+}
+
+/// Returns the oldest packet not read yet, if any.
+#[allow(unused)]
+pub fn poll() -> Option<MouseState> {
+    PACKETS.pop()
+}
+
+/// Number of packets dropped so far, because they were not read fast enough.
+#[allow(unused)]
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    /// Feeds a whole packet to `decoder`.
+    fn decode(decoder: &mut PacketDecoder, bytes: [u8; 3]) -> Option<MouseState> {
+        assert_eq!(decoder.push(bytes[0]), None);
+        assert_eq!(decoder.push(bytes[1]), None);
+        decoder.push(bytes[2])
+    }
+
+    #[test_case]
+    fn test_mouse_packets() -> TestCase {
+        TestCase {
+            name: "Test decoding mouse packets",
+            test: || {
+                let mut decoder = PacketDecoder::new();
+
+                // Left button, moving right and down.
+                assert_eq!(
+                    decode(&mut decoder, [0b0010_1001, 5, 0xFD]),
+                    Some(MouseState {
+                        dx: 5,
+                        dy: -3,
+                        left: true,
+                        ..Default::default()
+                    })
+                );
+
+                // Right and middle buttons, X overflowed.
+                assert_eq!(
+                    decode(&mut decoder, [0b0101_1110, 0xFF, 1]),
+                    Some(MouseState {
+                        dx: 0,
+                        dy: 1,
+                        right: true,
+                        middle: true,
+                        ..Default::default()
+                    })
+                );
+
+                // Out of sync: bytes without the always-one bit are skipped.
+                assert_eq!(decoder.push(0x00), None);
+                assert_eq!(decoder.push(0xF0), None);
+                assert_eq!(
+                    decode(&mut decoder, [0b0000_1000, 0, 0]),
+                    Some(MouseState::default())
+                );
+            },
+            ..Default::default()
+        }
+    }
+}
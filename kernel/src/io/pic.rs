@@ -0,0 +1,157 @@
+//! 8259 PIC (Programmable Interrupt Controller).
+//!
+//! The BIOS maps the PIC IRQs to vectors 0x08-0x0F and 0x70-0x77, which collide with CPU
+//! exceptions, so we remap them to `PIC1_OFFSET` and `PIC2_OFFSET`.
+//!
+//! `init()` masks every IRQ, the drivers then `unmask()` the ones they handle (e.g. the timer, the
+//! keyboard and the mouse). Spurious IRQ7/IRQ15 can still show up even while masked (e.g. on
+//! noise), they are handled by `handle_irq7()` and `handle_irq15()`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{inb, outb};
+use crate::utils::bits::GetBit;
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+/// Vector of IRQ0.
+pub const PIC1_OFFSET: u8 = 0x20;
+/// Vector of IRQ8.
+pub const PIC2_OFFSET: u8 = 0x28;
+
+/// Initialization, ICW4 will be sent.
+const ICW1_INIT: u8 = 0x11;
+/// 8086 mode.
+const ICW4_8086: u8 = 0x01;
+/// OCW3 to read the In-Service Register on the next read of the command port.
+const OCW3_READ_ISR: u8 = 0x0B;
+const END_OF_INTERRUPT: u8 = 0x20;
+
+/// What is usually wired to each IRQ line.
+const IRQ_NAMES: [&str; 16] = [
+    "timer", "keyboard", "cascade", "com2", "com1", "lpt2", "floppy", "lpt1", "rtc", "acpi",
+    "irq10", "irq11", "mouse", "fpu", "ata1", "ata2",
+];
+
+/// Number of spurious IRQs received so far.
+static SPURIOUS_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Gives the PIC some time to handle the previous command, by writing to an unused port.
+fn io_wait() {
+    unsafe { outb(0x80, 0) }
+}
+
+/// Remaps both PICs and masks all their IRQs.
+pub fn init() {
+    unsafe {
+        outb(PIC1_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(PIC2_COMMAND, ICW1_INIT);
+        io_wait();
+
+        // ICW2: vector offsets.
+        outb(PIC1_DATA, PIC1_OFFSET);
+        io_wait();
+        outb(PIC2_DATA, PIC2_OFFSET);
+        io_wait();
+
+        // ICW3: the slave is on the master's IRQ2.
+        outb(PIC1_DATA, 1 << 2);
+        io_wait();
+        outb(PIC2_DATA, 2);
+        io_wait();
+
+        outb(PIC1_DATA, ICW4_8086);
+        io_wait();
+        outb(PIC2_DATA, ICW4_8086);
+        io_wait();
+
+        // Mask everything.
+        outb(PIC1_DATA, 0xFF);
+        outb(PIC2_DATA, 0xFF);
+    }
+}
+
+/// Lets the PICs send `irq`.
+pub fn unmask(irq: u8) {
+    let (port, bit) = if irq < 8 {
+        (PIC1_DATA, irq)
+    } else {
+        (PIC2_DATA, irq - 8)
+    };
+
+    unsafe {
+        let mask = inb(port) & !(1 << bit);
+        outb(port, mask);
+
+        // The slave's IRQs go through the master's IRQ2.
+        if irq >= 8 {
+            outb(PIC1_DATA, inb(PIC1_DATA) & !(1 << 2));
+        }
+    }
+}
+
+/// Prints the Interrupt Mask Register of both PICs, one line each: masked IRQs are prefixed with
+/// `-`, unmasked ones with `+`.
+pub fn print_masks() {
+    for (pic, port) in [PIC1_DATA, PIC2_DATA].into_iter().enumerate() {
+        let mask = unsafe { inb(port) };
+
+        print!("PIC{} (mask {:#04x}):", pic + 1, mask);
+        for (bit, name) in (0..).zip(&IRQ_NAMES[pic * 8..][..8]) {
+            print!(" {}{}", if mask.get_bit(bit) { '-' } else { '+' }, name);
+        }
+        print!("\n");
+    }
+}
+
+/// Reads the In-Service Registers of both PICs: bit `n` is set if IRQ `n` is being serviced.
+fn read_isr() -> u16 {
+    unsafe {
+        outb(PIC1_COMMAND, OCW3_READ_ISR);
+        outb(PIC2_COMMAND, OCW3_READ_ISR);
+
+        ((inb(PIC2_COMMAND) as u16) << 8) | inb(PIC1_COMMAND) as u16
+    }
+}
+
+/// Acknowledges `irq`, so that the PICs can send the next one.
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(PIC2_COMMAND, END_OF_INTERRUPT);
+        }
+        outb(PIC1_COMMAND, END_OF_INTERRUPT);
+    }
+}
+
+/// Handles IRQ7. If it's spurious (not in service), no EOI must be sent.
+pub fn handle_irq7() {
+    if !read_isr().get_bit(7) {
+        SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    end_of_interrupt(7);
+}
+
+/// Handles IRQ15. If it's spurious, the master still expects an EOI (it did see an IRQ2), but
+/// not the slave.
+pub fn handle_irq15() {
+    if !read_isr().get_bit(15) {
+        SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
+        end_of_interrupt(2);
+        return;
+    }
+
+    end_of_interrupt(15);
+}
+
+/// Number of spurious IRQs received so far.
+#[allow(unused)]
+pub fn spurious_count() -> u64 {
+    SPURIOUS_COUNT.load(Ordering::Relaxed)
+}
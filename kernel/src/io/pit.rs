@@ -0,0 +1,53 @@
+//! 8253/8254 PIT (Programmable Interval Timer).
+//!
+//! Channel 0 is the periodic timer raising IRQ0, see `time::start_timer()`.
+//!
+//! Channel 2 is a one-shot timer polled through the keyboard controller's port B. Unlike channel
+//! 0, it does not raise any IRQ, so it works with interrupts disabled.
+
+use super::{inb, outb};
+use crate::utils::bits::GetBit;
+
+/// Frequency at which the PIT counters decrement.
+pub const FREQUENCY_HZ: u64 = 1_193_182;
+
+const CHANNEL0_DATA: u16 = 0x40;
+const CHANNEL2_DATA: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Keyboard controller port B: bit 0 gates channel 2, bit 1 enables the speaker and bit 5 is
+/// channel 2's output.
+const PORT_B: u16 = 0x61;
+
+/// Channel 0, low then high byte access, mode 2 (rate generator), binary.
+const CHANNEL0_PERIODIC: u8 = 0x34;
+/// Channel 2, low then high byte access, mode 0 (interrupt on terminal count), binary.
+const CHANNEL2_ONE_SHOT: u8 = 0xB0;
+
+/// Makes channel 0 raise IRQ0 `hz` times per second.
+///
+/// NOTE: The actual frequency is rounded, and can't go below ~19 Hz.
+pub fn start_periodic(hz: u64) {
+    let divisor = (FREQUENCY_HZ / hz.max(1)).clamp(1, u16::MAX as u64) as u16;
+
+    unsafe {
+        outb(COMMAND, CHANNEL0_PERIODIC);
+        outb(CHANNEL0_DATA, divisor as u8);
+        outb(CHANNEL0_DATA, (divisor >> 8) as u8);
+    }
+}
+
+/// Busy-waits for `ticks` PIT ticks (of `1 / FREQUENCY_HZ` seconds each).
+pub fn wait_ticks(ticks: u16) {
+    unsafe {
+        // Gate channel 2 on, but keep the speaker quiet.
+        let port_b = inb(PORT_B);
+        outb(PORT_B, (port_b & !0x02) | 0x01);
+
+        outb(COMMAND, CHANNEL2_ONE_SHOT);
+        outb(CHANNEL2_DATA, ticks as u8);
+        outb(CHANNEL2_DATA, (ticks >> 8) as u8);
+
+        // The output goes high once the counter reaches 0.
+        while !inb(PORT_B).get_bit(5) {}
+    }
+}
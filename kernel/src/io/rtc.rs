@@ -0,0 +1,218 @@
+//! CMOS Real-Time Clock (RTC).
+//!
+//! NOTE: The CMOS has no standard century register, so we assume we're in the 2000s.
+
+use core::fmt;
+
+use super::{inb, outb};
+use crate::utils::bits::GetBit;
+
+/// Port used to select the CMOS register to read.
+const INDEX_PORT: u16 = 0x70;
+/// Port used to read the selected register.
+const DATA_PORT: u16 = 0x71;
+
+const SECONDS_REG: u8 = 0x00;
+const MINUTES_REG: u8 = 0x02;
+const HOURS_REG: u8 = 0x04;
+const DAY_REG: u8 = 0x07;
+const MONTH_REG: u8 = 0x08;
+const YEAR_REG: u8 = 0x09;
+const STATUS_A_REG: u8 = 0x0A;
+const STATUS_B_REG: u8 = 0x0B;
+
+/// Number of panics so far, see `record_panic()`.
+///
+/// NOTE: Registers past `STATUS_B_REG` are firmware specific. This one is outside the range the
+/// standard checksum covers (0x10 to 0x2D) and unused by QEMU's firmware, other firmwares may use
+/// it though.
+const PANIC_COUNT_REG: u8 = 0x7E;
+
+/// Set in the hours register for PM times, when in 12-hour mode.
+const PM_BIT: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+fn read_register(reg: u8) -> u8 {
+    unsafe {
+        outb(INDEX_PORT, reg);
+        inb(DATA_PORT)
+    }
+}
+
+fn write_register(reg: u8, value: u8) {
+    unsafe {
+        outb(INDEX_PORT, reg);
+        outb(DATA_PORT, value);
+    }
+}
+
+/// Number of panics recorded by `record_panic()`, e.g. to tell how many times a soak test
+/// rebooted.
+///
+/// NOTE: It lives in the CMOS rather than in RAM, which the firmware and the bootloader are free to
+/// reuse on reset. On real hardware, it even survives power cycles (QEMU starts from a fresh CMOS).
+pub fn panic_count() -> u8 {
+    read_register(PANIC_COUNT_REG)
+}
+
+/// Increments the panic counter (wrapping at 255), and returns its new value.
+pub fn record_panic() -> u8 {
+    let count = panic_count().wrapping_add(1);
+    write_register(PANIC_COUNT_REG, count);
+
+    count
+}
+
+/// The RTC is updating its registers, reading them now could give garbage.
+fn update_in_progress() -> bool {
+    read_register(STATUS_A_REG).get_bit(7)
+}
+
+/// Reads the raw date and time registers, as they are stored in the CMOS.
+fn read_raw() -> DateTime {
+    while update_in_progress() {}
+
+    DateTime {
+        year: read_register(YEAR_REG) as u16,
+        month: read_register(MONTH_REG),
+        day: read_register(DAY_REG),
+        hours: read_register(HOURS_REG),
+        minutes: read_register(MINUTES_REG),
+        seconds: read_register(SECONDS_REG),
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Converts raw registers to a binary, 24-hour `DateTime`, according to status register B.
+fn decode(raw: DateTime, status_b: u8) -> DateTime {
+    let is_binary = status_b.get_bit(2);
+    let is_24_hour = status_b.get_bit(1);
+
+    let convert = |value: u8| {
+        if is_binary {
+            value
+        } else {
+            bcd_to_binary(value)
+        }
+    };
+
+    // NOTE: The PM bit has to be removed before converting from BCD.
+    let is_pm = raw.hours & PM_BIT != 0;
+    let mut hours = convert(raw.hours & !PM_BIT);
+    if !is_24_hour {
+        // 12 AM is midnight and 12 PM is noon.
+        hours %= 12;
+        if is_pm {
+            hours += 12;
+        }
+    }
+
+    DateTime {
+        year: 2000 + convert(raw.year as u8) as u16,
+        month: convert(raw.month),
+        day: convert(raw.day),
+        hours,
+        minutes: convert(raw.minutes),
+        seconds: convert(raw.seconds),
+    }
+}
+
+/// Reads the current date and time from the RTC.
+///
+/// NOTE: An update can still start while we read the registers, so we read them until we get the
+/// same values twice in a row.
+pub fn read_datetime() -> DateTime {
+    let mut last = read_raw();
+    loop {
+        let current = read_raw();
+        if current == last {
+            break;
+        }
+        last = current;
+    }
+
+    decode(last, read_register(STATUS_B_REG))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_rtc_decode() -> TestCase {
+        TestCase {
+            name: "Test decoding BCD and 12-hour RTC registers",
+            test: || {
+                let raw = DateTime {
+                    year: 0x24,
+                    month: 0x12,
+                    day: 0x31,
+                    hours: PM_BIT | 0x11,
+                    minutes: 0x59,
+                    seconds: 0x07,
+                };
+                let decoded = decode(raw, 0);
+                assert_eq!(decoded.year, 2024);
+                assert_eq!(decoded.month, 12);
+                assert_eq!(decoded.day, 31);
+                assert_eq!(decoded.hours, 23);
+                assert_eq!(decoded.minutes, 59);
+                assert_eq!(decoded.seconds, 7);
+
+                // 12 AM is midnight.
+                let midnight = decode(DateTime { hours: 0x12, ..raw }, 0);
+                assert_eq!(midnight.hours, 0);
+
+                // Binary and 24-hour mode.
+                let raw = DateTime {
+                    year: 24,
+                    month: 12,
+                    day: 31,
+                    hours: 23,
+                    minutes: 59,
+                    seconds: 7,
+                };
+                assert_eq!(decode(raw, 0b110), decoded);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_panic_count() -> TestCase {
+        TestCase {
+            name: "Test the panic counter survives in the CMOS",
+            test: || {
+                let before = panic_count();
+                assert_eq!(record_panic(), before.wrapping_add(1));
+                assert_eq!(panic_count(), before.wrapping_add(1));
+
+                write_register(PANIC_COUNT_REG, before);
+            },
+            ..Default::default()
+        }
+    }
+}
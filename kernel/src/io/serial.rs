@@ -1,8 +1,19 @@
 use core::{cell::UnsafeCell, fmt::Write};
 
 use super::{inb, outb};
+use crate::error::KernelError;
 
-pub struct SerialWriter;
+pub struct SerialWriter {
+    /// Sends `\r\n` instead of a bare `\n`, which is what most terminals expect.
+    translate_newlines: bool,
+
+    /// Last byte sent, so that an existing `\r\n` is not turned into `\r\r\n`.
+    last_byte: u8,
+
+    /// Bytes staged by `write_str()`, sent in one burst by `flush()`.
+    buf: [u8; FIFO_SIZE],
+    len: usize,
+}
 pub struct SerialWriterHolder(pub UnsafeCell<Option<SerialWriter>>);
 
 unsafe impl Sync for SerialWriterHolder {}
@@ -11,29 +22,55 @@ pub static SERIAL_WRITER: SerialWriterHolder = SerialWriterHolder(UnsafeCell::ne
 /// COM1 serial port within Qemu.
 const PORT: u16 = 0x3f8;
 
+/// Size of the transmit FIFO of the 16550 UART.
+const FIFO_SIZE: usize = 16;
+
+/// Sends whatever is staged and waits until it is all out.
 pub fn wait_until_done() {
-    unsafe { while !is_transmit_empty() {} }
+    unsafe {
+        if let Some(writer) = (*SERIAL_WRITER.0.get()).as_mut() {
+            writer.flush();
+        }
+
+        while !is_transmit_empty() {}
+    }
 }
 
-/// Checks if there is already something being transmitted.
+/// Checks if the transmit FIFO is empty, e.g. whether `FIFO_SIZE` bytes can be sent at once.
 unsafe fn is_transmit_empty() -> bool {
     (inb(PORT + 5) & 0x20) != 0
 }
 
-/// Writes a single byte on the serial port.
-unsafe fn write_byte(b: u8) {
-    while !is_transmit_empty() {}
-
-    outb(PORT, b);
+/// Checks if a received byte is waiting to be read.
+unsafe fn is_data_ready() -> bool {
+    (inb(PORT + 5) & 0x01) != 0
 }
 
-#[derive(Debug)]
-pub enum SerialError {
-    InitFailed,
+/// Reads a received byte, if any.
+///
+/// NOTE: This polls the port, serial interrupts are not used. Always `None` if `init_serial()`
+/// failed: a missing chip reads as all ones, e.g. as an endless stream of `0xFF`.
+pub fn read_byte() -> Option<u8> {
+    unsafe {
+        if (*SERIAL_WRITER.0.get()).is_none() || !is_data_ready() {
+            return None;
+        }
+
+        Some(inb(PORT))
+    }
 }
 
 impl SerialWriter {
-    pub fn init_serial() -> Result<(), SerialError> {
+    const fn new() -> Self {
+        Self {
+            translate_newlines: true,
+            last_byte: 0,
+            buf: [0; FIFO_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn init_serial() -> Result<(), KernelError> {
         unsafe {
             outb(PORT + 1, 0x00); // Disable all interrupts
             outb(PORT + 3, 0x80); // Enable DLAB (set baud rate divisor)
@@ -45,31 +82,124 @@ impl SerialWriter {
             outb(PORT + 4, 0x1E); // Set in loopback mode, test the serial chip
             outb(PORT, 0xAE); // Test serial chip (send byte 0xAE and check if serial returns same byte)
 
+            // Real UARTs may take a moment to loop the byte back.
+            // NOTE: This runs before `time::calibrate()`, so this is the rough fallback delay.
+            crate::time::delay_us(10);
+
             // Check if serial is faulty (i.e: not same byte as sent)
             if inb(PORT) != 0xAE {
-                return Err(SerialError::InitFailed);
+                return Err(KernelError::SerialInit);
             }
 
             // If serial is not faulty set it in normal operation mode
             // (not-loopback with IRQs enabled and OUT#1 and OUT#2 bits enabled)
             outb(PORT + 4, 0x0F);
 
-            SERIAL_WRITER.0.get().write(Some(SerialWriter {}));
+            SERIAL_WRITER.0.get().write(Some(SerialWriter::new()));
         }
 
         Ok(())
     }
+
+    #[allow(unused)]
+    pub fn set_translate_newlines(&mut self, value: bool) {
+        self.translate_newlines = value;
+    }
+
+    /// Sends the staged bytes.
+    ///
+    /// Instead of polling the UART before each byte, this waits once for the FIFO to be empty
+    /// and fills it in one burst.
+    pub fn flush(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+
+        unsafe {
+            while !is_transmit_empty() {
+                core::hint::spin_loop();
+            }
+
+            for &b in &self.buf[..self.len] {
+                outb(PORT, b);
+            }
+        }
+        self.len = 0;
+    }
+
+    /// Stages `b`, flushing first if the FIFO would overflow.
+    fn push(&mut self, b: u8) {
+        if self.len == FIFO_SIZE {
+            self.flush();
+        }
+
+        self.buf[self.len] = b;
+        self.len += 1;
+    }
+
+    /// Whether a `\r` has to be sent before `b`.
+    fn needs_carriage_return(&self, b: u8) -> bool {
+        self.translate_newlines && b == b'\n' && self.last_byte != b'\r'
+    }
 }
 
 /// So that we can use the nifty `write!()` macro.
+///
+/// NOTE: The last bytes written may stay staged until `flush()` is called.
 impl Write for SerialWriter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        unsafe {
-            for b in s.bytes() {
-                write_byte(b);
+        for b in s.bytes() {
+            if self.needs_carriage_return(b) {
+                self.push(b'\r');
             }
+            self.push(b);
+            self.last_byte = b;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_newline_translation() -> TestCase {
+        TestCase {
+            name: "Test translating newlines without doubling \\r",
+            test: || {
+                let mut writer = SerialWriter::new();
+                assert!(writer.needs_carriage_return(b'\n'));
+                assert!(!writer.needs_carriage_return(b'a'));
+
+                writer.last_byte = b'\r';
+                assert!(!writer.needs_carriage_return(b'\n'));
+
+                writer.last_byte = 0;
+                writer.set_translate_newlines(false);
+                assert!(!writer.needs_carriage_return(b'\n'));
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_staging() -> TestCase {
+        TestCase {
+            name: "Test staging serial writes until flushed",
+            test: || {
+                // NOTE: Never flushed, so that nothing ends up in the test output.
+                let mut writer = SerialWriter::new();
+
+                writeln!(writer, "ab").unwrap();
+                assert_eq!(&writer.buf[..writer.len], b"ab\r\n");
+
+                write!(writer, "{:012}", 0).unwrap();
+                assert_eq!(writer.len, FIFO_SIZE);
+            },
+            ..Default::default()
+        }
+    }
+}
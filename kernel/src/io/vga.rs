@@ -2,42 +2,298 @@
 //! Later on, we could figure out how to ship a font directly during compilation and then load it
 //! from within our kernel.
 //!
-//! NOTE:
-//! - `0xff` = White
-//! - `0x00` = Black
+//! Colors are RGB (see `Color`), laid out per the framebuffer's pixel format when written. Glyphs
+//! are blended from the background to the foreground color (see `set_colors()`) by the font's
+//! intensity.
 //!
-//! TODO:
-//! - Font color (background & foreground) support?!
-//! - We only support `3 bytes per pixel` formats ?
-//! - Investigate: For now, `'�'` as a backup char seems to crash stuff.
+//! Both BIOS (3 bytes per pixel) and UEFI (usually 4 bytes per pixel, the last one being reserved)
+//! framebuffers are supported.
+//!
+//! Characters missing from the font are replaced by the first available of `FALLBACK_CHARS`.
+//!
+//! Images (e.g. the boot logo, embedded from `kernel/assets/`) are drawn with `blit()`, which
+//! bypasses the text cursor.
+//!
+//! The text cursor can be shown as a blinking block (see `show_cursor()`), toggled from the timer
+//! IRQ. It inverts the pixels of its cell, so that toggling it again restores them: anything
+//! drawing over the cell erases it first.
 
-use core::{cell::UnsafeCell, fmt::Write};
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    ops::RangeInclusive,
+};
 
-use bootloader_api::info::FrameBuffer;
+use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
 use noto_sans_mono_bitmap::{
     get_raster, get_raster_width, FontWeight, RasterHeight, RasterizedChar,
 };
 
-const UNKNOWN_CHAR: char = ' '; // '�';
-const BG_COLOR: u8 = 0x00; // Black
+use crate::utils::{
+    mem::{fast_copy, fast_fill},
+    sync::Mutex,
+};
+
+/// Tried in order when a character is not in the font.
+const FALLBACK_CHARS: [char; 3] = ['�', '?', ' '];
+
+/// Default colors, see `VGAWriter::set_colors()`.
+const FG_COLOR: Color = Color::WHITE;
+const BG_COLOR: Color = Color::BLACK;
 
+/// Default border padding, in pixels, see `VGAWriter::set_padding()`.
 const HORIZONTAL_BORDER_PADDING: usize = 30;
 const VERTICAL_BORDER_PADDING: usize = 30;
 
-const CHAR_SPACING: usize = 0;
-const CHAR_HEIGHT: usize = RasterHeight::Size16.val();
-const CHAR_WIDTH: usize = get_raster_width(FontWeight::Regular, RasterHeight::Size16);
+/// Space between two characters, and between two lines, in pixels.
+///
+/// NOTE: The space between characters can be changed, see `VGAWriter::set_letter_spacing()`.
+const CHAR_SPACING: isize = 0;
 const LINE_SPACING: usize = 2;
 
+/// Longest word buffered when word wrapping, longer ones are broken anyway.
+const MAX_WORD_LEN: usize = 128;
+
+/// Timer ticks between two toggles of the blinking cursor, e.g. it blinks once per second.
+const CURSOR_BLINK_TICKS: u64 = crate::time::TICK_HZ / 2;
+
+/// An RGB color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[allow(unused)]
+impl Color {
+    pub const BLACK: Self = Self::new(0, 0, 0);
+    pub const WHITE: Self = Self::new(0xFF, 0xFF, 0xFF);
+    pub const GRAY: Self = Self::new(0x80, 0x80, 0x80);
+    pub const RED: Self = Self::new(0xFF, 0, 0);
+    pub const GREEN: Self = Self::new(0, 0xFF, 0);
+    pub const BLUE: Self = Self::new(0, 0, 0xFF);
+    pub const YELLOW: Self = Self::new(0xFF, 0xFF, 0);
+    pub const CYAN: Self = Self::new(0, 0xFF, 0xFF);
+    pub const MAGENTA: Self = Self::new(0xFF, 0, 0xFF);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Shade of gray, `0` being black and `0xFF` white.
+    pub const fn gray(intensity: u8) -> Self {
+        Self::new(intensity, intensity, intensity)
+    }
+
+    /// Mixes `self` towards `other`: `0` gives `self`, `0xFF` gives `other`.
+    pub fn blend(self, other: Self, amount: u8) -> Self {
+        let mix = |from: u8, to: u8| {
+            ((from as u16 * (0xFF - amount as u16) + to as u16 * amount as u16) / 0xFF) as u8
+        };
+
+        Self::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
+
+    /// Bytes of this color as laid out in a `format` framebuffer. Only the first
+    /// `byte_count(format)` are meaningful.
+    ///
+    /// NOTE: `U8` framebuffers get the luma (same weights as the usual one, scaled to 256), and
+    /// unknown formats a gray with that luma on 3 bytes.
+    pub fn to_bytes(self, format: PixelFormat) -> [u8; 3] {
+        let luma = ((77 * self.r as u16 + 150 * self.g as u16 + 29 * self.b as u16) >> 8) as u8;
+
+        match format {
+            PixelFormat::Rgb => [self.r, self.g, self.b],
+            PixelFormat::Bgr => [self.b, self.g, self.r],
+            PixelFormat::U8 => [luma, 0, 0],
+            _ => [luma; 3],
+        }
+    }
+
+    /// Number of bytes a color takes in a `format` framebuffer.
+    ///
+    /// NOTE: The 4th byte of UEFI framebuffers is reserved, so we leave it alone.
+    pub fn byte_count(format: PixelFormat) -> usize {
+        match format {
+            PixelFormat::U8 => 1,
+            _ => 3,
+        }
+    }
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Self::new(r, g, b)
+    }
+}
+
+/// Common East Asian wide code points (CJK, Hangul, full-width forms, most emoji), which take two
+/// text columns, see `is_wide()`.
+const WIDE_RANGES: [RangeInclusive<char>; 14] = [
+    '\u{1100}'..='\u{115F}',   // Hangul Jamo initials
+    '\u{2E80}'..='\u{303E}',   // CJK radicals, symbols and punctuation
+    '\u{3041}'..='\u{33FF}',   // Kana, Bopomofo, CJK compatibility
+    '\u{3400}'..='\u{4DBF}',   // CJK extension A
+    '\u{4E00}'..='\u{9FFF}',   // CJK unified ideographs
+    '\u{A000}'..='\u{A4CF}',   // Yi
+    '\u{AC00}'..='\u{D7A3}',   // Hangul syllables
+    '\u{F900}'..='\u{FAFF}',   // CJK compatibility ideographs
+    '\u{FE30}'..='\u{FE4F}',   // CJK compatibility forms
+    '\u{FF00}'..='\u{FF60}',   // Full-width forms
+    '\u{FFE0}'..='\u{FFE6}',   // Full-width signs
+    '\u{1F300}'..='\u{1F64F}', // Emoji
+    '\u{1F900}'..='\u{1F9FF}', // More emoji
+    '\u{20000}'..='\u{3FFFD}', // CJK extensions B and later
+];
+
+/// Whether `c` takes two text columns, like a terminal would draw it.
+///
+/// NOTE: The font has none of these, they are drawn as the fallback glyph followed by a blank.
+fn is_wide(c: char) -> bool {
+    WIDE_RANGES.iter().any(|range| range.contains(&c))
+}
+
+/// Drawn in the top right corner at boot.
+static LOGO: &[u8] = include_bytes!("../../assets/logo.ppm");
+
+/// An RGB image, borrowed from e.g. an embedded asset.
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    pub width: usize,
+    pub height: usize,
+    /// `width * height` pixels, 3 bytes each.
+    pub pixels: &'a [u8],
+}
+
+impl<'a> Image<'a> {
+    /// Parses a binary (`P6`) PPM image, e.g. embedded with `include_bytes!`.
+    ///
+    /// NOTE: Only 8 bits channels (max value of 255) are supported.
+    pub fn from_ppm(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        let mut pos = 0;
+
+        if ppm_token(bytes, &mut pos) != Some(b"P6") {
+            return Err("Not a binary PPM image");
+        }
+        let width = ppm_number(bytes, &mut pos)?;
+        let height = ppm_number(bytes, &mut pos)?;
+        if ppm_number(bytes, &mut pos)? != 255 {
+            return Err("Unsupported PPM max value");
+        }
+        if width == 0 || height == 0 {
+            return Err("Empty PPM image");
+        }
+        let len = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(3))
+            .ok_or("PPM image too big")?;
+
+        // A single whitespace separates the header from the pixels.
+        let pixels = bytes
+            .get(pos + 1..pos + 1 + len)
+            .ok_or("Truncated PPM image")?;
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+/// Returns the next whitespace separated token of a PPM header, skipping `#` comments.
+fn ppm_token<'a>(bytes: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        match bytes.get(*pos)? {
+            b'#' => {
+                while bytes.get(*pos).is_some_and(|&b| b != b'\n') {
+                    *pos += 1;
+                }
+            }
+            b if b.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+
+    Some(&bytes[start..*pos])
+}
+
+fn ppm_number(bytes: &[u8], pos: &mut usize) -> Result<usize, &'static str> {
+    let token = ppm_token(bytes, pos).ok_or("Truncated PPM header")?;
+
+    core::str::from_utf8(token)
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .ok_or("Invalid number in PPM header")
+}
+
+/// State of the ANSI escape sequences parser.
+#[derive(Debug, Clone, Copy)]
+enum Escape {
+    None,
+    /// Got `ESC`.
+    Start,
+    /// Got `ESC [`, followed by numeric parameters separated by `;`.
+    Csi {
+        params: [usize; 2],
+        count: usize,
+    },
+}
+
 pub struct VGAWriter {
     /// TODO: Put this behind a `Mutex` to allow multiple writers?
     buffer: &'static mut [u8],
 
-    info: bootloader_api::info::FrameBufferInfo,
+    /// NOTE: `width` and `height` are the visible pixels, but rows are `stride` pixels apart in
+    /// `buffer`. Offsets must go through `pixel_offset()` / `row_bytes()`.
+    info: FrameBufferInfo,
+
+    /// Blank space kept on the left/right and top/bottom of the screen, in pixels.
+    h_padding: usize,
+    v_padding: usize,
 
     cur_x: usize,
     cur_y: usize,
 
+    /// Cursor position (in pixels) stored by `save_cursor()`.
+    saved_cursor: (usize, usize),
+
+    /// Column new lines start at, see `set_indent()`.
+    indent: usize,
+
+    escape: Escape,
+
+    /// Text and background colors, see `set_colors()`.
+    fg: Color,
+    bg: Color,
+
+    /// When set, characters are buffered in `word` until the end of the word so that words are
+    /// not split across lines.
+    word_wrap: bool,
+    word: [char; MAX_WORD_LEN],
+    word_len: usize,
+
+    /// First and last text rows (inclusive) scrolled by newlines, see `set_scroll_region()`.
+    scroll_region: (usize, usize),
+
+    /// Whether the blinking cursor is enabled, see `show_cursor()`.
+    cursor_shown: bool,
+    /// Top left corner (in pixels) of the cell currently inverted by the cursor, if any.
+    cursor_drawn: Option<(usize, usize)>,
+
+    /// Pixels added after each glyph, negative to tighten the text, see `set_letter_spacing()`.
+    letter_spacing: isize,
+
     cur_font_weight: FontWeight,
     cur_font_height: RasterHeight,
 }
@@ -48,6 +304,40 @@ unsafe impl Sync for VGAWriterHolder {}
 
 pub static SCREEN_WRITER: VGAWriterHolder = VGAWriterHolder(UnsafeCell::new(None));
 
+/// Held while writing to `SCREEN_WRITER` (see `print!`), so that interrupt handlers (e.g. the
+/// status bar) only draw when they don't interrupt a write.
+pub static SCREEN_LOCK: Mutex<()> = Mutex::new(());
+
+/// Runs `f` on `SCREEN_WRITER` while holding `SCREEN_LOCK`. Returns `None` if there is no screen.
+///
+/// NOTE: This spins on the lock, so it must not be called from an interrupt handler nor while
+/// printing.
+pub fn with_screen<R>(f: impl FnOnce(&mut VGAWriter) -> R) -> Option<R> {
+    let _screen = SCREEN_LOCK.lock();
+    // Safety: We hold `SCREEN_LOCK`.
+    let writer = unsafe { (*SCREEN_WRITER.0.get()).as_mut() }?;
+
+    Some(f(writer))
+}
+
+/// Called on each timer tick, toggles the cursor every `CURSOR_BLINK_TICKS`.
+///
+/// NOTE: This runs in interrupt context, so if the screen is being written to, the toggle is
+/// simply skipped.
+pub fn on_tick(ticks: u64) {
+    if !ticks.is_multiple_of(CURSOR_BLINK_TICKS) {
+        return;
+    }
+
+    let Some(_screen) = SCREEN_LOCK.try_lock() else {
+        return;
+    };
+    // Safety: We hold `SCREEN_LOCK`.
+    if let Some(writer) = unsafe { (*SCREEN_WRITER.0.get()).as_mut() } {
+        writer.toggle_cursor();
+    }
+}
+
 impl VGAWriter {
     /// This function initializes `SCREEN_WRITER` given a frame buffer and its relative
     /// information.
@@ -56,119 +346,805 @@ impl VGAWriter {
     ///
     /// NOTE: For now we'll use the build-defaults font sizes (weights and height). If we want to
     /// support more, we just need to change the compile features of `noto_sans_mono_bitmap`.
-    pub fn init(fb: &mut FrameBuffer) {
+    pub fn init(fb: FrameBuffer) {
         let info = fb.info();
+        let buffer = fb.into_buffer();
 
-        // FIXME: For now we force the 3 bytes per pixel formats (e.g. either RGB or BGR).
-        assert_eq!(info.bytes_per_pixel, 3);
+        let mut writer = Self::new(buffer, info);
 
-        let buffer = unsafe {
-            let owned = core::ptr::read(fb as *mut FrameBuffer);
+        // Clear the whole screen.
+        writer.clear();
+        writer.draw_logo();
 
-            owned.into_buffer()
-        };
+        unsafe {
+            SCREEN_WRITER.0.get().write(Some(writer));
+        }
+    }
+
+    /// Creates a writer drawing into `buffer`, laid out as described by `info`.
+    fn new(buffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
+        match info.pixel_format {
+            PixelFormat::Rgb | PixelFormat::Bgr => assert!(info.bytes_per_pixel >= 3),
+            PixelFormat::U8 => assert!(info.bytes_per_pixel >= 1),
+            format => panic!("Unsupported framebuffer pixel format: {:?}", format),
+        }
+        assert!(info.stride >= info.width);
+        // So that any on-screen pixel is in `buffer`, see `write_pixel_unchecked()`.
+        assert!(info.stride * info.height * info.bytes_per_pixel <= buffer.len());
 
         let mut writer = Self {
             buffer,
             info,
+            h_padding: HORIZONTAL_BORDER_PADDING,
+            v_padding: VERTICAL_BORDER_PADDING,
             cur_x: HORIZONTAL_BORDER_PADDING,
             cur_y: VERTICAL_BORDER_PADDING,
+            saved_cursor: (HORIZONTAL_BORDER_PADDING, VERTICAL_BORDER_PADDING),
+            indent: 0,
+            escape: Escape::None,
+            fg: FG_COLOR,
+            bg: BG_COLOR,
+            word_wrap: false,
+            word: ['\0'; MAX_WORD_LEN],
+            word_len: 0,
+            scroll_region: (0, 0),
+            cursor_shown: false,
+            cursor_drawn: None,
+            letter_spacing: CHAR_SPACING,
             cur_font_weight: FontWeight::Regular,
             cur_font_height: RasterHeight::Size16,
         };
+        writer.scroll_region = (0, writer.text_size().1.saturating_sub(1));
 
-        // Clear the whole screen.
-        writer.clear();
+        writer
+    }
 
-        unsafe {
-            SCREEN_WRITER.0.get().write(Some(writer));
+    /// Clears the screen and fill it with the background color.
+    pub fn clear(&mut self) {
+        self.erase_cursor();
+        self.cur_x = self.h_padding;
+        self.cur_y = self.v_padding;
+
+        // NOTE: A single byte fill is much faster, but only works when all the bytes are the same.
+        let bytes = self.bg.to_bytes(self.info.pixel_format);
+        if bytes[..Color::byte_count(self.info.pixel_format)]
+            .iter()
+            .all(|&byte| byte == bytes[0])
+        {
+            unsafe { fast_fill(self.buffer.as_mut_ptr(), bytes[0], self.buffer.len()) };
+        } else {
+            self.clear_rect(0, 0, self.info.width, self.info.height);
         }
     }
 
-    /// Clears the screen and fill it with `BG_COLOR`.
-    pub fn clear(&mut self) {
-        self.cur_x = HORIZONTAL_BORDER_PADDING;
-        self.cur_y = VERTICAL_BORDER_PADDING;
+    /// Same as `clear()`, but each visible pixel gets the RGB color `pattern(x, y)` returns, e.g.
+    /// a gradient to check the pixel format and stride at a glance.
+    ///
+    /// NOTE: This is much slower than `clear()`, which fills the whole buffer at once.
+    #[allow(unused)]
+    pub fn clear_pattern(&mut self, pattern: impl Fn(usize, usize) -> (u8, u8, u8)) {
+        self.erase_cursor();
+        self.cur_x = self.h_padding;
+        self.cur_y = self.v_padding;
+
+        for y in 0..self.info.height {
+            for x in 0..self.info.width {
+                // Safety: Within the visible width and height.
+                unsafe { self.write_pixel_unchecked(x, y, pattern(x, y).into()) };
+            }
+        }
+    }
+
+    /// Fills the given rectangle (in pixels) with the background color.
+    fn clear_rect(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        self.erase_cursor_in(x, y, width, height);
+        for yi in y..(y + height).min(self.info.height) {
+            for xi in x..(x + width).min(self.info.width) {
+                // Safety: Clipped to the screen.
+                unsafe { self.write_pixel_unchecked(xi, yi, self.bg) };
+            }
+        }
+    }
+
+    /// Horizontal advance of a character with the current font, spacing included, in pixels.
+    ///
+    /// NOTE: The font is monospace, so this is the width of every text column.
+    pub fn char_width(&self) -> usize {
+        self.advance(self.glyph_width())
+    }
+
+    /// How far the cursor moves after a glyph `width` pixels wide, with the current spacing.
+    ///
+    /// NOTE: Always at least 1 pixel, so that a negative spacing can't stack glyphs up.
+    fn advance(&self, width: usize) -> usize {
+        width.saturating_add_signed(self.letter_spacing).max(1)
+    }
+
+    /// Height of a text line with the current font, spacing included, in pixels.
+    pub fn line_height(&self) -> usize {
+        self.glyph_height() + LINE_SPACING
+    }
+
+    /// Size of a glyph of the current font, in pixels.
+    fn glyph_width(&self) -> usize {
+        get_raster_width(self.cur_font_weight, self.cur_font_height)
+    }
+
+    fn glyph_height(&self) -> usize {
+        self.cur_font_height.val()
+    }
+
+    /// Width and height `s` takes once written, in pixels.
+    ///
+    /// Each line is as wide as the glyphs `print_char()` would draw, and as high as `line_height()`
+    /// (e.g. a trailing newline adds an empty line).
+    ///
+    /// NOTE: Escape sequences and control characters other than `'\n'` are not interpreted, and
+    /// lines are measured as if they were not wrapped.
+    #[allow(unused)]
+    pub fn measure_str(&self, s: &str) -> (usize, usize) {
+        if s.is_empty() {
+            return (0, 0);
+        }
+
+        let mut width = 0;
+        let mut lines = 0;
+        for line in s.split('\n') {
+            // Same advance as `draw_char()`.
+            let line_width = line
+                .chars()
+                .map(|c| {
+                    if is_wide(c) {
+                        return 2 * self.char_width();
+                    }
+                    self.get_rendered_char(c)
+                        .map_or(self.char_width(), |rendered| self.advance(rendered.width()))
+                })
+                .sum();
+
+            width = usize::max(width, line_width);
+            lines += 1;
+        }
+
+        (width, lines * self.line_height())
+    }
+
+    /// Width of the writable area (e.g. within the borders), in pixels.
+    fn text_width(&self) -> usize {
+        self.info.width - 2 * self.h_padding
+    }
+
+    /// Blanks the current line and goes back to its beginning.
+    pub fn clear_line(&mut self) {
+        self.carriage_return();
+        self.clear_rect(
+            self.h_padding,
+            self.cur_y,
+            self.text_width(),
+            self.line_height(),
+        );
+    }
+
+    /// Blanks the current line from the cursor to the right border.
+    fn clear_line_end(&mut self) {
+        let width = self.info.width - self.h_padding - self.cur_x.min(self.info.width);
+        self.clear_rect(self.cur_x, self.cur_y, width, self.line_height());
+    }
+
+    /// Blanks everything from the cursor to the bottom of the writable area. The cursor does not
+    /// move.
+    pub fn clear_to_end(&mut self) {
+        self.clear_line_end();
+
+        let below = self.cur_y + self.line_height();
+        let bottom = self.info.height - self.v_padding;
+        if below < bottom {
+            self.clear_rect(self.h_padding, below, self.text_width(), bottom - below);
+        }
+    }
+
+    /// Number of character columns and rows that fit within the borders.
+    pub fn text_size(&self) -> (usize, usize) {
+        let cols = self.info.width.saturating_sub(2 * self.h_padding) / self.char_width();
+        let rows = self.info.height.saturating_sub(2 * self.v_padding) / self.line_height();
+
+        (cols, rows)
+    }
+
+    /// Y coordinate of the top of text row `row`, in pixels.
+    fn row_y(&self, row: usize) -> usize {
+        self.v_padding + row * self.line_height()
+    }
+
+    /// Sets the border padding, in pixels, e.g. `(0, 0)` to use the whole screen.
+    ///
+    /// The text laid out with the previous padding would not line up with the new one anymore, so
+    /// the screen is cleared, and the scroll region reset to the whole writable area.
+    ///
+    /// NOTE: The logo is drawn within the padding, so it is not drawn again.
+    #[allow(unused)]
+    pub fn set_padding(&mut self, horizontal: usize, vertical: usize) {
+        assert!(
+            2 * horizontal < self.info.width && 2 * vertical < self.info.height,
+            "Padding {}x{} leaves no room for text",
+            horizontal,
+            vertical
+        );
+
+        self.flush();
+        self.h_padding = horizontal;
+        self.v_padding = vertical;
+        self.scroll_region = (0, self.text_size().1.saturating_sub(1));
+        self.clear();
+        self.saved_cursor = (self.cur_x, self.cur_y);
+    }
+
+    /// Restricts scrolling to the text rows from `top_row` to `bottom_row` (inclusive): a newline
+    /// on `bottom_row` scrolls these rows up, while the ones outside the region stay put.
+    ///
+    /// The cursor moves to the beginning of the region.
+    ///
+    /// NOTE: Rows past the bottom of the screen are clamped to the last one.
+    pub fn set_scroll_region(&mut self, top_row: usize, bottom_row: usize) {
+        let last_row = self.text_size().1.saturating_sub(1);
+        let bottom_row = bottom_row.min(last_row);
+        assert!(
+            top_row <= bottom_row,
+            "Invalid scroll region: {}..={}",
+            top_row,
+            bottom_row
+        );
+
+        self.flush();
+        self.scroll_region = (top_row, bottom_row);
+        self.set_cursor(0, top_row);
+    }
+
+    /// Moves the rows of the scroll region up by one, and blanks its last row.
+    fn scroll_up(&mut self) {
+        let (top, bottom) = self.scroll_region;
+
+        // NOTE: Whole pixel rows are moved (borders and stride padding included), which is
+        // simpler and faster than copying each row's visible part.
+        let dst = self.row_y(top) * self.row_bytes();
+        let src = self.row_y(top + 1) * self.row_bytes();
+        let end = self.row_y(bottom + 1) * self.row_bytes();
+        assert!(end <= self.buffer.len());
+        // Safety: `dst < src < end`, all within the buffer.
+        let base = self.buffer.as_mut_ptr();
+        unsafe { fast_copy(base.add(dst), base.add(src), end - src) };
+
+        self.clear_rect(
+            self.h_padding,
+            self.row_y(bottom),
+            self.text_width(),
+            self.line_height(),
+        );
+    }
+
+    /// Moves the cursor to the given character cell.
+    ///
+    /// NOTE: Out of bounds positions are clamped to the last column/row.
+    pub fn set_cursor(&mut self, col: usize, row: usize) {
+        let (cols, rows) = self.text_size();
+        let col = col.min(cols.saturating_sub(1));
+        let row = row.min(rows.saturating_sub(1));
+
+        self.cur_x = self.h_padding + col * self.char_width();
+        self.cur_y = self.v_padding + row * self.line_height();
+    }
 
-        // Fill with Black.
-        self.buffer.fill(BG_COLOR)
+    /// Returns the character cell the cursor is in.
+    #[allow(unused)]
+    pub fn cursor(&self) -> (usize, usize) {
+        (
+            (self.cur_x - self.h_padding) / self.char_width(),
+            (self.cur_y - self.v_padding) / self.line_height(),
+        )
+    }
+
+    /// Makes new lines (from `'\n'` or wrapping) start at column `col` instead of the left
+    /// border, e.g. to keep multi-line output aligned under a prompt. 0 restores the default.
+    ///
+    /// NOTE: `'\r'` still goes back to the left border.
+    pub fn set_indent(&mut self, col: usize) {
+        self.indent = col;
+    }
+
+    /// Sets the space added after each character (tracking), in pixels. Negative values tighten
+    /// the text, `0` (the default) puts glyphs side by side.
+    ///
+    /// This changes the width of the text columns, so the cursor (see `cursor()`) and wrapping
+    /// follow the new spacing. Text already on the screen is left as is.
+    ///
+    /// NOTE: Glyphs are drawn with their background, so with a negative spacing a glyph paints
+    /// over the right edge of the previous one.
+    #[allow(unused)]
+    pub fn set_letter_spacing(&mut self, spacing: isize) {
+        self.flush();
+        self.letter_spacing = spacing;
+    }
+
+    /// Sets the color of the text, and of the background.
+    ///
+    /// Only what is drawn afterwards uses them, e.g. call `clear()` to repaint the background.
+    #[allow(unused)]
+    pub fn set_colors(&mut self, fg: Color, bg: Color) {
+        self.flush();
+        self.fg = fg;
+        self.bg = bg;
+    }
+
+    /// Saves the cursor position, see `restore_cursor()`.
+    ///
+    /// NOTE: There is a single saved position, saving again overwrites it.
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = (self.cur_x, self.cur_y);
+    }
+
+    /// Moves the cursor back to where it was when calling `save_cursor()`.
+    pub fn restore_cursor(&mut self) {
+        (self.cur_x, self.cur_y) = self.saved_cursor;
+    }
+
+    /// Enables or disables word wrapping.
+    ///
+    /// NOTE: With word wrapping, the last word only shows up once it's complete (e.g. followed by a
+    /// space or a newline) or when calling `flush()`.
+    #[allow(unused)]
+    pub fn set_word_wrap(&mut self, value: bool) {
+        self.flush();
+        self.word_wrap = value;
+    }
+
+    /// Writes the buffered word, if any. It goes to the next line first if it does not fit on the
+    /// current one.
+    pub fn flush(&mut self) {
+        if self.word_len == 0 {
+            return;
+        }
+
+        let word_width = self.word[..self.word_len]
+            .iter()
+            .map(|&c| if is_wide(c) { 2 } else { 1 })
+            .sum::<usize>()
+            * self.char_width();
+        let line_end = self.info.width - self.h_padding;
+        if self.cur_x + word_width > line_end && self.cur_x > self.line_start() {
+            self.newline();
+        }
+
+        for i in 0..self.word_len {
+            self.put_char(self.word[i]);
+        }
+        self.word_len = 0;
+    }
+
+    /// Writes `args` on text row `row` from column `col`, blanking the rest of the row. The cursor
+    /// does not move.
+    ///
+    /// NOTE: Text going past the right border is cut, escape sequences are not interpreted, and
+    /// nothing is allocated, so this can be used from interrupt handlers.
+    pub fn write_at(&mut self, col: usize, row: usize, args: fmt::Arguments) {
+        /// Draws each character in the next cell, until the right border.
+        struct Cells<'a>(&'a mut VGAWriter);
+
+        impl Write for Cells<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let writer = &mut *self.0;
+                for c in s.chars() {
+                    if writer.cur_x + writer.char_extent(c) > writer.info.width - writer.h_padding {
+                        break;
+                    }
+
+                    writer.draw_char(c);
+                }
+
+                Ok(())
+            }
+        }
+
+        let saved = (self.cur_x, self.cur_y);
+        self.set_cursor(col, row);
+        // NOTE: This also erases the cursor if it's on that row.
+        self.clear_line_end();
+
+        // `Cells` never fails.
+        let _ = Cells(self).write_fmt(args);
+
+        (self.cur_x, self.cur_y) = saved;
     }
 
     /// Write a single character on the screen at the current position.
     pub fn print_char(&mut self, c: char) {
+        if !self.word_wrap {
+            return self.put_char(c);
+        }
+
+        // NOTE: Escape sequences take no room, so they are not part of the word either.
+        if c.is_whitespace() || c.is_control() || !matches!(self.escape, Escape::None) {
+            self.flush();
+            return self.put_char(c);
+        }
+
+        // The word would not fit on a line anyway, break it here.
+        let max_len = self.text_size().0.clamp(1, MAX_WORD_LEN);
+        if self.word_len >= max_len {
+            self.flush();
+        }
+
+        self.word[self.word_len] = c;
+        self.word_len += 1;
+    }
+
+    /// Writes a character right away, going to the next line if it does not fit.
+    fn put_char(&mut self, c: char) {
+        self.erase_cursor();
+
+        if self.handle_escape(c) {
+            return;
+        }
+
         match c {
             '\n' => self.newline(),
             '\r' => self.carriage_return(),
+            '\x08' => self.backspace(),
             c => {
                 // If the char will go over the right border, do a newline
-                let new_x = self.cur_x + CHAR_WIDTH;
-                if new_x > self.info.width - HORIZONTAL_BORDER_PADDING {
+                let new_x = self.cur_x + self.char_extent(c);
+                if new_x > self.info.width - self.h_padding {
                     self.newline();
                 }
-                // If the char will go over the bottom border, clear the screen.
-                // TODO: Implement screen scrolling ?
-                let new_y = self.cur_y + CHAR_HEIGHT;
-                if new_y > self.info.height - VERTICAL_BORDER_PADDING {
-                    self.clear();
+                // If the char will go over the bottom border (e.g. the cursor was moved below
+                // the scroll region), scroll and write it on the region's last row instead.
+                let new_y = self.cur_y + self.glyph_height();
+                if new_y > self.info.height - self.v_padding {
+                    self.scroll_up();
+                    self.cur_y = self.row_y(self.scroll_region.1);
                 }
 
-                self.write_rendered_char(self.get_rendered_char(c));
+                self.draw_char(c);
+            }
+        }
+    }
+
+    /// Draws `c` at the cursor, and moves the cursor past the columns it takes.
+    fn draw_char(&mut self, c: char) {
+        let start = self.cur_x;
+        match self.get_rendered_char(c) {
+            Some(rendered) => self.write_rendered_char(rendered),
+            // Nothing we can draw, still leave a blank.
+            None => self.cur_x += self.char_width(),
+        }
+
+        // The glyph only covers the first column, blank the second one.
+        if is_wide(c) {
+            let end = start + 2 * self.char_width();
+            let (x, y) = (self.cur_x, self.cur_y);
+            self.clear_rect(x, y, end.saturating_sub(x), self.glyph_height());
+            self.cur_x = end;
+        }
+    }
+
+    /// Width `c` covers once drawn, in pixels, e.g. what must fit before the right border.
+    fn char_extent(&self, c: char) -> usize {
+        if is_wide(c) {
+            self.char_width() + self.glyph_width()
+        } else {
+            self.glyph_width()
+        }
+    }
+
+    /// Feeds `c` to the escape sequences parser. Returns whether `c` was part of a sequence.
+    ///
+    /// Supported sequences:
+    /// - `ESC[<row>;<col>H`: moves the cursor (1-based, missing parameters default to 1).
+    /// - `ESC[s` / `ESC[u`: saves / restores the cursor.
+    /// - `ESC[K` / `ESC[2K`: clears to the end of the line / the whole line.
+    /// - `ESC[J`: clears to the end of the screen.
+    /// - `ESC[<top>;<bottom>r`: sets the scroll region (1-based, inclusive, missing parameters
+    ///   default to the whole screen).
+    /// - `ESC[<col>I`: sets the indent column, see `set_indent()` (1-based, a missing parameter
+    ///   means the current column). Not standard, `ESC[1I` goes back to no indent.
+    fn handle_escape(&mut self, c: char) -> bool {
+        match (self.escape, c) {
+            (Escape::None, '\x1b') => self.escape = Escape::Start,
+            (Escape::None, _) => return false,
+            (Escape::Start, '[') => {
+                self.escape = Escape::Csi {
+                    params: [0; 2],
+                    count: 0,
+                }
+            }
+            // Not a CSI sequence, ignore it.
+            (Escape::Start, _) => self.escape = Escape::None,
+            (Escape::Csi { mut params, count }, '0'..='9') => {
+                if let Some(param) = params.get_mut(count) {
+                    let digit = c as usize - '0' as usize;
+                    *param = param.saturating_mul(10).saturating_add(digit);
+                }
+                self.escape = Escape::Csi { params, count };
+            }
+            (Escape::Csi { params, count }, ';') => {
+                self.escape = Escape::Csi {
+                    params,
+                    count: count.saturating_add(1),
+                }
+            }
+            (Escape::Csi { params, .. }, end) => {
+                match end {
+                    'H' => {
+                        self.set_cursor(params[1].saturating_sub(1), params[0].saturating_sub(1))
+                    }
+                    'K' if params[0] == 2 => self.clear_line(),
+                    'K' => self.clear_line_end(),
+                    'J' => self.clear_to_end(),
+                    'r' => {
+                        let last_row = self.text_size().1.saturating_sub(1);
+                        let top = params[0].saturating_sub(1);
+                        let bottom = match params[1] {
+                            0 => last_row,
+                            bottom => (bottom - 1).min(last_row),
+                        };
+                        // Invalid region (e.g. reversed), ignore it like unsupported sequences.
+                        if top <= bottom {
+                            self.set_scroll_region(top, bottom);
+                        }
+                    }
+                    'I' => match params[0] {
+                        0 => self.set_indent(self.cursor().0),
+                        col => self.set_indent(col - 1),
+                    },
+                    's' => self.save_cursor(),
+                    'u' => self.restore_cursor(),
+                    // Unsupported sequence, ignore it.
+                    _ => {}
+                }
+                self.escape = Escape::None;
             }
         }
+
+        true
     }
 
-    /// Converts a character to its rendered bitmap.
-    fn get_rendered_char(&self, c: char) -> RasterizedChar {
-        get_raster(c, self.cur_font_weight, self.cur_font_height).unwrap_or(self.backup_char())
+    /// Converts a character to its rendered bitmap, or to the first of `FALLBACK_CHARS` the font
+    /// has.
+    ///
+    /// NOTE: This is `None` only if the font has none of them.
+    fn get_rendered_char(&self, c: char) -> Option<RasterizedChar> {
+        core::iter::once(c)
+            .chain(FALLBACK_CHARS)
+            .find_map(|c| get_raster(c, self.cur_font_weight, self.cur_font_height))
     }
 
     /// Writes a whole character on the screen.
     fn write_rendered_char(&mut self, char_pixels: RasterizedChar) {
         for (yi, row) in char_pixels.raster().iter().enumerate() {
             for (xi, pixel) in row.iter().enumerate() {
-                self.write_pixel(self.cur_x + xi, self.cur_y + yi, *pixel);
+                let color = self.bg.blend(self.fg, *pixel);
+                self.write_pixel(self.cur_x + xi, self.cur_y + yi, color);
             }
         }
 
         // Update the cursor's horizontal position.
-        self.cur_x += char_pixels.width() + CHAR_SPACING;
+        self.cur_x += self.advance(char_pixels.width());
+    }
+
+    /// Visible width and height of the screen, in pixels.
+    #[allow(unused)]
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.info.width, self.info.height)
+    }
+
+    #[allow(unused)]
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.info.pixel_format
+    }
+
+    /// Lends the raw framebuffer to `f`, e.g. for a custom renderer.
+    ///
+    /// NOTE: Rows are `info.stride` pixels apart (not `info.width`), and each pixel is
+    /// `info.bytes_per_pixel` bytes laid out as `info.pixel_format` says. The text cursor is left
+    /// as is, so whatever `f` draws may be written over by the next prints.
+    #[allow(unused)]
+    pub fn with_pixels<R>(&mut self, f: impl FnOnce(&mut [u8], FrameBufferInfo) -> R) -> R {
+        // Draw the buffered word first, so that it does not end up over what `f` draws.
+        self.flush();
+        self.erase_cursor();
+
+        f(self.buffer, self.info)
+    }
+
+    /// Size of a row of pixels in `buffer`, in bytes.
+    ///
+    /// NOTE: This includes the `stride - width` padding pixels at the end of each row.
+    fn row_bytes(&self) -> usize {
+        self.info.stride * self.info.bytes_per_pixel
+    }
+
+    /// Offset of the pixel at (`x`, `y`) in `buffer`.
+    fn pixel_offset(&self, x: usize, y: usize) -> usize {
+        y * self.row_bytes() + x * self.info.bytes_per_pixel
     }
 
     /// Writes a single pixel on the screen.
     ///
-    /// NOTE: `intensity` is basically a grayscale for now.
-    pub fn write_pixel(&mut self, x: usize, y: usize, intensity: u8) {
-        let idx = (y * self.info.stride + x) * self.info.bytes_per_pixel;
-        // NOTE: This could be behind a `hardened` feature.
-        assert!(idx < self.info.byte_len);
+    /// With the `hardened` feature (on by default), off-screen pixels panic. Otherwise, they only
+    /// do past the end of the buffer: e.g. `x` past the width writes in the stride padding, or on
+    /// the next row.
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+        #[cfg(feature = "hardened")]
+        assert!(
+            x < self.info.width && y < self.info.height,
+            "Pixel ({}, {}) is off screen",
+            x,
+            y
+        );
 
-        // For now, we manually write the three RGB values. Will fix this when adding color
-        // support.
-        self.buffer[idx] = intensity;
-        self.buffer[idx + 1] = intensity;
-        self.buffer[idx + 2] = intensity;
+        let idx = self.pixel_offset(x, y);
+        let count = Color::byte_count(self.info.pixel_format);
+        self.buffer[idx..idx + count]
+            .copy_from_slice(&color.to_bytes(self.info.pixel_format)[..count]);
     }
 
-    /// Goes to the beginning of the next line.
-    fn newline(&mut self) {
-        self.cur_y += CHAR_HEIGHT + LINE_SPACING;
-        self.carriage_return();
+    /// Same as `write_pixel()`, without any bounds check, for loops that already clip their
+    /// coordinates.
+    ///
+    /// Safety: (`x`, `y`) must be on screen.
+    unsafe fn write_pixel_unchecked(&mut self, x: usize, y: usize, color: Color) {
+        let idx = self.pixel_offset(x, y);
+        let count = Color::byte_count(self.info.pixel_format);
+        self.buffer
+            .get_unchecked_mut(idx..idx + count)
+            .copy_from_slice(&color.to_bytes(self.info.pixel_format)[..count]);
     }
 
-    /// Returns to the beginning of the current line.
-    fn carriage_return(&mut self) {
-        self.cur_x = HORIZONTAL_BORDER_PADDING;
+    /// Copies a `width` x `height` image to the screen, with its top left corner at (`x`, `y`).
+    ///
+    /// `pixels` is either RGB (3 bytes per pixel) or RGBA (4 bytes per pixel, fully transparent
+    /// pixels are skipped). The image is clipped to the screen, and the cursor is left untouched.
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, height: usize, pixels: &[u8]) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let src_bpp = match pixels.len().checked_div(width * height) {
+            Some(bpp @ (3 | 4)) if pixels.len() == bpp * width * height => bpp,
+            _ => panic!(
+                "blit: {} bytes for a {}x{} image is neither RGB nor RGBA",
+                pixels.len(),
+                width,
+                height
+            ),
+        };
+
+        let visible_width = width.min(self.info.width.saturating_sub(x));
+        let visible_height = height.min(self.info.height.saturating_sub(y));
+        if visible_width == 0 || visible_height == 0 {
+            return;
+        }
+        self.erase_cursor_in(x, y, visible_width, visible_height);
+
+        for row in 0..visible_height {
+            for col in 0..visible_width {
+                let src = (row * width + col) * src_bpp;
+                if src_bpp == 4 && pixels[src + 3] == 0 {
+                    continue;
+                }
+                let color = Color::new(pixels[src], pixels[src + 1], pixels[src + 2]);
+
+                // Safety: Clipped to the screen.
+                unsafe { self.write_pixel_unchecked(x + col, y + row, color) };
+            }
+        }
+    }
+
+    /// Enables or disables the blinking cursor block, drawn at the cursor position by `on_tick()`.
+    pub fn show_cursor(&mut self, show: bool) {
+        self.cursor_shown = show;
+        if !show {
+            self.erase_cursor();
+        }
+    }
+
+    /// Draws the cursor block if it's not drawn (and enabled), erases it otherwise.
+    pub fn toggle_cursor(&mut self) {
+        if self.cursor_drawn.is_some() {
+            return self.erase_cursor();
+        }
+        if !self.cursor_shown {
+            return;
+        }
+
+        // E.g. at the end of a line: the next character goes on the next one, past the border.
+        if self.cur_x + self.glyph_width() > self.info.width - self.h_padding
+            || self.cur_y + self.glyph_height() > self.info.height - self.v_padding
+        {
+            return;
+        }
+
+        self.invert_cell(self.cur_x, self.cur_y);
+        self.cursor_drawn = Some((self.cur_x, self.cur_y));
+    }
+
+    /// Restores the cell under the cursor block, if it's drawn.
+    fn erase_cursor(&mut self) {
+        if let Some((x, y)) = self.cursor_drawn.take() {
+            self.invert_cell(x, y);
+        }
+    }
+
+    /// Erases the cursor block if it overlaps the given rectangle (in pixels).
+    fn erase_cursor_in(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        if let Some((cx, cy)) = self.cursor_drawn {
+            if cx < x + width
+                && x < cx + self.glyph_width()
+                && cy < y + height
+                && y < cy + self.glyph_height()
+            {
+                self.erase_cursor();
+            }
+        }
     }
 
-    /// Gets the default char `UNKNOWN_CHAR` ready to be rendered.
+    /// Inverts the pixels of the character cell whose top left corner is at (`x`, `y`).
     ///
-    /// TODO: Maybe this should be generated only once ever using a `static` ?
+    /// NOTE: Inverting twice restores the cell exactly, whatever was drawn in it.
+    fn invert_cell(&mut self, x: usize, y: usize) {
+        // Same bytes as `write_pixel()`.
+        let bytes = Color::byte_count(self.info.pixel_format);
+
+        for yi in y..y + self.glyph_height() {
+            for xi in x..x + self.glyph_width() {
+                let idx = self.pixel_offset(xi, yi);
+                for byte in &mut self.buffer[idx..idx + bytes] {
+                    *byte = !*byte;
+                }
+            }
+        }
+    }
+
+    /// Draws `LOGO` in the top right corner, inside the border padding.
+    fn draw_logo(&mut self) {
+        let logo = Image::from_ppm(LOGO).expect("The embedded logo should be a valid PPM");
+        let x = self.info.width.saturating_sub(self.h_padding + logo.width);
+
+        self.blit(x, self.v_padding, logo.width, logo.height, logo.pixels);
+    }
+
+    /// Goes to the beginning of the next line, e.g. the indent column.
     ///
-    /// NOTE: This panics if unable to generate an `UNKNOWN_CHAR` with the current font weight and
-    /// height.
-    fn backup_char(&self) -> RasterizedChar {
-        get_raster(UNKNOWN_CHAR, self.cur_font_weight, self.cur_font_height)
-            .expect("Failed to get raster of backup char")
+    /// On the last row of the scroll region, the region scrolls up instead.
+    fn newline(&mut self) {
+        if self.cur_y == self.row_y(self.scroll_region.1) {
+            self.scroll_up();
+        } else {
+            self.cur_y += self.line_height();
+        }
+        self.cur_x = self.line_start();
+    }
+
+    /// Where new lines start, see `set_indent()`.
+    ///
+    /// NOTE: The indent is clamped so that at least one character fits, it could be too large
+    /// since the font or padding changed.
+    fn line_start(&self) -> usize {
+        let col = self.indent.min(self.text_size().0.saturating_sub(1));
+
+        self.h_padding + col * self.char_width()
+    }
+
+    /// Moves the cursor one character back, without going past the left border.
+    fn backspace(&mut self) {
+        self.cur_x = self
+            .cur_x
+            .saturating_sub(self.char_width())
+            .max(self.h_padding);
+    }
+
+    /// Returns to the beginning of the current line.
+    fn carriage_return(&mut self) {
+        self.cur_x = self.h_padding;
     }
 }
 
@@ -182,3 +1158,651 @@ impl Write for VGAWriter {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    /// Runs `f` on the real screen, holding `SCREEN_LOCK` like `with_screen()`.
+    ///
+    /// NOTE: Interrupt handlers always release the lock before we resume, so it can only be held
+    /// here if a test panicked with it (see `testing`). We take it over then, rather than spinning
+    /// forever, e.g. so that the test's `teardown` still works.
+    fn with_test_screen<R>(f: impl FnOnce(&mut VGAWriter) -> R) -> R {
+        let _screen = SCREEN_LOCK.try_lock().unwrap_or_else(|| {
+            // Safety: The test that held it is gone.
+            unsafe { SCREEN_LOCK.force_unlock() };
+            SCREEN_LOCK.lock()
+        });
+        // Safety: We hold `SCREEN_LOCK`.
+        let writer = unsafe { (*SCREEN_WRITER.0.get()).as_mut() }
+            .expect("SCREEN_WRITER should be initialized before the tests");
+
+        f(writer)
+    }
+
+    #[test_case]
+    fn test_unknown_chars() -> TestCase {
+        TestCase {
+            name: "Test printing characters missing from the font",
+            test: || {
+                with_test_screen(|writer| {
+                    assert!(writer.get_rendered_char('日').is_some());
+                    write!(writer, "日本語 Ελληνικά русский \u{1F980} ").unwrap();
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_set_cursor() -> TestCase {
+        TestCase {
+            name: "Test moving, saving and restoring the cursor",
+            test: || {
+                with_test_screen(|writer| {
+                    writer.save_cursor();
+                    let saved = writer.cursor();
+
+                    writer.set_cursor(2, 3);
+                    assert_eq!(writer.cursor(), (2, 3));
+
+                    // Out of bounds positions are clamped.
+                    let (cols, rows) = writer.text_size();
+                    writer.set_cursor(cols + 10, usize::MAX);
+                    assert_eq!(writer.cursor(), (cols - 1, rows - 1));
+
+                    // Same through escape sequences.
+                    write!(writer, "\x1b[4;3H").unwrap();
+                    assert_eq!(writer.cursor(), (2, 3));
+                    write!(writer, "\x1b[H").unwrap();
+                    assert_eq!(writer.cursor(), (0, 0));
+
+                    writer.restore_cursor();
+                    assert_eq!(writer.cursor(), saved);
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_clear_line() -> TestCase {
+        TestCase {
+            name: "Test clearing the current line",
+            test: || {
+                with_test_screen(|writer| {
+                    writer.save_cursor();
+
+                    writer.set_cursor(0, 0);
+                    write!(writer, "######").unwrap();
+                    writer.clear_line();
+                    assert_eq!(writer.cursor(), (0, 0));
+
+                    for y in writer.v_padding..writer.v_padding + writer.glyph_height() {
+                        for x in writer.h_padding..writer.h_padding + 6 * writer.glyph_width() {
+                            let idx = writer.pixel_offset(x, y);
+                            assert_eq!(
+                                writer.buffer[idx..idx + 3],
+                                writer.bg.to_bytes(PixelFormat::Rgb)
+                            );
+                        }
+                    }
+
+                    writer.restore_cursor();
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_word_wrap() -> TestCase {
+        TestCase {
+            name: "Test wrapping words instead of splitting them",
+            test: || {
+                with_test_screen(|writer| {
+                    writer.save_cursor();
+                    writer.set_word_wrap(true);
+                    let (cols, _) = writer.text_size();
+
+                    // "hello" does not fit at the end of the first line, it goes to the next one.
+                    writer.set_cursor(0, 0);
+                    for _ in 0..cols - 3 {
+                        writer.print_char('a');
+                    }
+                    write!(writer, " hello").unwrap();
+                    writer.flush();
+                    assert_eq!(writer.cursor(), (5, 1));
+
+                    // A color escape does not count in the width of the word that follows it.
+                    writer.set_cursor(0, 0);
+                    for _ in 0..cols - 5 {
+                        writer.print_char('a');
+                    }
+                    write!(writer, " \x1b[31mred\x1b[0m").unwrap();
+                    writer.flush();
+                    assert_eq!(writer.cursor(), (cols - 1, 0));
+
+                    // A word longer than a line is still split.
+                    writer.set_cursor(0, 0);
+                    for _ in 0..cols + 5 {
+                        writer.print_char('b');
+                    }
+                    writer.flush();
+                    assert_eq!(writer.cursor(), (5, 1));
+
+                    writer.set_word_wrap(false);
+                    writer.clear_line();
+                    writer.restore_cursor();
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    const TEST_WIDTH: usize = 10;
+    const TEST_STRIDE: usize = 16;
+    const TEST_HEIGHT: usize = 4;
+    const TEST_BPP: usize = 3;
+    const TEST_FB_LEN: usize = TEST_STRIDE * TEST_HEIGHT * TEST_BPP;
+
+    struct TestFrameBuffer(UnsafeCell<[u8; TEST_FB_LEN]>);
+    // Safety: Only used by a single test at a time.
+    unsafe impl Sync for TestFrameBuffer {}
+
+    static TEST_FB: TestFrameBuffer = TestFrameBuffer(UnsafeCell::new([0; TEST_FB_LEN]));
+
+    /// Returns a writer drawing to `TEST_FB`, whose stride is larger than its width. All its
+    /// bytes are set to `fill`.
+    fn test_writer(fill: u8) -> VGAWriter {
+        let buffer = unsafe { &mut *TEST_FB.0.get() };
+        buffer.fill(fill);
+
+        let info = FrameBufferInfo {
+            byte_len: TEST_FB_LEN,
+            width: TEST_WIDTH,
+            height: TEST_HEIGHT,
+            pixel_format: PixelFormat::Rgb,
+            bytes_per_pixel: TEST_BPP,
+            stride: TEST_STRIDE,
+        };
+
+        VGAWriter::new(buffer, info)
+    }
+
+    #[test_case]
+    fn test_stride_larger_than_width() -> TestCase {
+        TestCase {
+            name: "Test a framebuffer whose stride is larger than its width",
+            test: || {
+                let mut writer = test_writer(0xAA);
+
+                assert_eq!(writer.row_bytes(), TEST_STRIDE * TEST_BPP);
+                assert_eq!(writer.pixel_offset(0, 1), TEST_STRIDE * TEST_BPP);
+                assert_eq!(
+                    writer.pixel_offset(TEST_WIDTH - 1, 2),
+                    (2 * TEST_STRIDE + TEST_WIDTH - 1) * TEST_BPP
+                );
+
+                writer.write_pixel(TEST_WIDTH - 1, TEST_HEIGHT - 1, Color::WHITE);
+                let idx = ((TEST_HEIGHT - 1) * TEST_STRIDE + TEST_WIDTH - 1) * TEST_BPP;
+                assert_eq!(writer.buffer[idx..idx + TEST_BPP], [0xFF; TEST_BPP]);
+
+                // Clearing the whole screen only touches the visible pixels, not the padding.
+                writer.clear_rect(0, 0, TEST_WIDTH, TEST_HEIGHT);
+                for (i, row) in writer.buffer.chunks(TEST_STRIDE * TEST_BPP).enumerate() {
+                    let (visible, padding) = row.split_at(TEST_WIDTH * TEST_BPP);
+                    let bg = writer.bg.to_bytes(PixelFormat::Rgb);
+                    assert!(visible.chunks(TEST_BPP).all(|px| px == bg), "row {}", i);
+                    assert!(padding.iter().all(|&b| b == 0xAA), "row {}", i);
+                }
+            },
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "hardened")]
+    #[test_case]
+    fn test_write_pixel_off_screen() -> TestCase {
+        TestCase {
+            name: "Test writing a pixel in the stride padding panics",
+            test: || test_writer(0).write_pixel(TEST_WIDTH, 0, Color::WHITE),
+            should_panic: true,
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_color_bytes() -> TestCase {
+        TestCase {
+            name: "Test laying out colors per pixel format",
+            test: || {
+                let color = Color::from((1, 2, 3));
+                assert_eq!(color, Color { r: 1, g: 2, b: 3 });
+                assert_eq!(color.to_bytes(PixelFormat::Rgb), [1, 2, 3]);
+                assert_eq!(color.to_bytes(PixelFormat::Bgr), [3, 2, 1]);
+                assert_eq!(Color::WHITE.to_bytes(PixelFormat::U8)[0], 0xFF);
+                assert_eq!(Color::BLACK.blend(Color::WHITE, 0x80), Color::gray(0x80));
+
+                let mut writer = test_writer(0);
+                writer.write_pixel(0, 0, Color::RED);
+                assert_eq!(writer.buffer[..3], [0xFF, 0, 0]);
+
+                writer.info.pixel_format = PixelFormat::Bgr;
+                writer.write_pixel(0, 0, Color::RED);
+                assert_eq!(writer.buffer[..3], [0, 0, 0xFF]);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_blit() -> TestCase {
+        TestCase {
+            name: "Test blitting RGB and RGBA images, clipped to the screen",
+            test: || {
+                let mut writer = test_writer(0);
+
+                // 2x2 RGBA image, the last pixel is transparent. Only its left column is on
+                // screen.
+                let pixels = [
+                    1, 2, 3, 0xFF, 4, 5, 6, 0xFF, //
+                    7, 8, 9, 0xFF, 10, 11, 12, 0,
+                ];
+                writer.blit(TEST_WIDTH - 1, 0, 2, 2, &pixels);
+
+                let idx = writer.pixel_offset(TEST_WIDTH - 1, 0);
+                assert_eq!(writer.buffer[idx..idx + 3], [1, 2, 3]);
+                let idx = writer.pixel_offset(TEST_WIDTH - 1, 1);
+                assert_eq!(writer.buffer[idx..idx + 3], [7, 8, 9]);
+                // Clipped: the padding after the visible pixels is untouched.
+                let idx = writer.pixel_offset(TEST_WIDTH, 0);
+                assert_eq!(writer.buffer[idx..idx + 3], [0, 0, 0]);
+
+                // The embedded logo parses, and is clipped to the bottom of the screen.
+                let logo = Image::from_ppm(LOGO).unwrap();
+                assert_eq!(logo.pixels.len(), logo.width * logo.height * 3);
+                writer.blit(0, TEST_HEIGHT - 1, logo.width, logo.height, logo.pixels);
+
+                // Nothing to draw, whether the image is empty or entirely off screen.
+                writer.blit(0, 0, 0, 0, &[]);
+                writer.blit(TEST_WIDTH, 0, 2, 2, &pixels);
+
+                assert!(Image::from_ppm(b"P3\n1 1\n255\n").is_err());
+                assert!(Image::from_ppm(b"P6\n0 0\n255\n").is_err());
+                assert!(Image::from_ppm(b"P6\n99999999999 99999999999\n255\n").is_err());
+                assert!(Image::from_ppm(b"P6 # comment\n2 1 255\n\0\0\0\0\0").is_err());
+                assert_eq!(
+                    Image::from_ppm(b"P6 # comment\n2 1 255\n\0\0\0\0\0\0")
+                        .unwrap()
+                        .width,
+                    2
+                );
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Sum of the bytes of text row `row`, weighted by their position.
+    fn row_checksum(writer: &VGAWriter, row: usize) -> u64 {
+        let start = writer.pixel_offset(0, writer.row_y(row));
+        let end = writer.pixel_offset(0, writer.row_y(row + 1));
+
+        writer.buffer[start..end]
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| (i as u64 + 1) * b as u64)
+            .sum()
+    }
+
+    #[test_case]
+    fn test_scroll_region() -> TestCase {
+        TestCase {
+            name: "Test scrolling only the scroll region",
+            test: || {
+                with_test_screen(|writer| {
+                    writer.save_cursor();
+                    let (_, rows) = writer.text_size();
+
+                    writer.set_scroll_region(2, 4);
+                    assert_eq!(writer.cursor(), (0, 2));
+
+                    writer.set_cursor(0, 1);
+                    write!(writer, "fixed").unwrap();
+                    writer.set_cursor(0, 4);
+                    write!(writer, "scrolled").unwrap();
+
+                    let fixed = row_checksum(writer, 1);
+                    let last = row_checksum(writer, 4);
+                    assert_ne!(last, 0);
+
+                    // On the last row of the region, a newline scrolls it up.
+                    writeln!(writer).unwrap();
+                    assert_eq!(writer.cursor(), (0, 4));
+                    assert_eq!(row_checksum(writer, 3), last);
+                    assert_eq!(row_checksum(writer, 4), 0);
+                    assert_eq!(row_checksum(writer, 1), fixed);
+
+                    // Same through escape sequences, which also reset it.
+                    write!(writer, "\x1b[3;4r").unwrap();
+                    assert_eq!(writer.scroll_region, (2, 3));
+                    write!(writer, "\x1b[r").unwrap();
+                    assert_eq!(writer.scroll_region, (0, rows - 1));
+
+                    // Invalid regions are ignored, whether reversed or starting past the screen.
+                    write!(writer, "\x1b[5;3r").unwrap();
+                    write!(writer, "\x1b[{}r", rows + 1).unwrap();
+                    assert_eq!(writer.scroll_region, (0, rows - 1));
+
+                    writer.set_cursor(0, 1);
+                    writer.clear_to_end();
+                    writer.restore_cursor();
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_borderless() -> TestCase {
+        TestCase {
+            name: "Test changing the border padding",
+            test: || {
+                with_test_screen(|writer| {
+                    let (cols, rows) = writer.text_size();
+
+                    writer.set_padding(0, 0);
+                    let (width, height) = (writer.info.width, writer.info.height);
+                    assert_eq!(
+                        writer.text_size(),
+                        (width / writer.char_width(), height / writer.line_height())
+                    );
+                    assert_eq!(writer.scroll_region.1, writer.text_size().1 - 1);
+                    assert_eq!((writer.cur_x, writer.cur_y), (0, 0));
+
+                    writer.set_cursor(2, 1);
+                    assert_eq!(
+                        (writer.cur_x, writer.cur_y),
+                        (2 * writer.char_width(), writer.line_height())
+                    );
+
+                    // Back to the defaults.
+                    writer.set_padding(HORIZONTAL_BORDER_PADDING, VERTICAL_BORDER_PADDING);
+                    assert_eq!(writer.text_size(), (cols, rows));
+                    assert_eq!(writer.scroll_region, (0, rows - 1));
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_write_at() -> TestCase {
+        TestCase {
+            name: "Test writing at a fixed position without moving the cursor",
+            test: || {
+                with_test_screen(|writer| {
+                    writer.save_cursor();
+                    writer.set_cursor(3, 5);
+
+                    writer.write_at(0, 2, format_args!("status {}", 42));
+                    assert_eq!(writer.cursor(), (3, 5));
+                    assert_ne!(row_checksum(writer, 2), 0);
+
+                    // The rest of the row is blanked.
+                    writer.write_at(0, 2, format_args!(""));
+                    assert_eq!(row_checksum(writer, 2), 0);
+
+                    writer.restore_cursor();
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_indent() -> TestCase {
+        TestCase {
+            name: "Test new lines and wrapping going back to the indent column",
+            test: || {
+                with_test_screen(|writer| {
+                    writer.save_cursor();
+                    let cols = writer.text_size().0;
+
+                    writer.set_cursor(0, 3);
+                    writer.clear_to_end();
+                    writer.set_indent(4);
+                    writeln!(writer).unwrap();
+                    assert_eq!(writer.cursor(), (4, 4));
+
+                    // Filling the rest of the row, then wrapping.
+                    for _ in 4..cols {
+                        write!(writer, "x").unwrap();
+                    }
+                    write!(writer, "y").unwrap();
+                    assert_eq!(writer.cursor(), (5, 5));
+
+                    // A carriage return still goes back to the border.
+                    write!(writer, "\r").unwrap();
+                    assert_eq!(writer.cursor(), (0, 5));
+
+                    // Same through escape sequences: at the current column, then explicitly.
+                    writeln!(writer, "ab\x1b[I").unwrap();
+                    assert_eq!(writer.cursor(), (2, 6));
+                    writeln!(writer, "\x1b[1I").unwrap();
+                    assert_eq!(writer.cursor(), (0, 7));
+                });
+            },
+            // NOTE: Even if the test failed, so that the following output is not indented.
+            teardown: || {
+                with_test_screen(|screen| {
+                    screen.set_indent(0);
+                    screen.set_cursor(0, 3);
+                    screen.clear_to_end();
+                    screen.restore_cursor();
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_cursor_blink() -> TestCase {
+        TestCase {
+            name: "Test the blinking cursor never overwrites the text",
+            test: || {
+                with_test_screen(|writer| {
+                    writer.save_cursor();
+
+                    writer.set_cursor(0, 3);
+                    writer.clear_line();
+                    write!(writer, "ab").unwrap();
+                    let text = row_checksum(writer, 3);
+
+                    // Disabled by default.
+                    writer.toggle_cursor();
+                    assert_eq!(writer.cursor_drawn, None);
+
+                    writer.show_cursor(true);
+                    writer.toggle_cursor();
+                    assert_ne!(row_checksum(writer, 3), text);
+                    writer.toggle_cursor();
+                    assert_eq!(row_checksum(writer, 3), text);
+
+                    // Printing over the drawn cursor erases it first.
+                    writer.toggle_cursor();
+                    write!(writer, "c").unwrap();
+                    assert_eq!(writer.cursor_drawn, None);
+                    let printed = row_checksum(writer, 3);
+
+                    writer.show_cursor(false);
+                    writer.set_cursor(0, 4);
+                    writer.clear_line();
+                    write!(writer, "abc").unwrap();
+                    assert_eq!(row_checksum(writer, 4), printed);
+
+                    writer.set_cursor(0, 3);
+                    writer.clear_to_end();
+                    writer.restore_cursor();
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_clear_pattern() -> TestCase {
+        TestCase {
+            name: "Test clearing with a pattern",
+            test: || {
+                let mut writer = test_writer(0xAA);
+                writer.set_cursor(1, 0);
+
+                writer.clear_pattern(|x, y| (x as u8, y as u8, 0x80));
+                assert_eq!(
+                    (writer.cur_x, writer.cur_y),
+                    (writer.h_padding, writer.v_padding)
+                );
+
+                let idx = writer.pixel_offset(TEST_WIDTH - 1, TEST_HEIGHT - 1);
+                assert_eq!(
+                    writer.buffer[idx..idx + 3],
+                    [TEST_WIDTH as u8 - 1, TEST_HEIGHT as u8 - 1, 0x80]
+                );
+                // The stride padding is untouched.
+                let idx = writer.pixel_offset(TEST_WIDTH, 0);
+                assert_eq!(writer.buffer[idx..idx + 3], [0xAA; 3]);
+
+                // Channels are swapped for BGR framebuffers.
+                writer.info.pixel_format = PixelFormat::Bgr;
+                writer.clear_pattern(|_, _| (1, 2, 3));
+                assert_eq!(writer.buffer[0..3], [3, 2, 1]);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_measure_str() -> TestCase {
+        TestCase {
+            name: "Test measuring multi-line strings",
+            test: || {
+                let writer = test_writer(0);
+                let (w, h) = (writer.char_width(), writer.line_height());
+
+                assert_eq!(writer.measure_str(""), (0, 0));
+                assert_eq!(writer.measure_str("abc"), (3 * w, h));
+                assert_eq!(writer.measure_str("ab\nabcde\n\nx"), (5 * w, 4 * h));
+                // The trailing newline starts an empty line.
+                assert_eq!(writer.measure_str("abc\n"), (3 * w, 2 * h));
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_letter_spacing() -> TestCase {
+        TestCase {
+            name: "Test measuring and writing with a letter spacing",
+            test: || {
+                let mut writer = test_writer(0);
+                let glyph = writer.glyph_width();
+                assert_eq!(writer.measure_str("abcd").0, 4 * glyph);
+
+                writer.set_letter_spacing(3);
+                assert_eq!(writer.char_width(), glyph + 3);
+                assert_eq!(writer.measure_str("abcd").0, 4 * (glyph + 3));
+
+                writer.set_letter_spacing(-2);
+                assert_eq!(writer.measure_str("abcd").0, 4 * (glyph - 2));
+
+                // The cursor advances by the same amount, on the real screen since the test
+                // framebuffer is too small to hold a glyph.
+                with_test_screen(|screen| {
+                    screen.save_cursor();
+                    screen.set_letter_spacing(3);
+                    screen.set_cursor(0, 3);
+                    screen.clear_line();
+
+                    let start = screen.cur_x;
+                    write!(screen, "abcd").unwrap();
+                    assert_eq!(screen.cur_x - start, screen.measure_str("abcd").0);
+                    assert_eq!(screen.cursor(), (4, 3));
+
+                    screen.clear_line();
+                    screen.restore_cursor();
+                });
+            },
+            // NOTE: Back to the default, even if the test failed.
+            teardown: || {
+                with_test_screen(|screen| {
+                    screen.set_letter_spacing(CHAR_SPACING);
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_wide_chars() -> TestCase {
+        TestCase {
+            name: "Test wide characters take two columns, and wrap as a whole",
+            test: || {
+                assert!(is_wide('漢') && is_wide('한') && is_wide('Ａ'));
+                assert!(!is_wide('a') && !is_wide('é') && !is_wide('�'));
+
+                let w = test_writer(0).char_width();
+                assert_eq!(test_writer(0).measure_str("a漢b").0, 4 * w);
+
+                with_test_screen(|writer| {
+                    writer.save_cursor();
+                    let cols = writer.text_size().0;
+
+                    // Fits exactly in the last two columns.
+                    writer.set_cursor(cols - 2, 3);
+                    write!(writer, "漢").unwrap();
+                    assert_eq!(writer.cursor(), (cols, 3));
+
+                    // Only one column left, goes to the next line.
+                    writer.set_cursor(cols - 1, 3);
+                    write!(writer, "漢").unwrap();
+                    assert_eq!(writer.cursor(), (2, 4));
+
+                    writer.set_cursor(0, 3);
+                    writer.clear_to_end();
+                    writer.restore_cursor();
+                });
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_with_pixels() -> TestCase {
+        TestCase {
+            name: "Test lending the raw framebuffer",
+            test: || {
+                let mut writer = test_writer(0);
+                assert_eq!(writer.dimensions(), (TEST_WIDTH, TEST_HEIGHT));
+                assert_eq!(writer.pixel_format(), PixelFormat::Rgb);
+                let cursor = (writer.cur_x, writer.cur_y);
+
+                writer.with_pixels(|buffer, info| {
+                    assert_eq!(buffer.len(), TEST_FB_LEN);
+                    assert_eq!(info.stride, TEST_STRIDE);
+
+                    // Last pixel of the second row.
+                    let offset = (info.stride + info.width - 1) * info.bytes_per_pixel;
+                    buffer[offset..offset + info.bytes_per_pixel].fill(0xFF);
+                });
+
+                let offset = writer.pixel_offset(TEST_WIDTH - 1, 1);
+                assert_eq!(writer.buffer[offset..offset + TEST_BPP], [0xFF; TEST_BPP]);
+                assert_eq!((writer.cur_x, writer.cur_y), cursor);
+            },
+            ..Default::default()
+        }
+    }
+}
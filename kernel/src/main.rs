@@ -2,94 +2,214 @@
 #![no_main]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![test_runner(crate::testing::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
 #[macro_use]
 mod io;
 mod allocator;
+#[cfg(feature = "bench")]
+mod bench;
+mod cpu;
+mod error;
 mod interrupts;
+mod memory;
+mod sched;
+mod shell;
+mod statusbar;
 #[cfg(test)]
 mod testing;
+mod time;
 mod utils;
+mod watchdog;
 
 extern crate alloc;
 
-use core::{cell::OnceCell, panic::PanicInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use bootloader_api::{config::Mapping, info::FrameBuffer, BootloaderConfig};
+use bootloader_api::{config::Mapping, BootloaderConfig};
+use error::KernelError;
 use io::{serial::SerialWriter, vga::VGAWriter};
 
-struct U64Cell(OnceCell<u64>);
-// Safety: We're in single thread for now.
-unsafe impl Sync for U64Cell {}
-
-static PHYS_MEM_OFFSET: U64Cell = U64Cell(OnceCell::new());
+/// Number of stack words printed on panic.
+const PANIC_STACK_WORDS: usize = 8;
 
-macro_rules! interrupt {
-    ($num:expr) => {
-        unsafe { core::arch::asm!(concat!("int ", stringify!($num))) }
-    };
-}
+/// Reboot once the panic is reported, instead of halting, e.g. for soak tests. See
+/// `io::rtc::panic_count()` to tell how many times that happened.
+pub static REBOOT_ON_PANIC: AtomicBool = AtomicBool::new(false);
 
 /// This function is called on panic.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    print!("\nPANIC!!! ");
+    // NOTE: This must come first, before formatting anything clobbers the registers.
+    let regs = cpu::Registers::capture();
+
+    // NOTE: Panics the test runner recovers from (e.g. `should_panic` tests) are not counted.
+    #[cfg(test)]
+    let counted = !testing::resumes_panic();
+    #[cfg(not(test))]
+    let counted = true;
+
+    if counted {
+        print!("\nPANIC #{}!!! ", io::rtc::record_panic());
+    } else {
+        print!("\nPANIC!!! ");
+    }
     if let Some(location) = info.location() {
         print!("[{}:{}] ", location.file(), location.line());
     }
 
     println!("{}\n", info.message());
 
-    loop {}
-    io::exit(1);
+    println!("Registers:\n{}", regs);
+    println!("Stack:");
+    // Safety: `rsp` is our own stack, and we're far from its top.
+    unsafe { cpu::print_stack(regs.rsp, PANIC_STACK_WORDS) };
+    cpu::print_backtrace(regs.rbp);
+
+    // Report the failing test and exit QEMU with a failure code (`io::exit()` drains the serial
+    // port first), so that the host does not wait for a timeout.
+    // NOTE: This only returns without the `isa-debug-exit` device.
+    #[cfg(test)]
+    testing::on_panic();
+
+    io::serial::wait_until_done();
+    if REBOOT_ON_PANIC.load(Ordering::Relaxed) {
+        cpu::reboot();
+    }
+
+    loop {
+        unsafe { core::arch::asm!("hlt", options(nomem, nostack)) };
+    }
+}
+
+/// Stops the boot on an error we can't work around.
+fn fatal(err: KernelError) -> ! {
+    panic!("Can't boot: {}", err);
+}
+
+/// Initializes VGA (e.g. text outputs on screen), if we have a screen.
+fn init_screen(boot_info: &mut bootloader_api::BootInfo) -> Result<(), KernelError> {
+    // NOTE: The `FrameBuffer` is moved out of `boot_info`, so that `VGAWriter` is the only owner
+    // of its buffer.
+    let fb = boot_info
+        .framebuffer
+        .take()
+        .ok_or(KernelError::NoFramebuffer)?;
+    VGAWriter::init(fb);
+
+    // Red goes up from left to right, green from top to bottom, blue is constant: swapped
+    // channels or a wrong stride are obvious.
+    #[cfg(feature = "gradient")]
+    io::vga::with_screen(|writer| {
+        let (width, height) = writer.dimensions();
+        writer.clear_pattern(|x, y| ((x * 255 / width) as u8, (y * 255 / height) as u8, 0x40));
+    });
+
+    Ok(())
 }
 
+// NOTE: When testing, we never get past `test_main()`.
+#[cfg_attr(test, allow(unreachable_code))]
 fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
-    // NOTE: We extract the `FrameBuffer` here so that we can still borrow `boot_info` later on
-    let mut owned_fb = unsafe {
-        let bootloader_api::info::Optional::Some(fb) = &mut boot_info.framebuffer else {
-            panic!("Missing framebuffer in boot info.");
-        };
-        core::ptr::read(fb as *mut FrameBuffer)
-    };
-
-    // Initialize VGA and Serial port writing (e.g. text outputs).
-    VGAWriter::init(&mut owned_fb);
-    SerialWriter::init_serial().expect("Failed to initialize Serial writer.");
+    // Initialize VGA first, so that a missing serial port can be reported on screen. Either one is
+    // enough to boot, `print!` skips the writer that is not there.
+    // NOTE: Nothing is logged if setting up the screen panics, since serial is not up yet.
+    let screen = init_screen(boot_info);
+    if let Err(err) = SerialWriter::init_serial() {
+        println!("WARNING: {}, only logging to the screen.", err);
+    }
+    if let Err(err) = screen {
+        println!("WARNING: {}, only logging to serial.", err);
+    }
 
-    #[cfg(test)]
-    {
-        test_main();
-        loop {}
-        io::exit(1);
+    utils::cpuid::print_info();
+    let avx = cpu::enable_sse();
+    println!("SSE enabled (AVX: {})", avx);
+    let nx = cpu::enable_memory_protection();
+    println!("Write protection enabled (NX: {})", nx);
+    match time::calibrate() {
+        Some(cycles_per_us) => {
+            println!("TSC: {} cycles/us", cycles_per_us);
+        }
+        None => {
+            println!("WARNING: No TSC, delays will be approximate.");
+        }
     }
+    println!("Date: {}", io::rtc::read_datetime());
+    println!("Panics so far: {}", io::rtc::panic_count());
 
     // We only work using mapped physical memory.
-    let bootloader_api::info::Optional::Some(physical_memory_offset) =
-        boot_info.physical_memory_offset
-    else {
-        panic!("Physical memory is not mapped !!");
-    };
+    // NOTE: This is done before running the tests since paging needs it.
+    let physical_memory_offset = boot_info
+        .physical_memory_offset
+        .into_option()
+        .ok_or(KernelError::NoPhysMem)
+        .unwrap_or_else(|err| fatal(err));
     println!("Physical memory offset: {:#X}", physical_memory_offset);
 
-    // Safety: This is the first time we access `PHYS_MEM_OFFSET`.
-    let _ = PHYS_MEM_OFFSET.0.set(physical_memory_offset);
+    memory::set_phys_offset(physical_memory_offset);
+    memory::set_memory_map(&boot_info.memory_regions);
+
+    #[cfg(test)]
+    {
+        // The watchdog runs from the timer IRQ, so it needs both to catch a hung test.
+        interrupts::init();
+        time::start_timer();
+
+        // NOTE: `test_runner()` exits QEMU with a code reflecting the results, so we only get
+        // here if that did not work, e.g. without the `isa-debug-exit` device.
+        test_main();
+        println!("ERROR: The tests are done, but QEMU did not exit.");
+
+        loop {
+            unsafe { core::arch::asm!("hlt", options(nomem, nostack)) };
+        }
+    }
 
     println!("HElllozz");
     println!("AGAIN");
 
+    memory::print_summary(boot_info);
+
     // Initialize allocator.
-    allocator::init(boot_info);
+    let heap = allocator::init(boot_info).unwrap_or_else(|err| fatal(err));
     allocator::print_free_segments();
 
+    // Initialize the physical frame allocator with whatever the heap did not take.
+    memory::frame_alloc::init(boot_info, heap);
+
+    // Move to our own stack, which has a guard page to catch overflows.
+    // NOTE: Mapping it needs the frame allocator, see `memory::stack` for the other constraints.
+    let stack_top = memory::stack::alloc_kernel_stack().unwrap_or_else(|err| fatal(err));
+    unsafe { memory::stack::switch_stack(stack_top, kernel_run) }
+}
+
+/// Rest of `kernel_main`, running on the stack from `memory::stack::alloc_kernel_stack()`.
+extern "C" fn kernel_run() -> ! {
     // Initialize interrupts
     interrupts::init();
+    io::keyboard::init();
+    if let Err(err) = io::mouse::init() {
+        println!("WARNING: No mouse: {}", err);
+    }
+    time::start_timer();
+    watchdog::pet();
+    statusbar::init();
+
+    #[cfg(feature = "bench")]
+    {
+        bench::bench_runner(bench::BENCHES);
+        io::exit(0);
+    }
 
     println!("It did not crash. Triggering interrupt");
 
-    interrupt!(3);
+    interrupts::raise(3);
 
     println!("Done");
 
@@ -107,14 +227,30 @@ fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
         println!("v = {:?}", v1);
     }
 
-    loop {}
-    io::exit(0);
+    sched::demo();
+
+    // NOTE: The shell stays on our stack, for its guard page. It idles while waiting for input.
+    shell::run();
+    println!("Left the shell, idling.");
+    cpu::idle()
 }
 
+/// Size of the stack the bootloader sets up for `kernel_main`.
+///
+/// NOTE: The default (80 KiB) is tight: everything up to `kernel_run` (including the tests, the
+/// panic handler's backtrace and the allocator/paging setup) runs on it, and a stack overflow
+/// there hits no guard page of ours. 512 KiB leaves plenty of margin and is still negligible
+/// compared to the memory QEMU gives us.
+const BOOT_STACK_SIZE: u64 = 512 * 1024;
+
 // We force physical memory mapping to our kernel.
 pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
     config.mappings.physical_memory = Some(Mapping::Dynamic);
+    config.kernel_stack_size = BOOT_STACK_SIZE;
+    // Let the bootloader place the stack, so that it does not collide with our fixed mappings
+    // (e.g. `memory::stack::KERNEL_STACK_TOP`).
+    config.mappings.kernel_stack = Mapping::Dynamic;
     config
 };
 
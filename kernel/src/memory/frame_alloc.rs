@@ -0,0 +1,167 @@
+//! Physical frame allocator.
+//!
+//! For now, this is a simple bump allocator over the usable memory regions given by the
+//! bootloader: 4KiB frames are handed out in order and never freed.
+//!
+//! TODO:
+//! - Add a free list so that frames can be given back, `next_frame` would pop from it first.
+
+use core::ops::Range;
+
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+
+use super::paging::PAGE_SIZE;
+use crate::utils::sync::Mutex;
+
+pub struct FrameAllocator {
+    regions: &'static [MemoryRegion],
+
+    /// Physical memory that must never be handed out (e.g. the kernel and the heap).
+    reserved: [Range<u64>; 2],
+
+    /// Index of the region we're currently allocating from.
+    region_idx: usize,
+    /// Next frame to try in the current region.
+    next: u64,
+}
+
+impl FrameAllocator {
+    pub fn new(regions: &'static [MemoryRegion], reserved: [Range<u64>; 2]) -> Self {
+        Self {
+            regions,
+            reserved,
+            region_idx: 0,
+            next: 0,
+        }
+    }
+
+    /// Returns the physical address of a free frame, or `None` when we ran out of memory.
+    pub fn next_frame(&mut self) -> Option<u64> {
+        while let Some(region) = self.regions.get(self.region_idx) {
+            if region.kind == MemoryRegionKind::Usable {
+                let mut frame = self.next.max(region.start).next_multiple_of(PAGE_SIZE);
+
+                while frame + PAGE_SIZE <= region.end {
+                    // Jump over the reserved range this frame overlaps with, if any.
+                    if let Some(reserved) = self
+                        .reserved
+                        .iter()
+                        .find(|r| r.start < frame + PAGE_SIZE && frame < r.end)
+                    {
+                        frame = reserved.end.next_multiple_of(PAGE_SIZE);
+                        continue;
+                    }
+
+                    self.next = frame + PAGE_SIZE;
+                    return Some(frame);
+                }
+            }
+
+            // This region is exhausted.
+            self.region_idx += 1;
+            self.next = 0;
+        }
+
+        None
+    }
+}
+
+/// NOTE: Interrupts are disabled while it is locked, so that a handler can't deadlock on it.
+static FRAME_ALLOCATOR: Mutex<Option<FrameAllocator>> = Mutex::new(None);
+
+/// Initializes the global frame allocator, skipping the kernel and the heap.
+pub fn init(boot_info: &'static bootloader_api::BootInfo, heap: Range<u64>) {
+    let kernel = super::kernel_range(boot_info);
+
+    let allocator = FrameAllocator::new(&boot_info.memory_regions, [kernel, heap]);
+    crate::interrupts::without_interrupts(|| {
+        *FRAME_ALLOCATOR.lock() = Some(allocator);
+    });
+}
+
+/// Returns the physical address of a free frame from the global frame allocator.
+///
+/// NOTE: Returns `None` if `init()` was not called yet.
+pub fn next_frame() -> Option<u64> {
+    crate::interrupts::without_interrupts(|| FRAME_ALLOCATOR.lock().as_mut()?.next_frame())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    static REGIONS: [MemoryRegion; 3] = [
+        MemoryRegion {
+            start: 0x0,
+            end: 0x10000,
+            kind: MemoryRegionKind::Usable,
+        },
+        MemoryRegion {
+            start: 0x10000,
+            end: 0x20000,
+            kind: MemoryRegionKind::Bootloader,
+        },
+        // Not page aligned on purpose.
+        MemoryRegion {
+            start: 0x20000,
+            end: 0x25800,
+            kind: MemoryRegionKind::Usable,
+        },
+    ];
+
+    fn count_frames(allocator: &mut FrameAllocator) -> usize {
+        let mut count = 0;
+        let mut last = None;
+
+        while let Some(frame) = allocator.next_frame() {
+            assert!(frame.is_multiple_of(PAGE_SIZE));
+            // Frames are handed out in order, and only once.
+            assert!(last.is_none_or(|last| last < frame));
+            last = Some(frame);
+
+            count += 1;
+        }
+
+        count
+    }
+
+    #[test_case]
+    fn test_frame_count() -> TestCase {
+        TestCase {
+            name: "Test the frame allocator hands out the whole usable memory",
+            test: || {
+                let mut allocator = FrameAllocator::new(&REGIONS, [0..0, 0..0]);
+
+                // 16 frames in the first region, 5 in the last one.
+                assert_eq!(count_frames(&mut allocator), 0x10000 / 4096 + 0x5000 / 4096);
+                assert_eq!(allocator.next_frame(), None);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_frame_reserved() -> TestCase {
+        TestCase {
+            name: "Test the frame allocator skips the reserved memory",
+            test: || {
+                let kernel = 0x3000..0x4800;
+                let heap = 0x22000..0x23000;
+                let mut allocator = FrameAllocator::new(&REGIONS, [kernel.clone(), heap.clone()]);
+
+                let mut count = 0;
+                while let Some(frame) = allocator.next_frame() {
+                    for reserved in [&kernel, &heap] {
+                        assert!(frame + PAGE_SIZE <= reserved.start || reserved.end <= frame);
+                    }
+                    count += 1;
+                }
+
+                // The kernel overlaps 2 frames, the heap 1.
+                assert_eq!(count, 16 - 2 + 5 - 1);
+            },
+            ..Default::default()
+        }
+    }
+}
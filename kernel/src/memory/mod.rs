@@ -0,0 +1,330 @@
+pub mod frame_alloc;
+pub mod paging;
+pub mod stack;
+
+use core::{
+    cell::UnsafeCell,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+
+use crate::utils::fmt::ByteSize;
+
+/// `PHYS_OFFSET` before `set_phys_offset()`, never a valid offset since it's not page aligned.
+const UNSET: u64 = u64::MAX;
+
+/// Virtual address at which the bootloader mapped the whole physical memory.
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(UNSET);
+
+/// Records where the physical memory is mapped. Must be called once, before anything uses
+/// `phys_offset()` (e.g. paging and the allocator).
+pub fn set_phys_offset(offset: u64) {
+    assert_ne!(offset, UNSET, "Invalid physical memory offset");
+    PHYS_OFFSET
+        .compare_exchange(UNSET, offset, Ordering::Relaxed, Ordering::Relaxed)
+        .expect("The physical memory offset was already set");
+}
+
+/// Virtual address at which the whole physical memory is mapped.
+pub fn phys_offset() -> u64 {
+    try_phys_offset().expect("Physical memory offset used before `memory::set_phys_offset()`")
+}
+
+/// Same as `phys_offset()`, but `None` if it's not set yet instead of panicking.
+pub fn try_phys_offset() -> Option<u64> {
+    match PHYS_OFFSET.load(Ordering::Relaxed) {
+        UNSET => None,
+        offset => Some(offset),
+    }
+}
+
+/// NOTE: We use `UnsafeCell` to achieve interior mutability here.
+struct MemoryMapHolder(UnsafeCell<&'static [MemoryRegion]>);
+
+// Safety: Only written once by `set_memory_map()`, before anything reads it.
+unsafe impl Sync for MemoryMapHolder {}
+
+/// Memory map given by the bootloader, empty until `set_memory_map()`.
+static MEMORY_MAP: MemoryMapHolder = MemoryMapHolder(UnsafeCell::new(&[]));
+
+/// Records the memory map, so that `region_as_slice()` can check its ranges. Must be called once,
+/// right after `set_phys_offset()`.
+pub fn set_memory_map(regions: &'static [MemoryRegion]) {
+    let map = unsafe { &mut *MEMORY_MAP.0.get() };
+    assert!(map.is_empty(), "The memory map was already set");
+    *map = regions;
+}
+
+/// Whether `range` is within usable memory, e.g. a single usable region, or adjacent ones since
+/// the bootloader may split contiguous memory.
+fn is_usable(regions: &[MemoryRegion], range: &Range<u64>) -> bool {
+    let mut run = 0..0;
+    for region in regions {
+        if region.kind != MemoryRegionKind::Usable {
+            continue;
+        }
+
+        if region.start == run.end {
+            run.end = region.end;
+        } else {
+            run = region.start..region.end;
+        }
+        if run.start <= range.start && range.end <= run.end {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Returns the `len` bytes of physical memory from `phys_start`, through the physical memory
+/// mapping.
+///
+/// NOTE: Debug builds check that the range is usable memory according to the memory map, e.g.
+/// not MMIO nor something the bootloader still uses.
+///
+/// Safety: Nothing else may access that memory while the slice is alive, e.g. it must not be
+/// handed out by the frame allocator, nor given to `region_as_slice()` again.
+pub unsafe fn region_as_slice(phys_start: u64, len: usize) -> &'static mut [u8] {
+    let range = phys_start
+        ..phys_start
+            .checked_add(len as u64)
+            .expect("Physical range overflows");
+    debug_assert!(
+        is_usable(*MEMORY_MAP.0.get(), &range),
+        "[{:#x} -> {:#x}] is not usable memory",
+        range.start,
+        range.end
+    );
+
+    core::slice::from_raw_parts_mut(phys_to_virt(phys_start), len)
+}
+
+/// Pointer to the physical address `phys`, through the physical memory mapping.
+pub fn phys_to_virt(phys: u64) -> *mut u8 {
+    (phys + phys_offset()) as *mut u8
+}
+
+/// Physical address `virt` is mapped to.
+///
+/// NOTE: This walks the page tables, so it works for any mapped address (e.g. the kernel or its
+/// stack), not only the physical memory mapping. Panics if `virt` is not mapped at all.
+#[allow(unused)]
+pub fn virt_to_phys(virt: *const u8) -> u64 {
+    let virt = virt as u64;
+
+    paging::translate(virt).unwrap_or_else(|| panic!("{:#x} is not mapped", virt))
+}
+
+/// Physical memory occupied by the kernel.
+pub fn kernel_range(boot_info: &bootloader_api::BootInfo) -> Range<u64> {
+    let end = boot_info
+        .kernel_addr
+        .checked_add(boot_info.kernel_len)
+        .expect("The kernel end overflows, the boot info is corrupted");
+
+    boot_info.kernel_addr..end
+}
+
+/// Totals of the memory map given by the bootloader, in bytes.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MemorySummary {
+    pub total: u64,
+    pub usable: u64,
+    pub kernel: u64,
+    /// Largest run of adjacent usable regions.
+    pub largest_usable: u64,
+}
+
+impl MemorySummary {
+    pub fn new(regions: &[MemoryRegion], kernel_len: u64) -> Self {
+        let mut summary = Self {
+            kernel: kernel_len,
+            ..Default::default()
+        };
+
+        // Usable run we're currently in, as the bootloader may split contiguous memory.
+        let mut run = 0..0;
+        for region in regions {
+            summary.total += region.end - region.start;
+
+            if region.kind != MemoryRegionKind::Usable {
+                continue;
+            }
+            summary.usable += region.end - region.start;
+
+            if region.start == run.end {
+                run.end = region.end;
+            } else {
+                run = region.start..region.end;
+            }
+            summary.largest_usable = summary.largest_usable.max(run.end - run.start);
+        }
+
+        summary
+    }
+}
+
+/// Prints how much memory the bootloader reported, e.g. to check QEMU's `-m`.
+pub fn print_summary(boot_info: &bootloader_api::BootInfo) {
+    let summary = MemorySummary::new(&boot_info.memory_regions, boot_info.kernel_len);
+
+    println!("\n----- Memory Summary -----");
+    println!("Total RAM: {}", ByteSize(summary.total));
+    println!("Usable RAM: {}", ByteSize(summary.usable));
+    println!("Kernel: {}", ByteSize(summary.kernel));
+    println!(
+        "Largest usable region: {}",
+        ByteSize(summary.largest_usable)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    /// Page owned by `test_region_as_slice()`, so that its slice can't alias anything else.
+    #[repr(C, align(4096))]
+    struct TestPage(UnsafeCell<[u8; 0x1000]>);
+
+    // Safety: Only used by `test_region_as_slice()`.
+    unsafe impl Sync for TestPage {}
+
+    static TEST_PAGE: TestPage = TestPage(UnsafeCell::new([0; 0x1000]));
+
+    /// Memory map marking `TEST_PAGE` as usable, filled in by `test_region_as_slice()`.
+    struct TestMap(UnsafeCell<[MemoryRegion; 1]>);
+
+    // Safety: Only used by `test_region_as_slice()`.
+    unsafe impl Sync for TestMap {}
+
+    static TEST_MAP: TestMap = TestMap(UnsafeCell::new([MemoryRegion {
+        start: 0,
+        end: 0,
+        kind: MemoryRegionKind::Usable,
+    }]));
+
+    /// The real memory map, while `TEST_MAP` replaces it.
+    static SAVED_MAP: MemoryMapHolder = MemoryMapHolder(UnsafeCell::new(&[]));
+
+    #[test_case]
+    fn test_memory_summary() -> TestCase {
+        TestCase {
+            name: "Test summing up the memory map",
+            test: || {
+                let regions = [
+                    MemoryRegion {
+                        start: 0x0,
+                        end: 0x1000,
+                        kind: MemoryRegionKind::Usable,
+                    },
+                    MemoryRegion {
+                        start: 0x1000,
+                        end: 0x3000,
+                        kind: MemoryRegionKind::Bootloader,
+                    },
+                    // These two are adjacent, so they count as a single region.
+                    MemoryRegion {
+                        start: 0x3000,
+                        end: 0x5000,
+                        kind: MemoryRegionKind::Usable,
+                    },
+                    MemoryRegion {
+                        start: 0x5000,
+                        end: 0x6000,
+                        kind: MemoryRegionKind::Usable,
+                    },
+                    MemoryRegion {
+                        start: 0x8000,
+                        end: 0xA000,
+                        kind: MemoryRegionKind::Usable,
+                    },
+                ];
+
+                assert_eq!(
+                    MemorySummary::new(&regions, 0x2000),
+                    MemorySummary {
+                        total: 0x8000,
+                        usable: 0x6000,
+                        kernel: 0x2000,
+                        largest_usable: 0x3000,
+                    }
+                );
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_phys_virt_round_trip() -> TestCase {
+        TestCase {
+            name: "Test translating between physical and virtual addresses",
+            test: || {
+                let virt = phys_to_virt(0x5123);
+                assert_eq!(virt as u64, phys_offset() + 0x5123);
+                assert_eq!(virt_to_phys(virt), 0x5123);
+
+                // Outside of the physical memory mapping, e.g. on the stack.
+                let value: u64 = 0xDEADBEEF;
+                let phys = virt_to_phys(&value as *const u64 as *const u8);
+                assert_eq!(unsafe { *(phys_to_virt(phys) as *const u64) }, 0xDEADBEEF);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_region_as_slice() -> TestCase {
+        TestCase {
+            name: "Test checking and mapping physical memory ranges",
+            test: || {
+                let region = |start, end, kind| MemoryRegion { start, end, kind };
+                let regions = [
+                    region(0x0, 0x1000, MemoryRegionKind::Bootloader),
+                    // Adjacent, so a range may span both.
+                    region(0x1000, 0x3000, MemoryRegionKind::Usable),
+                    region(0x3000, 0x4000, MemoryRegionKind::Usable),
+                    region(0x4000, 0x5000, MemoryRegionKind::UnknownBios(1)),
+                ];
+                assert!(is_usable(&regions, &(0x1000..0x4000)));
+                assert!(is_usable(&regions, &(0x2800..0x3800)));
+                assert!(!is_usable(&regions, &(0x0800..0x2000)));
+                assert!(!is_usable(&regions, &(0x3800..0x4001)));
+
+                let phys = virt_to_phys(TEST_PAGE.0.get() as *const u8);
+                let slice = unsafe { region_as_slice(phys, 0x1000) };
+                assert_eq!(slice.as_ptr(), phys_to_virt(phys) as *const u8);
+                assert_eq!(slice.len(), 0x1000);
+
+                // Both are views of the same memory.
+                slice[0x123] = 0x42;
+                assert_eq!(unsafe { (*TEST_PAGE.0.get())[0x123] }, 0x42);
+            },
+            // The real memory map has no usable page we own, so we swap it for one that marks
+            // our own page as usable (it's part of the kernel otherwise).
+            setup: || unsafe {
+                let phys = virt_to_phys(TEST_PAGE.0.get() as *const u8);
+                let test_map = &mut *TEST_MAP.0.get();
+                test_map[0].start = phys;
+                test_map[0].end = phys + 0x1000;
+                *SAVED_MAP.0.get() = core::mem::replace(&mut *MEMORY_MAP.0.get(), test_map);
+            },
+            teardown: || unsafe { *MEMORY_MAP.0.get() = *SAVED_MAP.0.get() },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_set_phys_offset_twice() -> TestCase {
+        TestCase {
+            name: "Test setting the physical memory offset twice panics",
+            // NOTE: `kernel_main` sets it before running the tests.
+            test: || set_phys_offset(phys_offset()),
+            should_panic: true,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! 4-level paging: PML4 -> PDPT -> PD -> PT.
+//!
+//! Page tables hold physical addresses. We access them through the bootloader's mapping of the
+//! whole physical memory, e.g. at `memory::phys_offset()`.
+//!
+//! Missing intermediate tables are created with frames from the frame allocator.
+
+use core::{arch::asm, ops::BitOr};
+
+use crate::utils::bits::GetBit;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Number of entries in any page table.
+const ENTRY_COUNT: usize = 512;
+
+/// Bits 51..12 of an entry hold the physical address of the frame (or of the next table).
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Flags of a page table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags(u64);
+
+#[allow(unused)]
+impl PageFlags {
+    pub const PRESENT: Self = Self(1 << 0);
+    pub const WRITABLE: Self = Self(1 << 1);
+    pub const USER: Self = Self(1 << 2);
+    pub const WRITE_THROUGH: Self = Self(1 << 3);
+    pub const NO_CACHE: Self = Self(1 << 4);
+    /// Set in a PDPT or PD entry to map a 1GiB or 2MiB page.
+    pub const HUGE: Self = Self(1 << 7);
+    pub const GLOBAL: Self = Self(1 << 8);
+    /// NOTE: Reserved (e.g. faults) until `cpu::enable_memory_protection()` enabled NX.
+    pub const NO_EXECUTE: Self = Self(1 << 63);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PageFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[repr(transparent)]
+struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    fn is_present(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    fn is_huge(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    fn addr(&self) -> u64 {
+        self.0 & ADDR_MASK
+    }
+
+    fn set(&mut self, addr: u64, flags: PageFlags) {
+        self.0 = (addr & ADDR_MASK) | flags.0;
+    }
+}
+
+#[repr(C, align(4096))]
+struct PageTable([PageTableEntry; ENTRY_COUNT]);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// Addresses must be page aligned.
+    Unaligned,
+    /// An intermediate table was missing and there is no free frame left to create it.
+    OutOfFrames,
+    /// The address is already part of a huge page.
+    HugePage,
+    AlreadyMapped,
+    NotMapped,
+}
+
+/// Returns the physical address of the active PML4, read from CR3.
+fn active_pml4() -> u64 {
+    let cr3: u64;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    }
+
+    cr3 & ADDR_MASK
+}
+
+/// Converts a physical address to a pointer through the physical memory mapping.
+fn phys_to_ptr<T>(phys: u64) -> *mut T {
+    super::phys_to_virt(phys) as *mut T
+}
+
+/// Index of `virt` in the table of the given level (4 = PML4, 1 = PT).
+fn table_index(virt: u64, level: u32) -> usize {
+    virt.get_bits(11 + 9 * level, 9) as usize
+}
+
+/// Size of the memory mapped by a single entry of the table at `level`.
+fn entry_size(level: u32) -> u64 {
+    1 << (12 + 9 * (level - 1))
+}
+
+/// Invalidates the TLB entry of the page containing `virt`.
+fn flush(virt: u64) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) virt, options(nostack, preserves_flags));
+    }
+}
+
+/// Walks the page tables to find the physical address `virt` is mapped to.
+pub fn translate(virt: u64) -> Option<u64> {
+    let mut table: *mut PageTable = phys_to_ptr(active_pml4());
+
+    for level in (1..=4).rev() {
+        let entry = unsafe { &(*table).0[table_index(virt, level)] };
+
+        if !entry.is_present() {
+            return None;
+        }
+
+        // Either a 4KiB page, or a huge one (2MiB at level 2, 1GiB at level 3).
+        if level == 1 || entry.is_huge() {
+            let size = entry_size(level);
+
+            return Some((entry.addr() & !(size - 1)) + (virt & (size - 1)));
+        }
+
+        table = phys_to_ptr(entry.addr());
+    }
+
+    unreachable!()
+}
+
+/// Maps the 4KiB page at `virt` to the frame at `phys`.
+#[allow(unused)]
+pub fn map_page(virt: u64, phys: u64, flags: PageFlags) -> Result<(), MapError> {
+    if !virt.is_multiple_of(PAGE_SIZE) || !phys.is_multiple_of(PAGE_SIZE) {
+        return Err(MapError::Unaligned);
+    }
+
+    let mut table: *mut PageTable = phys_to_ptr(active_pml4());
+
+    for level in (2..=4).rev() {
+        let entry = unsafe { &mut (*table).0[table_index(virt, level)] };
+
+        if !entry.is_present() {
+            let frame = super::frame_alloc::next_frame().ok_or(MapError::OutOfFrames)?;
+
+            // A new table must not contain garbage entries.
+            unsafe { phys_to_ptr::<u8>(frame).write_bytes(0, PAGE_SIZE as usize) };
+            entry.set(frame, PageFlags::PRESENT);
+        }
+        if entry.is_huge() {
+            return Err(MapError::HugePage);
+        }
+
+        // The parent entries must allow whatever the page allows.
+        entry.0 |= flags.0 & (PageFlags::WRITABLE | PageFlags::USER).0;
+
+        table = phys_to_ptr(entry.addr());
+    }
+
+    let entry = unsafe { &mut (*table).0[table_index(virt, 1)] };
+    if entry.is_present() {
+        return Err(MapError::AlreadyMapped);
+    }
+
+    entry.set(phys, flags | PageFlags::PRESENT);
+    flush(virt);
+
+    Ok(())
+}
+
+/// Unmaps the 4KiB page at `virt` and returns the frame it was mapped to.
+///
+/// NOTE: The frame is not given back to the frame allocator and empty tables are not freed.
+#[allow(unused)]
+pub fn unmap_page(virt: u64) -> Result<u64, MapError> {
+    if !virt.is_multiple_of(PAGE_SIZE) {
+        return Err(MapError::Unaligned);
+    }
+
+    let mut table: *mut PageTable = phys_to_ptr(active_pml4());
+
+    for level in (2..=4).rev() {
+        let entry = unsafe { &(*table).0[table_index(virt, level)] };
+
+        if !entry.is_present() {
+            return Err(MapError::NotMapped);
+        }
+        if entry.is_huge() {
+            return Err(MapError::HugePage);
+        }
+
+        table = phys_to_ptr(entry.addr());
+    }
+
+    let entry = unsafe { &mut (*table).0[table_index(virt, 1)] };
+    if !entry.is_present() {
+        return Err(MapError::NotMapped);
+    }
+
+    let frame = entry.addr();
+    entry.0 = 0;
+    flush(virt);
+
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_translate() -> TestCase {
+        TestCase {
+            name: "Test translating virtual addresses through the page tables",
+            test: || {
+                // The physical memory mapping maps every physical address at a fixed offset.
+                let offset = crate::memory::phys_offset();
+                assert_eq!(translate(offset + 0x5000), Some(0x5000));
+                assert_eq!(translate(offset + 0x5123), Some(0x5123));
+
+                // Reading back a stack variable through its physical address.
+                let value: u64 = 0xDEADBEEF;
+                let phys = translate(&value as *const u64 as u64).expect("Stack is not mapped");
+                assert_eq!(unsafe { *phys_to_ptr::<u64>(phys) }, 0xDEADBEEF);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_table_index() -> TestCase {
+        TestCase {
+            name: "Test splitting a virtual address into table indices",
+            test: || {
+                let virt = (3 << 39) | (5 << 30) | (7 << 21) | (9 << 12) | 0x123;
+                assert_eq!(table_index(virt, 4), 3);
+                assert_eq!(table_index(virt, 3), 5);
+                assert_eq!(table_index(virt, 2), 7);
+                assert_eq!(table_index(virt, 1), 9);
+            },
+            ..Default::default()
+        }
+    }
+}
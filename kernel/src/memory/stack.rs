@@ -0,0 +1,84 @@
+//! Kernel stack, with a guard page below it.
+//!
+//! The bootloader does not tell us much about the stack it gives us, so once the frame allocator
+//! is up we map our own, `KERNEL_STACK_SIZE` bytes right below `KERNEL_STACK_TOP`, and switch to
+//! it with `switch_stack()`. Nothing is ever mapped in the page below it: overflowing the stack
+//! page faults there instead of silently overwriting whatever lies below.
+//!
+//! NOTE: Ordering matters:
+//! - Mapping the stack needs the frame allocator, e.g. `alloc_kernel_stack()` must come after
+//!   `allocator::init()` and `frame_alloc::init()`, which thus run on the bootloader's stack.
+//! - Nothing on the bootloader's stack may be used after `switch_stack()`.
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{
+    frame_alloc,
+    paging::{self, PageFlags, PAGE_SIZE},
+};
+use crate::error::KernelError;
+
+/// Size of the kernel stack, without its guard page.
+pub const KERNEL_STACK_SIZE: u64 = 64 * 1024;
+
+/// The kernel stack is mapped right below this.
+///
+/// NOTE: This is the first address of the 300th PML4 entry, which the bootloader does not use
+/// (`alloc_kernel_stack()` fails otherwise).
+const KERNEL_STACK_TOP: u64 = 0xFFFF_9600_0000_0000;
+
+/// Address of the unmapped guard page, 0 if `alloc_kernel_stack()` was not called.
+static GUARD_PAGE: AtomicU64 = AtomicU64::new(0);
+
+/// Maps a zeroed `KERNEL_STACK_SIZE` bytes stack, and returns its top.
+pub fn alloc_kernel_stack() -> Result<u64, KernelError> {
+    let bottom = KERNEL_STACK_TOP - KERNEL_STACK_SIZE;
+
+    for page in (bottom..KERNEL_STACK_TOP).step_by(PAGE_SIZE as usize) {
+        let frame = frame_alloc::next_frame().ok_or(KernelError::AllocFailed)?;
+        paging::map_page(page, frame, PageFlags::WRITABLE)?;
+
+        unsafe { (page as *mut u8).write_bytes(0, PAGE_SIZE as usize) };
+    }
+
+    let guard = bottom - PAGE_SIZE;
+    assert!(
+        paging::translate(guard).is_none(),
+        "The kernel stack guard page is mapped"
+    );
+    GUARD_PAGE.store(guard, Ordering::Relaxed);
+
+    println!(
+        "Kernel stack: {:#x} - {:#x}, guard page at {:#x}",
+        bottom, KERNEL_STACK_TOP, guard
+    );
+
+    Ok(KERNEL_STACK_TOP)
+}
+
+/// Switches to the stack whose top is `stack_top`, and calls `f` on it.
+///
+/// Safety: `stack_top` must be the 16 bytes aligned top of a mapped stack. Since `f` never
+/// returns, the current stack is abandoned.
+pub unsafe fn switch_stack(stack_top: u64, f: extern "C" fn() -> !) -> ! {
+    asm!(
+        "mov rsp, {stack_top}",
+        // The backtrace stops at a null `rbp`.
+        "xor ebp, ebp",
+        "call {f}",
+        "ud2",
+        stack_top = in(reg) stack_top,
+        f = in(reg) f,
+        options(noreturn)
+    )
+}
+
+/// Whether `addr` lies within the stack guard page, e.g. a fault there is a stack overflow.
+pub fn is_guard_page(addr: u64) -> bool {
+    let guard = GUARD_PAGE.load(Ordering::Relaxed);
+
+    guard != 0 && (guard..guard + PAGE_SIZE).contains(&addr)
+}
@@ -0,0 +1,281 @@
+//! Cooperative scheduler: a task runs until it calls `yield_now()`, which switches to the next
+//! ready one, round robin.
+//!
+//! Whatever calls `yield_now()` first (e.g. `kernel_run`) is task 0, and keeps the stack it runs
+//! on. The other tasks are started by `spawn()`, each on one of the `TASK_STACKS`.
+//!
+//! Switching only saves the callee-saved registers and `rsp` on the task's own stack (see
+//! `switch_context()`): everything else was already saved by the compiler around the call.
+//!
+//! The timer IRQ never switches tasks, it only flags that the current one ran for a whole slice
+//! (see `should_yield()`), e.g. for long loops to give the others a turn.
+//!
+//! NOTE:
+//! - Task stacks have no guard page, overflowing one silently corrupts the next one.
+//! - SSE registers are not saved, tasks must not use them (the kernel is soft-float anyway).
+
+use core::{
+    arch::naked_asm,
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Maximum number of tasks, task 0 included.
+const MAX_TASKS: usize = 4;
+
+/// Stack size of the spawned tasks.
+const TASK_STACK_SIZE: usize = 16 * 1024;
+
+/// Timer ticks after which `should_yield()` tells the current task to yield.
+const SLICE_TICKS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TaskState {
+    /// Never used, or the task returned.
+    Free,
+    /// Running, or waiting for its turn.
+    Ready,
+}
+
+#[derive(Clone, Copy)]
+struct Task {
+    state: TaskState,
+    /// `None` for task 0, which was not started by `spawn()`.
+    entry: Option<fn()>,
+    /// Saved by `switch_context()` while the task is not running.
+    rsp: u64,
+}
+
+impl Task {
+    const FREE: Self = Self {
+        state: TaskState::Free,
+        entry: None,
+        rsp: 0,
+    };
+}
+
+struct Scheduler {
+    tasks: [Task; MAX_TASKS],
+    current: usize,
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        let mut tasks = [Task::FREE; MAX_TASKS];
+        tasks[0].state = TaskState::Ready;
+
+        Self { tasks, current: 0 }
+    }
+
+    /// Next ready task after the current one, if any other is.
+    fn next_ready(&self) -> Option<usize> {
+        (1..MAX_TASKS)
+            .map(|i| (self.current + i) % MAX_TASKS)
+            .find(|&i| self.tasks[i].state == TaskState::Ready)
+    }
+}
+
+/// NOTE: We use `UnsafeCell` to achieve interior mutability here.
+struct SchedulerHolder(UnsafeCell<Scheduler>);
+
+// Safety: Only used by the running task, never from interrupt handlers.
+unsafe impl Sync for SchedulerHolder {}
+
+static SCHEDULER: SchedulerHolder = SchedulerHolder(UnsafeCell::new(Scheduler::new()));
+
+/// Stacks of tasks 1 and up, task 0 keeps its own.
+#[repr(C, align(16))]
+struct TaskStacks(UnsafeCell<[[u8; TASK_STACK_SIZE]; MAX_TASKS - 1]>);
+
+// Safety: Each stack is only used by the task in the matching slot.
+unsafe impl Sync for TaskStacks {}
+
+static TASK_STACKS: TaskStacks = TaskStacks(UnsafeCell::new([[0; TASK_STACK_SIZE]; MAX_TASKS - 1]));
+
+/// Set by the timer IRQ once a slice is over, cleared by `yield_now()`.
+static RESCHEDULE: AtomicBool = AtomicBool::new(false);
+
+/// Starts `entry` as a new task. It first runs once the current task yields.
+///
+/// Returns the task's index, or an error if `MAX_TASKS` are already running.
+pub fn spawn(entry: fn()) -> Result<usize, &'static str> {
+    let sched = unsafe { &mut *SCHEDULER.0.get() };
+    let slot = (1..MAX_TASKS)
+        .find(|&i| sched.tasks[i].state == TaskState::Free)
+        .ok_or("No free task slot")?;
+
+    // Safety: The slot is free, so nothing runs on its stack anymore.
+    let stack = unsafe { &mut (*TASK_STACKS.0.get())[slot - 1] };
+    let top = stack.as_mut_ptr_range().end as u64;
+
+    // What `switch_context()` pops: the callee-saved registers (a null `rbp` stops backtraces),
+    // then the return address. The last word stands for `task_entry()`'s own return address, so
+    // that the stack is aligned as after a `call`.
+    let entry_addr = task_entry as extern "C" fn() -> ! as usize as u64;
+    let frame: [u64; 8] = [0, 0, 0, 0, 0, 0, entry_addr, 0];
+    let rsp = top - core::mem::size_of_val(&frame) as u64;
+    unsafe { (rsp as *mut [u64; 8]).write(frame) };
+
+    sched.tasks[slot] = Task {
+        state: TaskState::Ready,
+        entry: Some(entry),
+        rsp,
+    };
+
+    Ok(slot)
+}
+
+/// Switches to the next ready task, if there is any. Returns once it's our turn again.
+pub fn yield_now() {
+    RESCHEDULE.store(false, Ordering::Relaxed);
+
+    // NOTE: The borrow must end before switching, the next task uses the scheduler too.
+    let (old_rsp, new_rsp) = {
+        let sched = unsafe { &mut *SCHEDULER.0.get() };
+        let Some(next) = sched.next_ready() else {
+            return;
+        };
+
+        let prev = core::mem::replace(&mut sched.current, next);
+        (
+            &mut sched.tasks[prev].rsp as *mut u64,
+            sched.tasks[next].rsp,
+        )
+    };
+
+    unsafe { switch_context(old_rsp, new_rsp) };
+}
+
+/// Yields until every other task returned.
+pub fn wait_all() {
+    while unsafe { (*SCHEDULER.0.get()).next_ready() }.is_some() {
+        yield_now();
+    }
+}
+
+/// Whether the current task ran for a whole slice, and should call `yield_now()`.
+#[allow(unused)]
+pub fn should_yield() -> bool {
+    RESCHEDULE.load(Ordering::Relaxed)
+}
+
+/// Called on each timer tick.
+///
+/// NOTE: Only flags the end of the slice, switching from an interrupt handler would need to save
+/// every register.
+pub fn on_tick(ticks: u64) {
+    if ticks.is_multiple_of(SLICE_TICKS) {
+        RESCHEDULE.store(true, Ordering::Relaxed);
+    }
+}
+
+/// First code a spawned task runs, see `spawn()`.
+extern "C" fn task_entry() -> ! {
+    let entry = unsafe {
+        let sched = &*SCHEDULER.0.get();
+        sched.tasks[sched.current].entry
+    };
+    entry.expect("Spawned task without an entry point")();
+
+    // Done: the slot can be reused, and we never get switched back to.
+    unsafe {
+        let sched = &mut *SCHEDULER.0.get();
+        sched.tasks[sched.current].state = TaskState::Free;
+    }
+    loop {
+        yield_now();
+    }
+}
+
+/// Saves the callee-saved registers on the current stack and `rsp` in `*old_rsp`, then switches
+/// to `new_rsp` and pops the registers saved there, e.g. returns into the `yield_now()` of the
+/// task that saved them (or `task_entry()` for a new one).
+///
+/// NOTE: This is naked so that the compiler does not touch the stack around the switch.
+#[unsafe(naked)]
+unsafe extern "C" fn switch_context(old_rsp: *mut u64, new_rsp: u64) {
+    naked_asm!(
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "ret",
+    )
+}
+
+/// Two tasks printing in turns, to show the switches work.
+pub fn demo() {
+    fn ping_pong(name: &str) {
+        for i in 0..3 {
+            println!("[sched] {} #{}", name, i);
+            yield_now();
+        }
+    }
+
+    for task in [(|| ping_pong("ping")) as fn(), || ping_pong("pong")] {
+        if let Err(err) = spawn(task) {
+            println!("WARNING: Can't start the scheduler demo: {}", err);
+        }
+    }
+    wait_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+    use core::sync::atomic::AtomicU64;
+
+    /// Digits of the tasks that ran, in order.
+    static TRACE: AtomicU64 = AtomicU64::new(0);
+
+    fn record(task: u64) {
+        TRACE.store(TRACE.load(Ordering::Relaxed) * 10 + task, Ordering::Relaxed);
+    }
+
+    #[test_case]
+    fn test_yield_alternates() -> TestCase {
+        TestCase {
+            name: "Test two tasks yielding run in turns",
+            test: || {
+                TRACE.store(0, Ordering::Relaxed);
+                let first = spawn(|| {
+                    record(1);
+                    yield_now();
+                    record(1);
+                })
+                .unwrap();
+                let second = spawn(|| {
+                    record(2);
+                    yield_now();
+                    record(2);
+                })
+                .unwrap();
+                assert_ne!(first, second);
+
+                // Nothing runs until we yield.
+                assert_eq!(TRACE.load(Ordering::Relaxed), 0);
+                wait_all();
+                assert_eq!(TRACE.load(Ordering::Relaxed), 1212);
+
+                // Both slots are free again.
+                let sched = unsafe { &*SCHEDULER.0.get() };
+                assert_eq!(sched.current, 0);
+                assert!(sched.tasks[1..]
+                    .iter()
+                    .all(|task| task.state == TaskState::Free));
+            },
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Minimal debug shell, over the serial port (e.g. with QEMU's `-serial stdio`) or the keyboard.
+//!
+//! To add a command, add it to `COMMANDS`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::io::{keyboard, serial};
+
+/// Longest line we can read, anything after it is dropped.
+const MAX_LINE_LEN: usize = 64;
+
+/// Ctrl+C, drops the current line.
+const INTERRUPT: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+
+struct Command {
+    name: &'static str,
+    help: &'static str,
+    run: fn(),
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "List the commands",
+        run: help,
+    },
+    Command {
+        name: "gdt",
+        help: "Print the GDT",
+        run: || crate::interrupts::Gdtr::print(true),
+    },
+    Command {
+        name: "idt",
+        help: "Print the IDT",
+        run: || crate::interrupts::Idtr::print(true),
+    },
+    Command {
+        name: "pic",
+        help: "Print the masked (-) and unmasked (+) IRQs",
+        run: crate::io::pic::print_masks,
+    },
+    Command {
+        name: "mem",
+        help: "Print the heap free segments",
+        run: crate::allocator::print_free_segments,
+    },
+    Command {
+        name: "stats",
+        help: "Print heap statistics",
+        run: stats,
+    },
+    Command {
+        name: "onpanic",
+        help: "Toggle rebooting instead of halting on panic",
+        run: toggle_reboot_on_panic,
+    },
+    Command {
+        name: "trace",
+        help: "Toggle logging allocations over serial",
+        run: toggle_alloc_trace,
+    },
+    Command {
+        name: "fit",
+        help: "Switch to the next heap fit strategy",
+        run: next_fit_strategy,
+    },
+    Command {
+        name: "exit",
+        help: "Leave the shell, the kernel then idles",
+        run: || EXIT.store(true, Ordering::Relaxed),
+    },
+];
+
+/// Set by the `exit` command, `run()` returns after the command.
+static EXIT: AtomicBool = AtomicBool::new(false);
+
+fn help() {
+    for command in COMMANDS {
+        println!("{:<8} {}", command.name, command.help);
+    }
+}
+
+fn stats() {
+    let stats = crate::allocator::stats();
+    println!(
+        "Free: {} bytes in {} segments (largest: {} bytes)",
+        stats.free_bytes, stats.free_segments, stats.largest_free
+    );
+}
+
+fn toggle_reboot_on_panic() {
+    let enabled = !crate::REBOOT_ON_PANIC.fetch_xor(true, Ordering::Relaxed);
+    println!("Panics will {}", if enabled { "reboot" } else { "halt" });
+}
+
+fn toggle_alloc_trace() {
+    // `fetch_xor` returns the previous value.
+    let enabled = !crate::allocator::ALLOC_TRACE.fetch_xor(true, Ordering::Relaxed);
+    println!(
+        "Allocation tracing {}",
+        if enabled { "enabled" } else { "disabled" }
+    );
+}
+
+fn next_fit_strategy() {
+    let strategy = crate::allocator::fit_strategy().next();
+    crate::allocator::set_fit_strategy(strategy);
+    println!("Fit strategy: {:?}", strategy);
+}
+
+/// Reads a byte from either the serial port or the keyboard.
+fn read_byte() -> Option<u8> {
+    serial::read_byte().or_else(|| keyboard::read_char().and_then(|c| u8::try_from(c).ok()))
+}
+
+/// Reads a line, echoing it back. Returns once enter is pressed.
+fn read_line(buf: &mut [u8; MAX_LINE_LEN]) -> &str {
+    let mut len = 0;
+
+    loop {
+        let Some(byte) = read_byte() else {
+            // NOTE: Serial is polled, so it's only checked again on the next interrupt, e.g. the
+            // next timer tick at worst.
+            crate::cpu::idle_once();
+            continue;
+        };
+
+        match byte {
+            b'\r' | b'\n' => {
+                print!("\n");
+                break;
+            }
+            INTERRUPT => {
+                print!("^C\n");
+                len = 0;
+                break;
+            }
+            BACKSPACE | DELETE if len > 0 => {
+                len -= 1;
+                // Move back, erase the char with a space, and move back again.
+                print!("\x08 \x08");
+            }
+            // Only keep printable ASCII, so the line is always valid UTF-8.
+            byte if (byte.is_ascii_graphic() || byte == b' ') && len < MAX_LINE_LEN => {
+                buf[len] = byte;
+                len += 1;
+                print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+/// Runs the shell, until the `exit` command.
+pub fn run() {
+    println!("Debug shell, type `help` for the list of commands.");
+    crate::io::vga::with_screen(|writer| writer.show_cursor(true));
+
+    let mut buf = [0; MAX_LINE_LEN];
+    loop {
+        print!("> ");
+        let line = read_line(&mut buf).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match COMMANDS.iter().find(|command| command.name == line) {
+            Some(command) => (command.run)(),
+            None => {
+                println!("Unknown command: {}", line);
+            }
+        }
+
+        if EXIT.swap(false, Ordering::Relaxed) {
+            crate::io::vga::with_screen(|writer| writer.show_cursor(false));
+            return;
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Status bar on the top text row of the screen, showing the uptime, the free heap and a spinner.
+//!
+//! The rest of the screen becomes the scroll region, so the log scrolls below the bar without
+//! touching it. The bar is redrawn from the timer IRQ (see `on_tick()`), or on demand with
+//! `render()`.
+//!
+//! NOTE: Rendering happens in interrupt context, so it must not allocate nor block: if the
+//! interrupted code was writing to the screen, the bar is simply drawn on a later tick.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    allocator,
+    io::vga::{self, SCREEN_LOCK, SCREEN_WRITER},
+    time::{self, TICK_HZ},
+    utils::fmt::ByteSize,
+};
+
+/// Text row of the bar.
+const ROW: usize = 0;
+
+/// Redraws per second, which is also the speed of the spinner.
+const RENDER_HZ: u64 = 4;
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Set by `init()`, so that ticks don't draw before the bar has its row.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Reserves the top row for the bar and draws it.
+///
+/// NOTE: Does nothing without a screen. Whatever was on the top row (e.g. the first boot logs) is
+/// drawn over.
+pub fn init() {
+    let reserved = vga::with_screen(|writer| {
+        // Keep logging where we were, unless that's on the bar.
+        let (col, row) = writer.cursor();
+        writer.set_scroll_region(ROW + 1, usize::MAX);
+        writer.set_cursor(col, row.max(ROW + 1));
+    });
+    if reserved.is_none() {
+        return;
+    }
+
+    ENABLED.store(true, Ordering::Relaxed);
+    render();
+}
+
+/// Called on each timer tick, redraws the bar `RENDER_HZ` times per second.
+pub fn on_tick(ticks: u64) {
+    if ticks.is_multiple_of(TICK_HZ / RENDER_HZ) {
+        render();
+    }
+}
+
+/// Redraws the bar, unless the screen is being written to.
+pub fn render() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let Some(_screen) = SCREEN_LOCK.try_lock() else {
+        return;
+    };
+    // Safety: We hold `SCREEN_LOCK`.
+    let Some(writer) = (unsafe { (*SCREEN_WRITER.0.get()).as_mut() }) else {
+        return;
+    };
+
+    let ticks = time::ticks();
+    let seconds = ticks / TICK_HZ;
+    let spinner = SPINNER[(ticks / (TICK_HZ / RENDER_HZ)) as usize % SPINNER.len()];
+    // NOTE: Interrupts are disabled while the allocator updates the free list, so it is
+    // consistent here.
+    let free = allocator::stats().free_bytes;
+
+    writer.write_at(
+        0,
+        ROW,
+        format_args!(
+            "{} Uptime {:02}:{:02}:{:02} | Heap free: {}",
+            spinner,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            ByteSize(free as u64)
+        ),
+    );
+}
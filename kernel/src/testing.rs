@@ -1,27 +1,293 @@
+//! Custom test framework.
+//!
+//! Since we're `no_std`, we can't unwind out of a panicking test. Instead, the runner saves its
+//! context in `run_guarded()` before calling each test body, and the panic handler jumps back to
+//! it through `resume()`. A panic is then either expected (`should_panic` tests), or reported as a
+//! failure, and the run goes on with the next test either way. Panics outside of a test body (e.g.
+//! in `setup`/`teardown`) still stop the whole run.
+//!
+//! Each test can be run several times (see `REPEAT_FILE`), e.g. to catch order dependent bugs. It
+//! only passes if every run did, and is reported as flaky if only some of them did.
+//!
+//! NOTE: This is not true isolation: nothing is dropped when jumping back, so whatever the test
+//! was holding (allocations, locks, half-updated globals...) is leaked as is, and may make the
+//! following tests fail too. Only the interrupt flag is put back.
+
+use core::{
+    arch::naked_asm,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::utils::tsc::rdtsc;
+
 pub struct TestCase {
     pub name: &'static str,
 
     pub test: fn(),
+
+    /// The test passes only if its body panics.
+    pub should_panic: bool,
+
+    /// Called before `test`, e.g. to snapshot or reinitialize global state.
+    pub setup: fn(),
+
+    /// Called after `test`, even if it panicked (for `should_panic` tests).
+    pub teardown: fn(),
+}
+
+impl Default for TestCase {
+    fn default() -> Self {
+        Self {
+            name: "",
+            test: || {},
+            should_panic: false,
+            setup: || {},
+            teardown: || {},
+        }
+    }
+}
+
+/// fw_cfg file holding the test filter, set by the host `test-runner`.
+const FILTER_FILE: &str = "opt/rust-os/test-filter";
+const MAX_FILTER_LEN: usize = 64;
+/// fw_cfg file holding how many times each test runs, set by the host `test-runner`.
+const REPEAT_FILE: &str = "opt/rust-os/test-repeat";
+/// Number of runs when the host did not set `REPEAT_FILE`.
+const DEFAULT_REPEAT: usize = 1;
+
+/// Number of tests that passed so far.
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+/// Number of tests selected to run (e.g. the ones matching `FILTER`).
+static TOTAL: AtomicUsize = AtomicUsize::new(0);
+
+/// Set while running a test body, so that the panic handler knows it can resume.
+static IN_TEST: AtomicBool = AtomicBool::new(false);
+/// Stack pointer saved by `run_guarded()`, used by `resume()` to return from it.
+static RESUME_RSP: AtomicU64 = AtomicU64::new(0);
+
+fn is_selected(case: &TestCase, filter: Option<&str>) -> bool {
+    match filter {
+        Some(filter) => case.name.contains(filter),
+        None => true,
+    }
+}
+
+/// Reads the test filter passed by the host, if any.
+fn read_filter(buf: &mut [u8; MAX_FILTER_LEN]) -> Option<&str> {
+    let len = crate::io::fw_cfg::read_file(FILTER_FILE, buf)?;
+    let filter = core::str::from_utf8(&buf[..len]).ok()?;
+
+    if filter.is_empty() {
+        return None;
+    }
+
+    Some(filter)
+}
+
+/// Reads how many times each test should run, as passed by the host.
+fn read_repeat() -> usize {
+    let mut buf = [0; 8];
+    let Some(len) = crate::io::fw_cfg::read_file(REPEAT_FILE, &mut buf) else {
+        return DEFAULT_REPEAT;
+    };
+
+    core::str::from_utf8(&buf[..len])
+        .ok()
+        .and_then(|repeat| repeat.trim().parse().ok())
+        .filter(|&repeat| repeat > 0)
+        .unwrap_or(DEFAULT_REPEAT)
 }
 
-const FILTER: Option<&'static str> = None; //Some("GateDescriptor");
+/// Runs `case` once, with its `setup` and `teardown`. Returns whether it passed, and how many
+/// cycles its body took.
+fn run_once(case: &TestCase) -> (bool, u64) {
+    (case.setup)();
+
+    let interrupts = crate::interrupts::are_enabled();
+    IN_TEST.store(true, Ordering::Relaxed);
+    let start = rdtsc();
+    let returned = unsafe { run_guarded(&case.test, RESUME_RSP.as_ptr()) };
+    let cycles = rdtsc() - start;
+    IN_TEST.store(false, Ordering::Relaxed);
+
+    // A panic may have jumped out of e.g. `without_interrupts()` or an interrupt gate, which
+    // would leave interrupts (and thus the timer and the watchdog) off for the following tests.
+    if !returned && interrupts {
+        crate::interrupts::enable();
+    }
+
+    (case.teardown)();
+
+    (returned != case.should_panic, cycles)
+}
+
+/// Prints the `X/Y tests passed` summary line.
+fn print_summary() {
+    println!(
+        "{}/{} tests passed",
+        PASSED.load(Ordering::Relaxed),
+        TOTAL.load(Ordering::Relaxed)
+    );
+}
 
 pub fn test_runner(tests: &[&dyn Fn() -> TestCase]) {
-    println!("Running {} tests", tests.len());
+    // NOTE: The allocator is not initialized when running tests, so we count the selected tests
+    // in a first pass instead of collecting them.
+    let mut filter_buf = [0; MAX_FILTER_LEN];
+    let filter = read_filter(&mut filter_buf);
 
+    let total = tests
+        .iter()
+        .filter(|test| is_selected(&test(), filter))
+        .count();
+    TOTAL.store(total, Ordering::Relaxed);
+
+    if let Some(filter) = filter {
+        println!("Running {} tests (filter: \"{}\")", total, filter);
+    } else {
+        println!("Running {} tests", total);
+    }
+
+    let repeat = read_repeat();
+    if repeat > 1 {
+        println!("Running each test {} times", repeat);
+    }
+
+    let mut idx = 0;
     for test in tests {
         let case = test();
 
-        if let Some(filter) = FILTER {
-            if !case.name.contains(filter) {
-                continue;
+        if !is_selected(&case, filter) {
+            continue;
+        }
+        idx += 1;
+
+        // Printed beforehand, so that a panic in `setup`/`teardown` (which stops the whole run)
+        // still pinpoints the culprit.
+        print!(
+            "{} passed, running test {}: {} ",
+            PASSED.load(Ordering::Relaxed),
+            idx,
+            case.name
+        );
+
+        let mut passes = 0;
+        let mut best_cycles = u64::MAX;
+        for _ in 0..repeat {
+            crate::watchdog::pet();
+            let (passed, cycles) = run_once(&case);
+            if passed {
+                passes += 1;
+                best_cycles = best_cycles.min(cycles);
             }
         }
 
-        print!("{}", case.name);
-        (case.test)();
-        println!("[ok]");
+        match passes {
+            _ if passes == repeat => print!("[ok] "),
+            0 => print!("[FAILED] "),
+            _ => print!("[FLAKY] "),
+        }
+        if repeat > 1 {
+            print!("{}/{} passed ", passes, repeat);
+        }
+
+        if passes == repeat {
+            PASSED.fetch_add(1, Ordering::Relaxed);
+            // NOTE: For `should_panic` tests, this includes the panic handler. With several runs,
+            // this is the fastest one.
+            println!("({} cycles)", best_cycles);
+        } else if case.should_panic {
+            println!("(did not panic)");
+        } else {
+            // The panic handler already printed why.
+            print!("\n");
+        }
     }
+
+    print_summary();
+
+    // NOTE: The host `test-runner` also checks the summary, in case we exit with 0 by mistake.
+    let all_passed = PASSED.load(Ordering::Relaxed) == total;
+    crate::io::exit(if all_passed { 0 } else { 1 });
+}
+
+/// Whether the current panic is in a test body, so that `on_panic()` resumes the run.
+pub fn resumes_panic() -> bool {
+    IN_TEST.load(Ordering::Relaxed)
+}
+
+/// Called by the panic handler when a test panics.
+///
+/// If a test body is running, this jumps back into the runner, which tells whether the panic was
+/// expected. Otherwise, we can't recover, so this reports the failure and exits with a non-zero
+/// code.
+pub fn on_panic() {
+    if IN_TEST.swap(false, Ordering::Relaxed) {
+        unsafe { resume() }
+    }
+
+    println!("[failed]");
+    print_summary();
+
+    crate::io::exit(1);
+}
+
+extern "C" fn call_test(test: *const fn()) {
+    unsafe { (*test)() }
+}
+
+/// Runs `test` and returns `true` once it returns.
+///
+/// Before calling the test, this saves the callee-saved registers on the stack and the resulting
+/// stack pointer in `saved_rsp`. `resume()` can then return from this function (with `false`)
+/// from anywhere deeper in the stack.
+#[unsafe(naked)]
+unsafe extern "C" fn run_guarded(test: *const fn(), saved_rsp: *mut u64) -> bool {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // Keep the stack 16 bytes aligned for the call.
+        "sub rsp, 8",
+        "mov [rsi], rsp",
+        // `test` is still in `rdi`.
+        "call {call_test}",
+        "mov eax, 1",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+        call_test = sym call_test,
+    )
+}
+
+/// Returns from the last `run_guarded()` call with `false`.
+///
+/// Safety: `run_guarded()` must still be on the stack.
+unsafe fn resume() -> ! {
+    let rsp = RESUME_RSP.load(Ordering::Relaxed);
+
+    core::arch::asm!(
+        "mov rsp, {rsp}",
+        "xor eax, eax",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+        rsp = in(reg) rsp,
+        options(noreturn)
+    )
 }
 
 #[test_case]
@@ -29,5 +295,36 @@ fn trivial_assertion() -> TestCase {
     TestCase {
         name: "Trivial assertion... ",
         test: || assert_eq!(1, 1),
+        ..Default::default()
+    }
+}
+
+#[test_case]
+fn trivial_should_panic() -> TestCase {
+    TestCase {
+        name: "Trivial should_panic... ",
+        test: || panic!("Expected panic."),
+        should_panic: true,
+        ..Default::default()
+    }
+}
+
+#[test_case]
+fn should_panic_without_interrupts() -> TestCase {
+    TestCase {
+        name: "Trivial should_panic with interrupts disabled... ",
+        test: || crate::interrupts::without_interrupts(|| panic!("Expected panic.")),
+        should_panic: true,
+        ..Default::default()
+    }
+}
+
+#[test_case]
+fn interrupts_back_after_panic() -> TestCase {
+    TestCase {
+        name: "Interrupts are back on after the previous test panicked... ",
+        // NOTE: `kernel_main` enables them before running the tests.
+        test: || assert!(crate::interrupts::are_enabled()),
+        ..Default::default()
     }
 }
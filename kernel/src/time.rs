@@ -0,0 +1,122 @@
+//! Timer ticks and busy-wait delays.
+//!
+//! Once `start_timer()` is called, the PIT raises IRQ0 `TICK_HZ` times per second, counted by
+//! `ticks()`.
+//!
+//! `calibrate()` measures how fast the timestamp counter runs against the PIT, `delay_us()` then
+//! spins on `rdtsc`. Without a TSC (or before calibrating), it falls back to writing to port
+//! 0x80, which takes roughly a microsecond each time.
+//!
+//! NOTE: This assumes an invariant TSC, e.g. one that does not change frequency with the CPU.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    io::{outb, pic, pit},
+    utils::{cpuid, tsc::rdtsc},
+};
+
+/// Frequency of the timer IRQ.
+pub const TICK_HZ: u64 = 100;
+const TIMER_IRQ: u8 = 0;
+
+/// Number of timer IRQs since `start_timer()`.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Length of the calibration, in PIT ticks (~10ms).
+const CALIBRATION_TICKS: u16 = (pit::FREQUENCY_HZ / 100) as u16;
+
+/// Set by `calibrate()`, 0 until then.
+static CYCLES_PER_US: AtomicU64 = AtomicU64::new(0);
+
+/// Starts the periodic timer and unmasks its IRQ.
+///
+/// NOTE: The IDT must have a handler calling `handle_irq()` by then.
+pub fn start_timer() {
+    pit::start_periodic(TICK_HZ);
+    pic::unmask(TIMER_IRQ);
+}
+
+/// Called by the IRQ0 handler.
+pub fn handle_irq() {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    crate::statusbar::on_tick(ticks);
+    crate::io::vga::on_tick(ticks);
+    crate::watchdog::on_tick(ticks);
+    crate::sched::on_tick(ticks);
+
+    pic::end_of_interrupt(TIMER_IRQ);
+}
+
+/// Number of timer ticks (of `1 / TICK_HZ` seconds each) since `start_timer()`.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Measures the TSC frequency against the PIT.
+///
+/// Returns the number of TSC cycles per microsecond, or `None` if the CPU has no TSC.
+pub fn calibrate() -> Option<u64> {
+    if !cpuid::has_tsc() {
+        return None;
+    }
+
+    let start = rdtsc();
+    pit::wait_ticks(CALIBRATION_TICKS);
+    let cycles = rdtsc() - start;
+
+    let us = CALIBRATION_TICKS as u64 * 1_000_000 / pit::FREQUENCY_HZ;
+    // NOTE: At least 1, so that we don't go back to the fallback on very slow CPUs.
+    let cycles_per_us = (cycles / us).max(1);
+    CYCLES_PER_US.store(cycles_per_us, Ordering::Relaxed);
+
+    Some(cycles_per_us)
+}
+
+/// Number of TSC cycles per microsecond, if `calibrate()` succeeded.
+pub fn cycles_per_us() -> Option<u64> {
+    match CYCLES_PER_US.load(Ordering::Relaxed) {
+        0 => None,
+        cycles => Some(cycles),
+    }
+}
+
+/// Spins for at least `us` microseconds.
+pub fn delay_us(us: u64) {
+    let Some(cycles_per_us) = cycles_per_us() else {
+        for _ in 0..us {
+            unsafe { outb(0x80, 0) };
+        }
+        return;
+    };
+
+    let start = rdtsc();
+    let cycles = us.saturating_mul(cycles_per_us);
+    while rdtsc() - start < cycles {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_delay_us() -> TestCase {
+        TestCase {
+            name: "Test delay_us waits for at least the given time",
+            test: || {
+                let Some(cycles_per_us) = cycles_per_us() else {
+                    // Nothing to measure the fallback against.
+                    return;
+                };
+
+                let start = rdtsc();
+                delay_us(1000);
+                assert!(rdtsc() - start >= 1000 * cycles_per_us);
+            },
+            ..Default::default()
+        }
+    }
+}
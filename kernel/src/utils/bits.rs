@@ -1,14 +1,23 @@
+//! Bit manipulation helpers.
+//!
+//! NOTE: Bit indices and lengths are `u32` everywhere, like `u64::BITS` and the shift amounts of
+//! `checked_shl()`/`checked_shr()`, so that getters and setters compose without casts.
+//!
+//! NOTE: Out of bounds bits (e.g. `get_bit(8)` on a `u8`, or `len > first_idx + 1`) panic, even
+//! in release builds where a plain shift would silently wrap. Lengths of 0 and `BITS` are fine.
+
 pub trait GetBit {
     /// Gets a single bit from `self`.
     ///
     /// NOTE: `idx` is 0-indexed "from the right".
     ///
     /// TODO: Should we return a `Self` instead of a `bool`?
-    fn get_bit(&self, idx: usize) -> bool;
+    fn get_bit(&self, idx: u32) -> bool;
 
     /// Gets multiple bits.
     ///
-    /// NOTE: `first_idx` is the index of the first bit to get.
+    /// NOTE: `first_idx` is the index of the first (highest) bit to get, the last one being
+    /// `first_idx + 1 - len`.
     fn get_bits(&self, first_idx: u32, len: u32) -> Self;
 }
 
@@ -16,47 +25,73 @@ pub trait SetBit {
     /// Sets a single bit in `self`.
     ///
     /// NOTE: `idx` is 0-indexed "from the right".
-    fn set_bit(&mut self, idx: usize, value: bool);
+    fn set_bit(&mut self, idx: u32, value: bool);
 
     /// Sets multiple bits at the given index.
     ///
-    /// NOTE: `first_idx` is the index of the first bit that'll set.
+    /// NOTE: `first_idx` is the index of the first bit that'll set, see `GetBit::get_bits`. Bits of
+    /// `value` past `len` are ignored.
     fn set_bits(&mut self, first_idx: u32, len: u32, value: Self);
 }
 
+/// Panics unless the `len` bits down from `first_idx` are within an integer of `bits` bits.
+#[track_caller]
+fn check_range(first_idx: u32, len: u32, bits: u32) {
+    assert!(
+        first_idx < bits && len <= first_idx + 1,
+        "{} bits from bit {} are out of a {} bits integer",
+        len,
+        first_idx,
+        bits
+    );
+}
+
+// NOTE: Shifting by `BITS` or more overflows, hence the checked shifts: the mask of `len == 0`
+// bits is `MAX >> BITS`, and its shift amount can be `BITS` too.
 macro_rules! impl_get_bit {
     ($t:ty) => {
         impl GetBit for $t {
-            fn get_bit(&self, idx: usize) -> bool {
+            #[track_caller]
+            fn get_bit(&self, idx: u32) -> bool {
+                check_range(idx, 1, Self::BITS);
+
                 (self & (1 << idx)) != 0
             }
 
+            #[track_caller]
             fn get_bits(&self, first_idx: u32, len: u32) -> Self {
-                let mask = Self::MAX >> (Self::BITS - len);
+                check_range(first_idx, len, Self::BITS);
+                let mask = Self::MAX.checked_shr(Self::BITS - len).unwrap_or(0);
 
-                (self >> ((first_idx + 1) - len)) & mask
+                self.checked_shr(first_idx + 1 - len).unwrap_or(0) & mask
             }
         }
     };
 }
 
 impl_get_bit!(u8);
-// impl_get_bit!(u16);
+impl_get_bit!(u16);
 impl_get_bit!(u32);
 impl_get_bit!(u64);
 
 macro_rules! impl_set_bit {
     ($t:ty) => {
         impl SetBit for $t {
-            fn set_bit(&mut self, idx: usize, value: bool) {
+            #[track_caller]
+            fn set_bit(&mut self, idx: u32, value: bool) {
+                check_range(idx, 1, Self::BITS);
+
                 *self = (*self & !(1 << idx)) | (if value { 1 } else { 0 }) << idx;
             }
 
+            #[track_caller]
             fn set_bits(&mut self, first_idx: u32, len: u32, value: Self) {
-                let mask = Self::MAX >> (Self::BITS - len);
-                let mask = !(mask << ((first_idx + 1) - len));
+                check_range(first_idx, len, Self::BITS);
+                let mask = Self::MAX.checked_shr(Self::BITS - len).unwrap_or(0);
+                let shift = first_idx + 1 - len;
 
-                *self = (*self & mask) | (value << (first_idx + 1 - len));
+                *self = (*self & !mask.checked_shl(shift).unwrap_or(0))
+                    | (value & mask).checked_shl(shift).unwrap_or(0);
             }
         }
     };
@@ -67,6 +102,78 @@ impl_set_bit!(u8);
 // impl_set_bit!(u32);
 impl_set_bit!(u64);
 
+/// Generates getter/setter pairs for named bit ranges of a tuple struct wrapping an integer.
+///
+/// ```ignore
+/// bitfield! {
+///     impl AccessByte(u8) {
+///         p, set_p: bool @ 7;
+///         dpl, set_dpl: Dpl @ 6, 2;
+///     }
+/// }
+/// ```
+///
+/// - `bool` fields are a single bit, at the given index.
+/// - Other fields span `len` bits starting at `first_idx` (see `GetBit::get_bits`). Their type must
+///   implement `TryFrom<backing type>` (the getter panics on invalid values) and the backing type
+///   must implement `From<field type>`.
+///
+/// NOTE: Accessors are `#[allow(unused)]` since structs rarely use all of them.
+macro_rules! bitfield {
+    (impl $name:ident($backing:ty) { $($fields:tt)* }) => {
+        impl $name {
+            $crate::utils::bits::bitfield!(@fields $backing; $($fields)*);
+        }
+    };
+
+    (@fields $backing:ty;) => {};
+
+    (
+        @fields $backing:ty;
+        $(#[$attr:meta])* $get:ident, $set:ident: bool @ $idx:literal;
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        #[allow(unused)]
+        fn $get(&self) -> bool {
+            $crate::utils::bits::GetBit::get_bit(&self.0, $idx)
+        }
+
+        #[allow(unused)]
+        fn $set(&mut self, value: bool) {
+            $crate::utils::bits::SetBit::set_bit(&mut self.0, $idx, value);
+        }
+
+        $crate::utils::bits::bitfield!(@fields $backing; $($rest)*);
+    };
+
+    (
+        @fields $backing:ty;
+        $(#[$attr:meta])* $get:ident, $set:ident: $field:ident @ $first_idx:literal, $len:literal;
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        #[allow(unused)]
+        fn $get(&self) -> $field {
+            <$field>::try_from($crate::utils::bits::GetBit::get_bits(&self.0, $first_idx, $len))
+                .expect(concat!("Invalid value for bitfield `", stringify!($get), "`."))
+        }
+
+        #[allow(unused)]
+        fn $set(&mut self, value: $field) {
+            $crate::utils::bits::SetBit::set_bits(
+                &mut self.0,
+                $first_idx,
+                $len,
+                <$backing>::from(value),
+            );
+        }
+
+        $crate::utils::bits::bitfield!(@fields $backing; $($rest)*);
+    };
+}
+pub(crate) use bitfield;
+
 #[cfg(test)]
 mod tests {
     use crate::testing::TestCase;
@@ -85,6 +192,7 @@ mod tests {
                 assert_eq!(0x9Au8.get_bit(7), true);
                 assert_eq!(0x9Au8.get_bit(4), true);
             },
+            ..Default::default()
         }
     }
 
@@ -103,6 +211,7 @@ mod tests {
                 assert_eq!(0x0123456789ABCDEFu64.get_bits(31, 16), 0x89AB);
                 assert_eq!(0x0123456789ABCDEFu64.get_bits(15, 16), 0xCDEF);
             },
+            ..Default::default()
         }
     }
 
@@ -117,6 +226,7 @@ mod tests {
                 val.set_bit(3, false);
                 assert_eq!(val, 0);
             },
+            ..Default::default()
         }
     }
 
@@ -147,6 +257,7 @@ mod tests {
                 v.set_bits(15, 16, 0xCDEF);
                 assert_eq!(v, 0x000000000000CDEF);
             },
+            ..Default::default()
         }
     }
 
@@ -167,6 +278,123 @@ mod tests {
                 val.set_bit(7, false);
                 assert_eq!(val, 0);
             },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_get_set_round_trip() -> TestCase {
+        TestCase {
+            name: "Test copying bits with get_bits/set_bits",
+            test: || {
+                let src = 0x0123456789ABCDEFu64;
+
+                // Copy `src` 8 bits at a time, and bit by bit.
+                let mut by_bytes = 0u64;
+                let mut by_bits = 0u64;
+                for first_idx in (7..u64::BITS).step_by(8) {
+                    by_bytes.set_bits(first_idx, 8, src.get_bits(first_idx, 8));
+                }
+                for idx in 0..u64::BITS {
+                    by_bits.set_bit(idx, src.get_bit(idx));
+                }
+
+                assert_eq!(by_bytes, src);
+                assert_eq!(by_bits, src);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_bits_boundaries() -> TestCase {
+        TestCase {
+            name: "Test get_bits/set_bits with empty and full ranges",
+            test: || {
+                // No bits at all.
+                assert_eq!(0xFFu8.get_bits(7, 0), 0);
+                assert_eq!(u64::MAX.get_bits(0, 0), 0);
+                let mut v = 0xA5u8;
+                v.set_bits(7, 0, 0xFF);
+                assert_eq!(v, 0xA5);
+
+                // Every bit.
+                assert_eq!(0xA5u8.get_bits(7, 8), 0xA5);
+                assert_eq!(u64::MAX.get_bits(63, 64), u64::MAX);
+                let mut v = 0u64;
+                v.set_bits(63, 64, 0x0123456789ABCDEF);
+                assert_eq!(v, 0x0123456789ABCDEF);
+
+                // Down to bit 0, and up to the highest one.
+                assert_eq!(0x80u8.get_bits(7, 1), 1);
+                assert_eq!(0x01u8.get_bits(0, 1), 1);
+                let mut v = 0u8;
+                v.set_bit(7, true);
+                assert_eq!(v, 0x80);
+
+                // A too wide value does not spill over the neighbouring bits.
+                let mut v = 0u8;
+                v.set_bits(5, 2, 0xFF);
+                assert_eq!(v, 0x30);
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_bits_below_zero() -> TestCase {
+        TestCase {
+            name: "Test get_bits panics on a range going below bit 0",
+            test: || {
+                0xFFu8.get_bits(2, 4);
+            },
+            should_panic: true,
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_bit_out_of_bounds() -> TestCase {
+        TestCase {
+            name: "Test set_bit panics past the highest bit",
+            test: || {
+                let mut v = 0u8;
+                v.set_bit(8, true);
+            },
+            should_panic: true,
+            ..Default::default()
+        }
+    }
+
+    struct Fields(u64);
+
+    bitfield! {
+        impl Fields(u64) {
+            low, set_low: bool @ 0;
+            middle, set_middle: u64 @ 31, 16;
+            high, set_high: bool @ 63;
+        }
+    }
+
+    #[test_case]
+    fn test_bitfield() -> TestCase {
+        TestCase {
+            name: "Test bitfield! accessors",
+            test: || {
+                let mut fields = Fields(0);
+                fields.set_low(true);
+                fields.set_middle(0xABCD);
+                fields.set_high(true);
+                assert_eq!(fields.0, 0x80000000ABCD0001);
+
+                assert!(fields.low());
+                assert_eq!(fields.middle(), 0xABCD);
+                assert!(fields.high());
+
+                fields.set_middle(0);
+                assert_eq!(fields.0, 0x8000000000000001);
+            },
+            ..Default::default()
         }
     }
 }
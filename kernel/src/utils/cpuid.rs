@@ -0,0 +1,157 @@
+//! CPUID wrapper, used to check what the CPU supports before relying on it.
+
+use core::{arch::asm, fmt};
+
+use super::bits::GetBit;
+
+/// Registers returned by the `cpuid` instruction.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Runs `cpuid` for the given leaf (with sub-leaf 0).
+pub fn cpuid(leaf: u32) -> CpuidResult {
+    let eax: u32;
+    let rbx: u64;
+    let ecx: u32;
+    let edx: u32;
+
+    // NOTE: LLVM reserves `rbx`, so it can't be used as an operand. We save it in another
+    // register, run `cpuid`, then swap it back.
+    unsafe {
+        asm!(
+            "mov {tmp:r}, rbx",
+            "cpuid",
+            "xchg {tmp:r}, rbx",
+            tmp = out(reg) rbx,
+            inout("eax") leaf => eax,
+            inout("ecx") 0 => ecx,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    CpuidResult {
+        eax,
+        ebx: rbx as u32,
+        ecx,
+        edx,
+    }
+}
+
+/// Highest basic leaf supported.
+fn max_leaf() -> u32 {
+    cpuid(0).eax
+}
+
+/// Highest extended leaf (`0x8000_0000` and above) supported.
+fn max_extended_leaf() -> u32 {
+    cpuid(0x8000_0000).eax
+}
+
+/// CPU vendor, e.g. "GenuineIntel" or "AuthenticAMD".
+pub struct Vendor([u8; 12]);
+
+impl Vendor {
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).unwrap_or("<invalid>")
+    }
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Reads the vendor string from leaf 0.
+///
+/// NOTE: The string is stored in `ebx`, `edx` then `ecx` (not in register order).
+pub fn vendor() -> Vendor {
+    let result = cpuid(0);
+
+    let mut vendor = [0; 12];
+    vendor[0..4].copy_from_slice(&result.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&result.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&result.ecx.to_le_bytes());
+
+    Vendor(vendor)
+}
+
+/// Whether the CPU has an on-chip APIC (leaf 1, edx bit 9).
+pub fn has_apic() -> bool {
+    max_leaf() >= 1 && cpuid(1).edx.get_bit(9)
+}
+
+/// Whether the CPU has the `rdtsc` instruction (leaf 1, edx bit 4).
+pub fn has_tsc() -> bool {
+    max_leaf() >= 1 && cpuid(1).edx.get_bit(4)
+}
+
+/// Whether the CPU supports SSE and SSE2 (leaf 1, edx bits 25 and 26).
+pub fn has_sse() -> bool {
+    max_leaf() >= 1 && cpuid(1).edx.get_bit(25) && cpuid(1).edx.get_bit(26)
+}
+
+/// Whether the CPU supports `xsave`/`xsetbv` (leaf 1, ecx bit 26).
+pub fn has_xsave() -> bool {
+    max_leaf() >= 1 && cpuid(1).ecx.get_bit(26)
+}
+
+/// Whether the CPU supports AVX (leaf 1, ecx bit 28).
+pub fn has_avx() -> bool {
+    max_leaf() >= 1 && cpuid(1).ecx.get_bit(28)
+}
+
+/// Whether `rep movsb`/`rep stosb` are fast, ERMS (leaf 7, ebx bit 9).
+pub fn has_erms() -> bool {
+    max_leaf() >= 7 && cpuid(7).ebx.get_bit(9)
+}
+
+/// Whether the CPU supports no-execute pages, NX (leaf 0x8000_0001, edx bit 20).
+pub fn has_nx() -> bool {
+    max_extended_leaf() >= 0x8000_0001 && cpuid(0x8000_0001).edx.get_bit(20)
+}
+
+/// Whether the CPU has the `rdtscp` instruction (leaf 0x8000_0001, edx bit 27).
+#[allow(unused)]
+pub fn has_rdtscp() -> bool {
+    max_extended_leaf() >= 0x8000_0001 && cpuid(0x8000_0001).edx.get_bit(27)
+}
+
+/// Prints the vendor and a few feature flags.
+pub fn print_info() {
+    println!(
+        "CPU: {} (max leaf = {:#x}), APIC: {}, TSC: {}",
+        vendor(),
+        max_leaf(),
+        has_apic(),
+        has_tsc()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_cpuid_vendor() -> TestCase {
+        TestCase {
+            name: "Test reading the CPU vendor through CPUID",
+            test: || {
+                // Every x86_64 CPU supports at least leaf 1.
+                assert!(max_leaf() >= 1);
+
+                let vendor = vendor();
+                assert!(vendor.0.iter().all(|c| c.is_ascii_graphic()));
+                assert_ne!(vendor.as_str(), "<invalid>");
+            },
+            ..Default::default()
+        }
+    }
+}
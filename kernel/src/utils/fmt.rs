@@ -0,0 +1,77 @@
+//! Formatting helpers.
+
+use core::fmt;
+
+/// A number of bytes, displayed with the largest fitting binary unit, e.g. `1.5 MiB`.
+///
+/// NOTE: At most one decimal is shown, and it is truncated rather than rounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [(&str, u64); 3] = [("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)];
+
+        let Some((name, unit)) = UNITS.into_iter().find(|(_, unit)| self.0 >= *unit) else {
+            return write!(f, "{} B", self.0);
+        };
+
+        let whole = self.0 / unit;
+        let tenths = (self.0 % unit) * 10 / unit;
+        if tenths == 0 {
+            write!(f, "{} {}", whole, name)
+        } else {
+            write!(f, "{}.{} {}", whole, tenths, name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    /// Formats `size` in `buf`, since we can't allocate a `String` in tests.
+    fn format(size: u64, buf: &mut [u8; 32]) -> &str {
+        struct Cursor<'a>(&'a mut [u8], usize);
+
+        impl fmt::Write for Cursor<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let end = self.1 + s.len();
+                self.0
+                    .get_mut(self.1..end)
+                    .ok_or(fmt::Error)?
+                    .copy_from_slice(s.as_bytes());
+                self.1 = end;
+
+                Ok(())
+            }
+        }
+
+        let mut cursor = Cursor(buf, 0);
+        fmt::write(&mut cursor, format_args!("{}", ByteSize(size))).unwrap();
+        let len = cursor.1;
+
+        core::str::from_utf8(&buf[..len]).unwrap()
+    }
+
+    #[test_case]
+    fn test_byte_size() -> TestCase {
+        TestCase {
+            name: "Test ByteSize picks the right unit",
+            test: || {
+                let mut buf = [0; 32];
+
+                assert_eq!(format(0, &mut buf), "0 B");
+                assert_eq!(format(1023, &mut buf), "1023 B");
+                assert_eq!(format(1024, &mut buf), "1 KiB");
+                assert_eq!(format(1536, &mut buf), "1.5 KiB");
+                assert_eq!(format((1 << 20) - 1, &mut buf), "1023.9 KiB");
+                assert_eq!(format(64 << 20, &mut buf), "64 MiB");
+                assert_eq!(format(1 << 30, &mut buf), "1 GiB");
+                assert_eq!(format(u64::MAX, &mut buf), "17179869183.9 GiB");
+            },
+            ..Default::default()
+        }
+    }
+}
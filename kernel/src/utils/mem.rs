@@ -0,0 +1,123 @@
+//! `memset`/`memcpy` fast paths for large buffers, e.g. the framebuffer.
+//!
+//! Without SSE in `compiler_builtins`, `fill()`, `copy_within()` and `write_bytes()` may end up as
+//! simple loops. On CPUs with ERMS (Enhanced REP MOVSB/STOSB), `rep stosb` and `rep movsb` are the
+//! fastest way to fill or copy anything bigger than a few cache lines, so we use them there and
+//! fall back to the `core::ptr` functions otherwise.
+//!
+//! NOTE: For small buffers, the startup cost of `rep` outweighs the gain, just use the slice
+//! methods.
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use super::cpuid;
+
+/// `ERMS` values, the CPUID result is cached since `cpuid` is slow (and traps in a VM).
+const UNKNOWN: u8 = 0;
+const ABSENT: u8 = 1;
+const PRESENT: u8 = 2;
+
+static ERMS: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// Whether `rep stosb`/`rep movsb` are fast on this CPU.
+fn has_erms() -> bool {
+    match ERMS.load(Ordering::Relaxed) {
+        UNKNOWN => {
+            let erms = cpuid::has_erms();
+            ERMS.store(if erms { PRESENT } else { ABSENT }, Ordering::Relaxed);
+            erms
+        }
+        erms => erms == PRESENT,
+    }
+}
+
+/// Sets `len` bytes from `dst` to `value`.
+///
+/// Safety: `dst` must be valid for `len` bytes of writes.
+pub unsafe fn fast_fill(dst: *mut u8, value: u8, len: usize) {
+    if has_erms() {
+        rep_stosb(dst, value, len);
+    } else {
+        dst.write_bytes(value, len);
+    }
+}
+
+/// Copies `len` bytes from `src` to `dst`.
+///
+/// Safety: `src` must be valid for `len` bytes of reads, and `dst` for `len` bytes of writes. The
+/// two may only overlap if `dst <= src`, since the bytes are copied forward, e.g. to scroll up.
+pub unsafe fn fast_copy(dst: *mut u8, src: *const u8, len: usize) {
+    debug_assert!(dst as usize <= src as usize || dst as usize >= src as usize + len);
+
+    if has_erms() {
+        rep_movsb(dst, src, len);
+    } else {
+        core::ptr::copy(src, dst, len);
+    }
+}
+
+/// Safety: See `fast_fill()`.
+unsafe fn rep_stosb(dst: *mut u8, value: u8, len: usize) {
+    // NOTE: The direction flag is clear, as the ABI requires, so `rdi` goes up.
+    asm!(
+        "rep stosb",
+        inout("rcx") len => _,
+        inout("rdi") dst => _,
+        in("al") value,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Safety: See `fast_copy()`.
+unsafe fn rep_movsb(dst: *mut u8, src: *const u8, len: usize) {
+    asm!(
+        "rep movsb",
+        inout("rcx") len => _,
+        inout("rdi") dst => _,
+        inout("rsi") src => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_rep_fill_and_copy() -> TestCase {
+        TestCase {
+            name: "Test filling and copying with rep stosb/movsb",
+            test: || {
+                // NOTE: The `rep` variants are called directly, so that they're tested even
+                // without ERMS (where they're slower, but still correct).
+                let mut buf = [0u8; 64];
+
+                unsafe { rep_stosb(buf.as_mut_ptr().add(8), 0xAB, 16) };
+                assert!(buf[..8].iter().all(|&b| b == 0));
+                assert!(buf[8..24].iter().all(|&b| b == 0xAB));
+                assert!(buf[24..].iter().all(|&b| b == 0));
+
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = i as u8;
+                }
+                // Overlapping, moving down like `VGAWriter::scroll_up()` does.
+                unsafe { rep_movsb(buf.as_mut_ptr(), buf.as_ptr().add(4), 32) };
+                assert!((0..32).all(|i| buf[i] == i as u8 + 4));
+                assert!((32..64).all(|i| buf[i] == i as u8));
+
+                // Whichever path this CPU takes.
+                unsafe {
+                    fast_fill(buf.as_mut_ptr(), 0x11, 64);
+                    fast_copy(buf.as_mut_ptr(), [0x22u8; 8].as_ptr(), 8);
+                }
+                assert!(buf[..8].iter().all(|&b| b == 0x22));
+                assert!(buf[8..].iter().all(|&b| b == 0x11));
+            },
+            ..Default::default()
+        }
+    }
+}
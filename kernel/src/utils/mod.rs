@@ -1 +1,7 @@
 pub mod bits;
+pub mod cpuid;
+pub mod fmt;
+pub mod mem;
+pub mod ringbuf;
+pub mod sync;
+pub mod tsc;
@@ -0,0 +1,186 @@
+//! Fixed-capacity single-producer/single-consumer ring buffer.
+//!
+//! Meant to pass data from an interrupt handler (the producer) to the main thread (the consumer)
+//! without a lock: the producer only ever writes `tail`, the consumer only ever writes `head`.
+//!
+//! NOTE: Nothing enforces that there is a single producer and a single consumer, pushing (or
+//! popping) from two places at once corrupts the buffer.
+
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub struct RingBuffer<T, const N: usize> {
+    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+
+    /// Number of values popped so far, wrapping. Only written by the consumer.
+    head: AtomicUsize,
+    /// Number of values pushed so far, wrapping. Only written by the producer.
+    tail: AtomicUsize,
+}
+
+// Safety: Values are moved in by the producer and out by the consumer, and a slot is never
+// accessed by both at once (see `push` and `pop`).
+unsafe impl<T: Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+#[allow(unused)]
+impl<T, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        // NOTE: Otherwise `head % N` and `tail % N` would skip slots when the counters wrap.
+        assert!(
+            N.is_power_of_two(),
+            "RingBuffer capacity must be a power of 2"
+        );
+
+        Self {
+            slots: UnsafeCell::new([const { MaybeUninit::uninit() }; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends `value` to the buffer.
+    ///
+    /// Never blocks: if the buffer is full, `value` is dropped and `false` is returned.
+    pub fn push(&self, value: T) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        // `Acquire` pairs with the `Release` in `pop`, so that the slot is really free.
+        let head = self.head.load(Ordering::Acquire);
+
+        if tail.wrapping_sub(head) == N {
+            return false;
+        }
+
+        // Safety: The buffer is not full, so the consumer is not reading this slot.
+        unsafe { (*self.slots.get())[tail % N].write(value) };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        true
+    }
+
+    /// Removes the oldest value from the buffer, if any.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        // `Acquire` pairs with the `Release` in `push`, so that the slot is fully written.
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head == tail {
+            return None;
+        }
+
+        // Safety: The buffer is not empty, so this slot was initialized by `push` and the
+        // producer won't touch it until `head` moves past it.
+        let value = unsafe { (*self.slots.get())[head % N].assume_init_read() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        tail.wrapping_sub(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<T, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for RingBuffer<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_ring_buffer_full_empty() -> TestCase {
+        TestCase {
+            name: "Test RingBuffer full and empty edge cases",
+            test: || {
+                let rb: RingBuffer<u8, 2> = RingBuffer::new();
+                assert!(rb.is_empty());
+                assert_eq!(rb.pop(), None);
+
+                assert!(rb.push(1));
+                assert!(rb.push(2));
+                assert!(rb.is_full());
+                assert_eq!(rb.len(), 2);
+
+                // Full: the value is dropped, the buffer is untouched.
+                assert!(!rb.push(3));
+                assert_eq!(rb.len(), 2);
+
+                assert_eq!(rb.pop(), Some(1));
+                assert_eq!(rb.pop(), Some(2));
+                assert_eq!(rb.pop(), None);
+                assert!(rb.is_empty());
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_ring_buffer_wrap_around() -> TestCase {
+        TestCase {
+            name: "Test RingBuffer wrap-around",
+            test: || {
+                let rb: RingBuffer<u32, 4> = RingBuffer::new();
+
+                // Go around the slots several times, with the buffer partially filled.
+                for i in 0..10 {
+                    assert!(rb.push(2 * i));
+                    assert!(rb.push(2 * i + 1));
+                    assert_eq!(rb.pop(), Some(i));
+                    assert_eq!(rb.len(), i as usize + 1);
+
+                    if rb.is_full() {
+                        break;
+                    }
+                }
+                assert!(rb.is_full());
+
+                let mut expected = 4;
+                while let Some(value) = rb.pop() {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                assert_eq!(expected, 8);
+
+                // The counters themselves wrapping around must not matter.
+                rb.head.store(usize::MAX, Ordering::Relaxed);
+                rb.tail.store(usize::MAX, Ordering::Relaxed);
+                assert!(rb.is_empty());
+                assert!(rb.push(42));
+                assert!(rb.push(43));
+                assert_eq!(rb.len(), 2);
+                assert_eq!(rb.pop(), Some(42));
+                assert_eq!(rb.pop(), Some(43));
+                assert!(rb.is_empty());
+            },
+            ..Default::default()
+        }
+    }
+}
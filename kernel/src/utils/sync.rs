@@ -0,0 +1,125 @@
+//! Synchronization primitives.
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// A spinlock protecting a `T`.
+///
+/// NOTE: There is no poisoning, since we can't unwind: if a panic happens while the lock is held,
+/// it stays held.
+///
+/// NOTE: Spinning in an interrupt handler on a lock held by the code it interrupted deadlocks.
+/// Handlers should use `try_lock()`, or the data should only be locked with interrupts disabled
+/// (see `interrupts::without_interrupts`).
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+// Safety: Only one `MutexGuard` can exist at a time, so `T` is never accessed concurrently.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Spins until the lock is acquired.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+
+            // Wait for the lock to look free before trying again, without hammering the cache
+            // line with writes.
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Acquires the lock if it is free, never spins.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+
+        Some(MutexGuard { mutex: self })
+    }
+
+    /// Releases the lock, whoever holds it.
+    ///
+    /// Safety: Its holder must never use its guard again, e.g. it was leaked by a test that
+    /// panicked.
+    #[cfg(test)]
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// Releases the lock when dropped.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: We hold the lock.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: We hold the lock.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_mutex() -> TestCase {
+        TestCase {
+            name: "Test Mutex lock and try_lock",
+            test: || {
+                let mutex = Mutex::new(0);
+
+                {
+                    let mut guard = mutex.lock();
+                    *guard += 1;
+
+                    // Already locked.
+                    assert!(mutex.try_lock().is_none());
+                }
+
+                // Released when the guard was dropped.
+                let mut guard = mutex.try_lock().expect("The mutex should be free");
+                *guard += 1;
+                drop(guard);
+
+                assert_eq!(*mutex.lock(), 2);
+            },
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Timestamp counter, for rough timings in cycles.
+//!
+//! NOTE: `rdtsc` is not serializing: the CPU is free to execute it before earlier instructions are
+//! done, or after later ones have started, which skews the measures. Surrounding it with `lfence`
+//! keeps it in order with the code being measured.
+//!
+//! NOTE: The counter is not calibrated, cycles can't be turned into wall-clock time.
+
+use core::arch::asm;
+
+/// Reads the timestamp counter.
+#[allow(unused)]
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+
+    unsafe {
+        asm!(
+            "lfence",
+            "rdtsc",
+            "lfence",
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    ((high as u64) << 32) | low as u64
+}
+
+/// Reads the timestamp counter with `rdtscp`, which waits for the previous instructions to
+/// complete. Also returns `IA32_TSC_AUX` (usually the CPU id).
+///
+/// Returns `None` if the CPU does not support `rdtscp`.
+#[allow(unused)]
+pub fn rdtscp() -> Option<(u64, u32)> {
+    if !super::cpuid::has_rdtscp() {
+        return None;
+    }
+
+    let low: u32;
+    let high: u32;
+    let aux: u32;
+
+    unsafe {
+        asm!(
+            "rdtscp",
+            "lfence",
+            out("eax") low,
+            out("edx") high,
+            out("ecx") aux,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    Some((((high as u64) << 32) | low as u64, aux))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_rdtsc_increases() -> TestCase {
+        TestCase {
+            name: "Test that the timestamp counter goes forward",
+            test: || {
+                let before = rdtsc();
+                let after = rdtsc();
+                assert!(after > before);
+            },
+            ..Default::default()
+        }
+    }
+}
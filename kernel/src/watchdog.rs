@@ -0,0 +1,131 @@
+//! Watchdog, exiting QEMU when the kernel hangs, e.g. so that CI does not wait forever.
+//!
+//! The timer IRQ checks the tick count against a deadline (see `on_tick()`), which `pet()` pushes
+//! forward. Long running loops (e.g. the shell, the test runner) must call `pet()` regularly.
+//!
+//! NOTE: Only the test builds enable it by default, see `set_timeout()` to enable it otherwise.
+//! Since it runs from the timer IRQ, it can't catch anything before `time::start_timer()` (which
+//! is why the tests run after it), nor a hang with interrupts disabled.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::time::{self, TICK_HZ};
+
+/// `TIMEOUT` value meaning the watchdog is disabled.
+const DISABLED: u64 = 0;
+
+/// 10 seconds in the test builds, disabled otherwise.
+const DEFAULT_TIMEOUT: u64 = if cfg!(test) { 10 * TICK_HZ } else { DISABLED };
+
+/// Ticks without a `pet()` before the watchdog fires.
+static TIMEOUT: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT);
+
+/// Tick count at which the watchdog fires, `u64::MAX` until the first `pet()`.
+static DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Number of times the watchdog fired.
+static FIRED: AtomicU64 = AtomicU64::new(0);
+
+/// Set by the tests, so that firing only counts in `FIRED` instead of exiting.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Sets how many ticks may pass between two `pet()`s, `None` disables the watchdog. This also
+/// pets it.
+#[allow(unused)]
+pub fn set_timeout(ticks: Option<u64>) {
+    TIMEOUT.store(ticks.unwrap_or(DISABLED), Ordering::Relaxed);
+    pet();
+}
+
+/// Tells the watchdog we're not stuck, pushing its deadline forward.
+pub fn pet() {
+    let deadline = match TIMEOUT.load(Ordering::Relaxed) {
+        DISABLED => u64::MAX,
+        timeout => time::ticks().saturating_add(timeout),
+    };
+    DEADLINE.store(deadline, Ordering::Relaxed);
+}
+
+/// Whether the watchdog is past its deadline at `ticks`.
+fn expired(ticks: u64) -> bool {
+    ticks >= DEADLINE.load(Ordering::Relaxed)
+}
+
+/// Called on each timer tick, exits QEMU with a failure code once the deadline passed.
+pub fn on_tick(ticks: u64) {
+    if !expired(ticks) {
+        return;
+    }
+    // Only fire once, e.g. if there is no exit device.
+    DEADLINE.store(u64::MAX, Ordering::Relaxed);
+    FIRED.fetch_add(1, Ordering::Relaxed);
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return;
+    }
+
+    println!(
+        "\nWATCHDOG: Not petted for {} ticks, the kernel looks stuck.",
+        TIMEOUT.load(Ordering::Relaxed)
+    );
+    crate::io::exit(1);
+
+    // NOTE: Only without the `isa-debug-exit` device, at least get a register dump.
+    panic!("Watchdog expired");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+
+    #[test_case]
+    fn test_watchdog_deadline() -> TestCase {
+        TestCase {
+            name: "Test petting the watchdog pushes its deadline forward",
+            test: || {
+                let now = time::ticks();
+
+                // NOTE: The timer runs, so the deadline is read back rather than computed.
+                set_timeout(Some(5));
+                let deadline = DEADLINE.load(Ordering::Relaxed);
+                assert!(deadline >= now + 5);
+                assert!(!expired(deadline - 1));
+                assert!(expired(deadline));
+
+                set_timeout(None);
+                assert!(!expired(u64::MAX - 1));
+            },
+            // NOTE: Back to the default, even if the test failed.
+            teardown: || set_timeout(Some(DEFAULT_TIMEOUT)),
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn test_watchdog_fires() -> TestCase {
+        TestCase {
+            name: "Test the watchdog fires once it is not petted in time",
+            test: || {
+                // Otherwise, no tick would wake us up.
+                assert!(crate::interrupts::are_enabled());
+                let fired = FIRED.load(Ordering::Relaxed);
+
+                set_timeout(Some(2));
+                let deadline = DEADLINE.load(Ordering::Relaxed);
+                while time::ticks() <= deadline {
+                    crate::cpu::wait_for_interrupt();
+                }
+
+                // Only once, it stays quiet until the next `pet()`.
+                assert_eq!(FIRED.load(Ordering::Relaxed), fired + 1);
+                assert_eq!(DEADLINE.load(Ordering::Relaxed), u64::MAX);
+            },
+            setup: || DRY_RUN.store(true, Ordering::Relaxed),
+            teardown: || {
+                DRY_RUN.store(false, Ordering::Relaxed);
+                set_timeout(Some(DEFAULT_TIMEOUT));
+            },
+            ..Default::default()
+        }
+    }
+}
@@ -1,28 +1,61 @@
+use ovmf_prebuilt::{Arch, FileType, Prebuilt, Source};
+
+/// Where the OVMF (UEFI firmware) files get downloaded.
+const OVMF_DIR: &str = "target/ovmf";
+
+/// RAM size given to QEMU when `QEMU_MEM` is not set.
+const DEFAULT_MEM: &str = "128M";
+
 fn main() {
     // read env variables that were set in build script
     let uefi_path = env!("UEFI_PATH");
     let bios_path = env!("BIOS_PATH");
 
-    // choose whether to start the UEFI or BIOS image
-    let uefi = false;
+    // choose whether to start the UEFI or BIOS image, e.g. `BOOT_MODE=uefi cargo run`
+    let uefi = std::env::var("BOOT_MODE").is_ok_and(|mode| mode.eq_ignore_ascii_case("uefi"));
+
+    // RAM size, e.g. `QEMU_MEM=500M cargo run`
+    let mem = std::env::var("QEMU_MEM").unwrap_or_else(|_| DEFAULT_MEM.to_string());
+    // Write the OS output to this file instead of stdio, e.g. `SERIAL_LOG=serial.log cargo run`
+    let serial = match std::env::var("SERIAL_LOG") {
+        Ok(path) => format!("file:{path}"),
+        Err(_) => "stdio".to_string(),
+    };
 
     let mut cmd = std::process::Command::new("qemu-system-x86_64");
+    cmd.args(["-m", &mem])
+        .arg("-no-reboot")
+        // Always there so that `io::exit()` works.
+        .args(["-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"])
+        .args(["-serial", &serial]);
+
     if uefi {
-        // FIXME: This is the old interface, lookup how to call `ovmf_prebuilt` now.
-        // cmd.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
-        cmd.arg("-drive")
-            .arg(format!("format=raw,file={uefi_path}"));
+        let prebuilt =
+            Prebuilt::fetch(Source::LATEST, OVMF_DIR).expect("Failed to fetch the OVMF firmware.");
+        let ovmf_code = prebuilt.get_file(Arch::X64, FileType::Code);
+        let ovmf_vars = prebuilt.get_file(Arch::X64, FileType::Vars);
+
+        cmd.args([
+            "-drive",
+            &format!(
+                "if=pflash,format=raw,readonly=on,file={}",
+                ovmf_code.display()
+            ),
+        ])
+        .args([
+            "-drive",
+            &format!("if=pflash,format=raw,file={}", ovmf_vars.display()),
+        ])
+        .args(["-drive", &format!("format=raw,file={uefi_path}")]);
     } else {
-        cmd
-            // Use this to change the RAM size
-            // .args(["-m", "500M"])
-            // Use this to write the OS output to a log file
-            // .args(["-serial", "file:serial.log"])
-            .arg("-no-reboot")
-            .args(["-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"])
-            .args(["-serial", "stdio"])
-            .args(["-drive", &format!("format=raw,file={bios_path}")]);
+        cmd.args(["-drive", &format!("format=raw,file={bios_path}")]);
+    }
+
+    // Anything else to give to QEMU, e.g. `QEMU_EXTRA_ARGS="-display none"`
+    if let Ok(extra_args) = std::env::var("QEMU_EXTRA_ARGS") {
+        cmd.args(extra_args.split_whitespace());
     }
+
     let mut child = cmd.spawn().unwrap();
     child.wait().unwrap();
 }
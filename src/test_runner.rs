@@ -1,8 +1,38 @@
-use std::path::PathBuf;
+use std::{
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{ExitCode, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
 
 use regex::Regex;
 
-fn main() {
+/// fw_cfg file read by the kernel's test runner, see `kernel/src/testing.rs`.
+const FILTER_FILE: &str = "opt/rust-os/test-filter";
+/// fw_cfg file holding how many times each test runs, see `kernel/src/testing.rs`.
+const REPEAT_FILE: &str = "opt/rust-os/test-repeat";
+
+/// QEMU is killed if it is still running after this (times the number of runs of each test).
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// With `isa-debug-exit`, QEMU exits with `(code << 1) | 1` when the kernel calls `io::exit(code)`.
+const QEMU_EXIT_SUCCESS: i32 = 1;
+
+fn main() -> ExitCode {
+    // Only run the tests whose name contains this, e.g. `cargo rt GateDescriptor`.
+    let filter = std::env::args().nth(1);
+    // Run each test this many times, e.g. `TEST_REPEAT=100 cargo rt` to find flaky tests.
+    let repeat: u32 = match std::env::var("TEST_REPEAT") {
+        Ok(repeat) => repeat
+            .parse()
+            .ok()
+            .filter(|&repeat| repeat > 0)
+            .expect("TEST_REPEAT must be a positive number"),
+        Err(_) => 1,
+    };
+
     // read env variables that were set in build script
     let mut build_cmd = std::process::Command::new("cargo");
     build_cmd
@@ -39,6 +69,81 @@ fn main() {
             &format!("format=raw,file={}", bios_path.display()),
         ]);
 
+    if let Some(filter) = filter {
+        // NOTE: QEMU uses `,` as an option separator, it has to be escaped by doubling it.
+        cmd.args([
+            "-fw_cfg",
+            &format!("name={FILTER_FILE},string={}", filter.replace(',', ",,")),
+        ]);
+    }
+    if repeat > 1 {
+        cmd.args(["-fw_cfg", &format!("name={REPEAT_FILE},string={repeat}")]);
+    }
+
+    // Capture the serial output (still echoing it) so we can look for the test summary.
+    cmd.stdout(Stdio::piped());
     let mut child = cmd.spawn().unwrap();
-    child.wait().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let reader = thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            println!("{line}");
+
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let summary_re = Regex::new(r"(\d+)/(\d+) tests passed").unwrap();
+    let mut summary = None;
+    let mut scan = |line: String| {
+        if let Some(captures) = summary_re.captures(&line) {
+            let passed: usize = captures[1].parse().unwrap();
+            let total: usize = captures[2].parse().unwrap();
+            summary = Some((passed, total));
+        }
+    };
+
+    let deadline = Instant::now() + TIMEOUT * repeat;
+    let status = loop {
+        if let Ok(line) = rx.recv_timeout(Duration::from_millis(50)) {
+            scan(line);
+        }
+
+        if let Some(status) = child.try_wait().unwrap() {
+            break Some(status);
+        }
+
+        if Instant::now() >= deadline {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            break None;
+        }
+    };
+
+    // QEMU is gone, get the remaining output.
+    reader.join().unwrap();
+    for line in rx.try_iter() {
+        scan(line);
+    }
+
+    let Some(status) = status else {
+        eprintln!("Tests timed out after {}s.", (TIMEOUT * repeat).as_secs());
+        return ExitCode::FAILURE;
+    };
+
+    let Some((passed, total)) = summary else {
+        eprintln!("QEMU exited ({status}) without printing the tests summary.");
+        return ExitCode::FAILURE;
+    };
+
+    if status.code() == Some(QEMU_EXIT_SUCCESS) && passed == total {
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("Tests failed: {passed}/{total} passed, QEMU exited ({status}).");
+        ExitCode::FAILURE
+    }
 }